@@ -0,0 +1,107 @@
+//! Ways of reaching files the `shell` user can't read, tried by [`Device::gain_root`](crate::device::Device::gain_root).
+
+use crate::device::quote;
+use std::{
+	fmt::{self, Display, Formatter},
+	str::FromStr,
+};
+
+/// Which ways of gaining root [`Device::gain_root`](crate::device::Device::gain_root) may try.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RootStrategy {
+	/// `adb root` first, then `su`.
+	Auto,
+	/// Only `adb root`, which restarts adbd as root on userdebug and eng builds.
+	AdbRoot,
+	/// Only `su`, as installed by Magisk or SuperSU.
+	Su,
+	/// Neither, just detect whether adbd already runs as root.
+	Never,
+}
+
+impl RootStrategy {
+	pub const NAMES: &'static [&'static str] = &["auto", "adb-root", "su", "never"];
+
+	#[must_use]
+	pub fn tries_adb_root(self) -> bool {
+		matches!(self, Self::Auto | Self::AdbRoot)
+	}
+
+	#[must_use]
+	pub fn tries_su(self) -> bool {
+		matches!(self, Self::Auto | Self::Su)
+	}
+}
+
+impl FromStr for RootStrategy {
+	type Err = String;
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		match s {
+			"auto" => Ok(Self::Auto),
+			"adb-root" => Ok(Self::AdbRoot),
+			"su" => Ok(Self::Su),
+			"never" => Ok(Self::Never),
+			other => Err(format!("Unknown root strategy: {}", other)),
+		}
+	}
+}
+
+/// How the arguments of an `su` binary are spelled.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Su {
+	/// `su -c 'command'`, as Magisk's and SuperSU's `su` take it.
+	DashC,
+	/// `su 0 sh -c 'command'`, as the `su` of userdebug builds takes it.
+	Uid,
+}
+
+impl Su {
+	pub const ALL: [Self; 2] = [Self::DashC, Self::Uid];
+
+	/// Wraps a shell command line so it runs as root.
+	#[must_use]
+	pub fn wrap(self, command: &[u8]) -> Vec<u8> {
+		let mut wrapped = match self {
+			Self::DashC => b"su -c ".to_vec(),
+			Self::Uid => b"su 0 sh -c ".to_vec(),
+		};
+		wrapped.extend_from_slice(&quote(command));
+		wrapped
+	}
+}
+
+/// How a device's files are being read, as found by [`Device::gain_root`](crate::device::Device::gain_root).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Access {
+	/// adbd already ran as root, as on emulators and userdebug builds.
+	Root,
+	/// adbd already ran as root in TWRP, with this `ro.twrp.version`.
+	Twrp(String),
+	/// adbd was restarted as root with `adb root`.
+	AdbRoot,
+	/// Listing and pulling go through commands wrapped in `su`, instead of the SYNC protocol.
+	Su(Su),
+	/// Only what the `shell` user can read is reachable.
+	Shell,
+}
+
+impl Access {
+	#[must_use]
+	pub fn is_root(&self) -> bool {
+		!matches!(self, Self::Shell)
+	}
+}
+
+impl Display for Access {
+	fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+		match self {
+			Self::Root => f.write_str("root, adbd runs as root"),
+			Self::Twrp(version) => write!(f, "root, adbd runs as root in TWRP {}", version),
+			Self::AdbRoot => f.write_str("root, adbd restarted with `adb root`"),
+			Self::Su(Su::DashC) => f.write_str("root, through `su -c`"),
+			Self::Su(Su::Uid) => f.write_str("root, through `su 0`"),
+			Self::Shell => f.write_str("the shell user, protected paths will fail"),
+		}
+	}
+}
@@ -0,0 +1,893 @@
+//! The archive formats dumps can be written as.
+//!
+//! ZIP is the most widely readable, but only stores UTF-8 names and has no notion of device nodes or FIFOs.
+//! TAR (with PAX headers) keeps arbitrary byte paths and more metadata, and can be compressed as a whole.
+//!
+//! Owners and SELinux contexts go into `ADB.owner` and `SCHILY.xattr.security.selinux` PAX records.
+//! Entries without an `ADB.owner` record have an unknown owner, whatever their header says.
+//! ZIP volumes keep owners in Info-ZIP's Unix extra field (`0x7875`) and list everything with a context in [`SECURITY_FILE_NAME`].
+
+use crate::{store, AnError, RawPath, RawStr, RawString};
+use chrono::{Datelike, NaiveDateTime, Timelike};
+use std::{
+	collections::HashMap,
+	convert::TryFrom,
+	fs::{self, File, OpenOptions},
+	io::{self, Error, ErrorKind, Read, Seek, SeekFrom, Write},
+	path::{Path, PathBuf},
+	str::FromStr,
+};
+use tar::{EntryType, Header};
+use zip::{write::FileOptions, CompressionMethod, DateTime, ZipArchive, ZipWriter};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+	Zip,
+	Tar,
+	#[cfg(feature = "gzip")]
+	TarGz,
+	#[cfg(feature = "zstd")]
+	TarZst,
+	/// Volumes of a snapshot in a [`crate::store`], with file contents kept separately.
+	Tree,
+}
+
+impl Format {
+	/// The names of the archive formats accepted by [`FromStr`], which double as file extensions.
+	///
+	/// [`Tree`](Self::Tree) (`tree`) is left out, since it's only written into stores.
+	pub const NAMES: &'static [&'static str] = &[
+		"zip",
+		"tar",
+		#[cfg(feature = "gzip")]
+		"tar.gz",
+		#[cfg(feature = "zstd")]
+		"tar.zst",
+	];
+
+	#[must_use]
+	pub fn extension(self) -> &'static str {
+		match self {
+			Self::Zip => "zip",
+			Self::Tar => "tar",
+			#[cfg(feature = "gzip")]
+			Self::TarGz => "tar.gz",
+			#[cfg(feature = "zstd")]
+			Self::TarZst => "tar.zst",
+			Self::Tree => "tree",
+		}
+	}
+
+	/// Every format, including [`Tree`](Self::Tree).
+	pub const ALL: &'static [Self] = &[
+		Self::Zip,
+		Self::Tar,
+		#[cfg(feature = "gzip")]
+		Self::TarGz,
+		#[cfg(feature = "zstd")]
+		Self::TarZst,
+		Self::Tree,
+	];
+
+	/// Recognizes volumes like `backup.1.zip` by their extension.
+	#[must_use]
+	pub fn of_file_name(name: &str) -> Option<Self> {
+		split_volume_name(name).map(|(_, _, format)| format)
+	}
+
+	/// Starts writing an archive of this format into a new file at `path`.
+	pub fn create(self, path: &Path) -> Result<Box<dyn Writer>, Error> {
+		let file = OpenOptions::new()
+			.create_new(true)
+			.read(true)
+			.write(true)
+			.open(path)?;
+		Ok(match self {
+			Self::Zip => Box::new(Zip {
+				writer: ZipWriter::new(file),
+				names: Vec::new(),
+				security: Vec::new(),
+				file_types: HashMap::new(),
+			}),
+			Self::Tar => Box::new(Tar::new(file)),
+			#[cfg(feature = "gzip")]
+			Self::TarGz => Box::new(Tar::new(flate2::write::GzEncoder::new(
+				file,
+				flate2::Compression::default(),
+			))),
+			#[cfg(feature = "zstd")]
+			Self::TarZst => Box::new(Tar::new(zstd::Encoder::new(file, 0)?)),
+			Self::Tree => Box::new(store::Tree::new(path, file)?),
+		})
+	}
+}
+
+impl FromStr for Format {
+	type Err = String;
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		match s {
+			"zip" => Ok(Self::Zip),
+			"tar" => Ok(Self::Tar),
+			#[cfg(feature = "gzip")]
+			"tar.gz" => Ok(Self::TarGz),
+			#[cfg(feature = "zstd")]
+			"tar.zst" => Ok(Self::TarZst),
+			"tree" => Ok(Self::Tree),
+			other => Err(format!("Unknown archive format: {}", other)),
+		}
+	}
+}
+
+/// An archive volume found by [`volumes`].
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Volume {
+	/// The name of the volume set, like `backup`.
+	pub set: String,
+	pub number: usize,
+	pub path: PathBuf,
+}
+
+/// Lists the archive volumes in `dir`, ordered by set name and number.
+pub fn volumes(dir: &Path) -> Result<Vec<Volume>, Error> {
+	let mut volumes = Vec::new();
+	for entry in fs::read_dir(dir)? {
+		let entry = entry?;
+		let name = entry.file_name().to_string_lossy().into_owned();
+		if let Some((set, number, _)) = split_volume_name(&name) {
+			volumes.push(Volume {
+				set: set.to_string(),
+				number,
+				path: entry.path(),
+			});
+		}
+	}
+	volumes.sort();
+	Ok(volumes)
+}
+
+/// Splits a volume's file name like `com.foo.1.tar.gz` into its set, number and format.
+///
+/// Set names keep the dots of the paths they were made from, so this parses from the right.
+fn split_volume_name(name: &str) -> Option<(&str, usize, Format)> {
+	let (rest, format) = Format::ALL.iter().find_map(|format| {
+		let rest = name.strip_suffix(format.extension())?.strip_suffix('.')?;
+		Some((rest, *format))
+	})?;
+	let (set, number) = rest.rsplit_once('.')?;
+	if !number.bytes().all(|b| b.is_ascii_digit()) {
+		return None;
+	}
+	Some((set, number.parse().ok()?, format))
+}
+
+/// The kind of an entry read back by [`read`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum EntryKind {
+	Directory,
+	File,
+	Symlink,
+	Other,
+}
+
+/// Reads back the volume at `path`, calling `f` with each entry's path (relative to the archive root), kind and contents.
+///
+/// Directory paths have no trailing `/`. ZIP entry names are decoded according to the volume's [`NAMES_FILE_NAME`].
+pub fn read(
+	path: &Path,
+	mut f: impl FnMut(&RawPath, EntryKind, &mut dyn Read) -> Result<(), Error>,
+) -> Result<(), Error> {
+	read_entries(path, |metadata, kind, data| f(metadata.path, kind, data))
+}
+
+/// Like [`read`], but with each entry's [`Metadata`].
+///
+/// The contents of a symlink are its target.
+pub fn read_entries(
+	path: &Path,
+	mut f: impl FnMut(&Metadata, EntryKind, &mut dyn Read) -> Result<(), Error>,
+) -> Result<(), Error> {
+	let format = path
+		.file_name()
+		.and_then(|name| Format::of_file_name(&name.to_string_lossy()))
+		.ok_or_else(|| {
+			Error::new(
+				ErrorKind::InvalidInput,
+				AnError(format!("Not an archive volume: {}", path.display())),
+			)
+		})?;
+	let file = File::open(path)?;
+	let reader: Box<dyn Read> = match format {
+		Format::Zip => return read_zip(file, f),
+		Format::Tar => Box::new(file),
+		#[cfg(feature = "gzip")]
+		Format::TarGz => Box::new(flate2::read::GzDecoder::new(file)),
+		#[cfg(feature = "zstd")]
+		Format::TarZst => Box::new(zstd::Decoder::new(file)?),
+		Format::Tree => return store::read(path, f),
+	};
+	for entry in tar::Archive::new(reader).entries()? {
+		let mut entry = entry?;
+		let header = entry.header();
+		let kind = match header.entry_type() {
+			EntryType::Directory => EntryKind::Directory,
+			EntryType::Regular | EntryType::Continuous => EntryKind::File,
+			EntryType::Symlink => EntryKind::Symlink,
+			_ => EntryKind::Other,
+		};
+		// Other tools may leave these blank, which shouldn't keep the contents from being read.
+		let permissions = header.mode().map_or(0o644, |mode| mode & 0o7777);
+		let mut owner = None;
+		let mut mtime = header
+			.mtime()
+			.map_or(0, |mtime| i64::try_from(mtime).unwrap_or(i64::MAX));
+		let mut context = None;
+		if let Some(extensions) = entry.pax_extensions()? {
+			for extension in extensions {
+				let extension = extension?;
+				match extension.key_bytes() {
+					b"mtime" => {
+						if let Some(Ok(seconds)) = extension
+							.value()
+							.ok()
+							.map(|value| value.split('.').next().unwrap_or_default().parse())
+						{
+							mtime = seconds;
+						}
+					}
+					key if key == OWNER_PAX_KEY.as_bytes() => {
+						owner = extension.value().ok().and_then(parse_owner);
+					}
+					key if key == SELINUX_PAX_KEY.as_bytes() => {
+						let value = extension.value_bytes();
+						context = Some(value.strip_suffix(b"\0").unwrap_or(value).to_vec());
+					}
+					_ => (),
+				}
+			}
+		}
+		let path = entry.path_bytes().into_owned();
+		let metadata = Metadata {
+			uid: owner.map(|(uid, _)| uid),
+			gid: owner.map(|(_, gid)| gid),
+			context: context.as_deref().map(RawStr::new),
+			..Metadata::new(trim_slash(&path), permissions, mtime)
+		};
+		if kind == EntryKind::Symlink {
+			let target = entry.link_name_bytes().unwrap_or_default().into_owned();
+			f(&metadata, kind, &mut &target[..])?;
+		} else {
+			f(&metadata, kind, &mut entry)?;
+		}
+	}
+	Ok(())
+}
+
+fn read_zip(
+	file: File,
+	mut f: impl FnMut(&Metadata, EntryKind, &mut dyn Read) -> Result<(), Error>,
+) -> Result<(), Error> {
+	let mut archive = ZipArchive::new(file)?;
+
+	let mut names = HashMap::new();
+	if let Ok(mut manifest) = archive.by_name(NAMES_FILE_NAME) {
+		let mut text = String::new();
+		manifest.read_to_string(&mut text)?;
+		for line in text.lines() {
+			let original = hex::decode(line).map_err(|_| {
+				Error::new(
+					ErrorKind::InvalidData,
+					AnError(format!("Invalid line in {}: {:?}", NAMES_FILE_NAME, line)),
+				)
+			})?;
+			names.insert(
+				RawStr::new(&original).encode_lossless().into_owned(),
+				original,
+			);
+		}
+	}
+
+	let mut security = HashMap::new();
+	if let Ok(mut list) = archive.by_name(SECURITY_FILE_NAME) {
+		let mut text = Vec::new();
+		list.read_to_end(&mut text)?;
+		for line in text.split(|b| *b == b'\n').filter(|line| !line.is_empty()) {
+			let (path, listed) = parse_security_line(line).ok_or_else(|| {
+				Error::new(
+					ErrorKind::InvalidData,
+					AnError(format!(
+						"Invalid line in {}: {:?}",
+						SECURITY_FILE_NAME,
+						RawStr::new(line)
+					)),
+				)
+			})?;
+			security.insert(path, listed);
+		}
+	}
+
+	for i in 0..archive.len() {
+		let mut entry = archive.by_index(i)?;
+		let mode = entry.unix_mode();
+		let kind = if entry.is_dir() {
+			EntryKind::Directory
+		} else if mode.map_or(false, |mode| mode & 0o170_000 == 0o120_000) {
+			EntryKind::Symlink
+		} else {
+			EntryKind::File
+		};
+		let name = entry.name().to_string();
+		let name = name.strip_suffix('/').unwrap_or(&name);
+		let path = names.get(name).map_or(name.as_bytes(), Vec::as_slice);
+		let (owner, context) = match security.get(path) {
+			Some((owner, context)) => (*owner, Some(&**context)),
+			None => (unix_extra_field(entry.extra_data()), None),
+		};
+		let metadata = Metadata {
+			uid: owner.map(|(uid, _)| uid),
+			gid: owner.map(|(_, gid)| gid),
+			context,
+			..Metadata::new(
+				RawPath::new(path),
+				mode.map_or(0o644, |mode| mode & 0o7777),
+				convert_zip_date_time(entry.last_modified()),
+			)
+		};
+		if kind == EntryKind::Symlink {
+			let mut target = String::new();
+			entry.read_to_string(&mut target)?;
+			let target = names.get(&target).map_or(target.as_bytes(), Vec::as_slice);
+			f(&metadata, kind, &mut &target[..])?;
+		} else {
+			f(&metadata, kind, &mut entry)?;
+		}
+	}
+	Ok(())
+}
+
+/// An owner (UID and GID) and SELinux context, as listed in [`SECURITY_FILE_NAME`].
+type Security = (Option<(u32, u32)>, RawString);
+
+/// Parses a line of [`SECURITY_FILE_NAME`] into the path and its [`Security`].
+fn parse_security_line(line: &[u8]) -> Option<(Vec<u8>, Security)> {
+	let mut fields = line.splitn(4, |b| *b == b'\t');
+	let path = hex::decode(fields.next()?).ok()?;
+	let mut number = || -> Option<Option<u32>> {
+		match fields.next()? {
+			b"" => Some(None),
+			number => Some(Some(std::str::from_utf8(number).ok()?.parse().ok()?)),
+		}
+	};
+	let owner = match (number()?, number()?) {
+		(Some(uid), Some(gid)) => Some((uid, gid)),
+		_ => None,
+	};
+	Some((path, (owner, RawString(fields.next()?.to_vec()))))
+}
+
+/// The ID of Info-ZIP's "new" Unix extra field, holding the owner as variable-length numbers.
+const UNIX_EXTRA_FIELD_ID: u16 = 0x7875;
+
+/// Finds the owner in a ZIP entry's [`UNIX_EXTRA_FIELD_ID`] extra field.
+fn unix_extra_field(mut extra: &[u8]) -> Option<(u32, u32)> {
+	while extra.len() >= 4 {
+		let id = u16::from_le_bytes([extra[0], extra[1]]);
+		let len = usize::from(u16::from_le_bytes([extra[2], extra[3]]));
+		let data = extra.get(4..4 + len)?;
+		if id == UNIX_EXTRA_FIELD_ID {
+			// Version 1, then size-prefixed little-endian UID and GID.
+			let (&1, data) = data.split_first()? else {
+				return None;
+			};
+			let number = |data: &mut &[u8]| {
+				let (&size, rest) = data.split_first()?;
+				let bytes = rest
+					.get(..usize::from(size))
+					.filter(|bytes| bytes.len() <= 4)?;
+				*data = &rest[bytes.len()..];
+				Some(bytes.iter().rev().fold(0, |n, b| n << 8 | u32::from(*b)))
+			};
+			let mut data = data;
+			return Some((number(&mut data)?, number(&mut data)?));
+		}
+		extra = &extra[4 + len..];
+	}
+	None
+}
+
+fn trim_slash(path: &[u8]) -> &RawPath {
+	RawPath::new(path.strip_suffix(b"/").unwrap_or(path))
+}
+
+/// What's known about an entry besides its contents.
+#[derive(Debug, Clone)]
+pub struct Metadata<'a> {
+	/// Relative to the archive root.
+	pub path: &'a RawPath,
+	/// Permission bits, without the file type.
+	pub permissions: u32,
+	/// Seconds since the Unix epoch.
+	pub mtime: i64,
+	pub uid: Option<u32>,
+	pub gid: Option<u32>,
+	/// The `security.selinux` label.
+	pub context: Option<&'a RawStr>,
+}
+
+impl<'a> Metadata<'a> {
+	#[must_use]
+	pub fn new(path: &'a RawPath, permissions: u32, mtime: i64) -> Self {
+		Self {
+			path,
+			permissions,
+			mtime,
+			uid: None,
+			gid: None,
+			context: None,
+		}
+	}
+}
+
+/// Entries that are neither directories, regular files nor symlinks.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Special {
+	BlockDevice { major: u32, minor: u32 },
+	CharDevice { major: u32, minor: u32 },
+	Fifo,
+	Socket,
+}
+
+impl Special {
+	/// `block`, `char`, `fifo` or `socket`.
+	#[must_use]
+	pub fn kind(self) -> &'static str {
+		match self {
+			Self::BlockDevice { .. } => "block",
+			Self::CharDevice { .. } => "char",
+			Self::Fifo => "fifo",
+			Self::Socket => "socket",
+		}
+	}
+
+	/// The major and minor number of a device.
+	#[must_use]
+	pub fn numbers(self) -> Option<(u32, u32)> {
+		match self {
+			Self::BlockDevice { major, minor } | Self::CharDevice { major, minor } => {
+				Some((major, minor))
+			}
+			Self::Fifo | Self::Socket => None,
+		}
+	}
+}
+
+/// An archive being written.
+pub trait Writer {
+	fn add_directory(&mut self, metadata: &Metadata) -> Result<(), Error>;
+
+	/// Starts a regular file of `size` bytes. Exactly that many must be written to the returned writer.
+	fn start_file(&mut self, metadata: &Metadata, size: u64) -> Result<&mut dyn Write, Error>;
+
+	fn add_file(&mut self, metadata: &Metadata, data: &[u8]) -> Result<(), Error> {
+		self.start_file(metadata, data.len() as u64)?
+			.write_all(data)
+	}
+
+	fn add_symlink(&mut self, metadata: &Metadata, target: &RawPath) -> Result<(), Error>;
+
+	/// Adds a [`Special`] entry, returning `false` if the format can't represent it.
+	fn add_special(&mut self, metadata: &Metadata, special: Special) -> Result<bool, Error>;
+
+	/// Completes the archive and makes sure it's on disk.
+	fn finish(self: Box<Self>) -> Result<(), Error>;
+}
+
+/// Lists the names in a ZIP volume that had to be encoded with [`RawStr::encode_lossless`],
+/// one hex-encoded original name per line.
+///
+/// Symlink targets are listed too.
+pub const NAMES_FILE_NAME: &str = "adb-dump.names.txt";
+
+/// Lists the owner and SELinux context of each entry in a ZIP volume that has a context,
+/// one tab-separated hex-encoded path, UID, GID (both empty if unknown) and context per line.
+pub const SECURITY_FILE_NAME: &str = "adb-dump.security.txt";
+
+/// The PAX record GNU tar and others keep SELinux contexts in.
+const SELINUX_PAX_KEY: &str = "SCHILY.xattr.security.selinux";
+/// Holds `uid:gid` when the owner is known, since header fields can't tell an unknown owner from root.
+const OWNER_PAX_KEY: &str = "ADB.owner";
+
+fn parse_owner(value: &str) -> Option<(u32, u32)> {
+	let (uid, gid) = value.split_once(':')?;
+	Some((uid.parse().ok()?, gid.parse().ok()?))
+}
+
+struct Zip {
+	writer: ZipWriter<File>,
+	/// Contents of the [`NAMES_FILE_NAME`] manifest.
+	names: Vec<u8>,
+	/// Contents of the [`SECURITY_FILE_NAME`] list.
+	security: Vec<u8>,
+	/// Directories and symlinks written as regular files to carry an owner, with the file type to give them back.
+	file_types: HashMap<String, u32>,
+}
+
+impl Zip {
+	fn options(metadata: &Metadata) -> FileOptions {
+		FileOptions::default()
+			.compression_method(CompressionMethod::Stored)
+			.last_modified_time(convert_date_time(metadata.mtime))
+			.unix_permissions(metadata.permissions)
+	}
+
+	/// ZIP names must be UTF-8, so others (and those with a `%`) are encoded and listed in the manifest.
+	fn name(&mut self, raw: &RawStr) -> String {
+		let encoded = raw.encode_lossless();
+		if encoded.as_bytes() != &**raw {
+			self.names.extend(hex::encode(&**raw).bytes());
+			self.names.push(b'\n');
+		}
+		encoded.into_owned()
+	}
+
+	/// Lists `metadata` in [`SECURITY_FILE_NAME`] if it has a context.
+	fn security(&mut self, metadata: &Metadata) {
+		if let Some(context) = metadata.context {
+			let number = |n: Option<u32>| n.map(|n| n.to_string()).unwrap_or_default();
+			self.security.extend(
+				format!(
+					"{}\t{}\t{}\t",
+					hex::encode(&***metadata.path),
+					number(metadata.uid),
+					number(metadata.gid)
+				)
+				.bytes(),
+			);
+			self.security.extend_from_slice(context);
+			self.security.push(b'\n');
+		}
+	}
+
+	/// Starts an entry with the owner of `metadata` in a [`UNIX_EXTRA_FIELD_ID`] extra field, if it's known.
+	///
+	/// `zip` only takes extra fields for regular files, so other types are recorded to be fixed up by
+	/// [`set_file_types`] once the archive is finished.
+	fn start_entry(
+		&mut self,
+		name: String,
+		metadata: &Metadata,
+		file_type: u32,
+		large_file: bool,
+	) -> Result<(), Error> {
+		let options = Self::options(metadata).large_file(large_file);
+		let (Some(uid), Some(gid)) = (metadata.uid, metadata.gid) else {
+			if file_type == 0o040_000 {
+				self.writer.add_directory(name, options)?;
+			} else {
+				self.writer.start_file(name, options)?;
+			}
+			return Ok(());
+		};
+		if file_type != 0o100_000 {
+			self.file_types.insert(name.clone(), file_type);
+		}
+		self.writer.start_file_with_extra_data(name, options)?;
+		self.writer.write_all(&UNIX_EXTRA_FIELD_ID.to_le_bytes())?;
+		self.writer.write_all(&11_u16.to_le_bytes())?;
+		self.writer.write_all(&[1, 4])?;
+		self.writer.write_all(&uid.to_le_bytes())?;
+		self.writer.write_all(&[4])?;
+		self.writer.write_all(&gid.to_le_bytes())?;
+		self.writer.end_extra_data()?;
+		Ok(())
+	}
+
+	fn add_list(&mut self, name: &str, contents: &[u8]) -> Result<(), Error> {
+		let mtime = chrono::Utc::now().timestamp();
+		self.writer.start_file(
+			name,
+			Self::options(&Metadata::new(RawPath::new(name), 0o644, mtime)),
+		)?;
+		self.writer.write_all(contents)
+	}
+}
+
+impl Writer for Zip {
+	fn add_directory(&mut self, metadata: &Metadata) -> Result<(), Error> {
+		let name = self.name(metadata.path) + "/";
+		self.security(metadata);
+		self.start_entry(name, metadata, 0o040_000, false)
+	}
+
+	fn start_file(&mut self, metadata: &Metadata, size: u64) -> Result<&mut dyn Write, Error> {
+		let name = self.name(metadata.path);
+		self.security(metadata);
+		self.start_entry(name, metadata, 0o100_000, size >= u64::from(u32::MAX))?;
+		Ok(&mut self.writer)
+	}
+
+	fn add_symlink(&mut self, metadata: &Metadata, target: &RawPath) -> Result<(), Error> {
+		let name = self.name(metadata.path);
+		let target = self.name(target);
+		self.security(metadata);
+		if metadata.uid.zip(metadata.gid).is_none() {
+			self.writer
+				.add_symlink(name, target, Self::options(metadata))?;
+			return Ok(());
+		}
+		self.start_entry(name, metadata, 0o120_000, false)?;
+		self.writer.write_all(target.as_bytes())
+	}
+
+	fn add_special(&mut self, _: &Metadata, _: Special) -> Result<bool, Error> {
+		Ok(false)
+	}
+
+	fn finish(mut self: Box<Self>) -> Result<(), Error> {
+		if !self.names.is_empty() {
+			let names = std::mem::take(&mut self.names);
+			self.add_list(NAMES_FILE_NAME, &names)?;
+		}
+		if !self.security.is_empty() {
+			let security = std::mem::take(&mut self.security);
+			self.add_list(SECURITY_FILE_NAME, &security)?;
+		}
+		let mut file = self.writer.finish()?;
+		set_file_types(&mut file, &self.file_types)?;
+		file.sync_all()
+	}
+}
+
+/// Overwrites the file type in the external attributes of the named entries of a finished ZIP archive.
+fn set_file_types(file: &mut File, file_types: &HashMap<String, u32>) -> Result<(), Error> {
+	if file_types.is_empty() {
+		return Ok(());
+	}
+	let mut archive = ZipArchive::new(&*file)?;
+	let mut patches = Vec::new();
+	for i in 0..archive.len() {
+		let entry = archive.by_index_raw(i)?;
+		if let (Some(file_type), Some(mode)) = (file_types.get(entry.name()), entry.unix_mode()) {
+			// The external attributes are at offset 38 of a central directory header, with the mode in the upper half.
+			patches.push((
+				entry.central_header_start() + 38,
+				(file_type | mode & 0o7777) << 16,
+			));
+		}
+	}
+	drop(archive);
+	for (offset, attributes) in patches {
+		file.seek(SeekFrom::Start(offset))?;
+		file.write_all(&attributes.to_le_bytes())?;
+	}
+	Ok(())
+}
+
+fn convert_date_time(mtime: i64) -> DateTime {
+	let converted = chrono::DateTime::from_timestamp(mtime, 0)
+		.as_ref()
+		.map(chrono::DateTime::naive_utc)
+		.ok_or(())
+		.and_then(|date_time: NaiveDateTime| {
+			let time = date_time.time();
+			DateTime::from_date_and_time(
+				u16::try_from(date_time.year()).map_err(|_| ())?,
+				u8::try_from(date_time.month()).unwrap(),
+				u8::try_from(date_time.day()).unwrap(),
+				u8::try_from(time.hour()).unwrap(),
+				u8::try_from(time.minute()).unwrap(),
+				u8::try_from(time.second()).unwrap(),
+			)
+		});
+	converted.unwrap_or_else(|()| {
+		let default = DateTime::default();
+		eprintln! {"Ignoring date: {}, using {:?} instead", mtime, default};
+		default
+	})
+}
+
+/// Reverses [`convert_date_time`], treating the ZIP date as UTC.
+fn convert_zip_date_time(date_time: DateTime) -> i64 {
+	chrono::NaiveDate::from_ymd_opt(
+		i32::from(date_time.year()),
+		u32::from(date_time.month()),
+		u32::from(date_time.day()),
+	)
+	.and_then(|date| {
+		date.and_hms_opt(
+			u32::from(date_time.hour()),
+			u32::from(date_time.minute()),
+			u32::from(date_time.second()),
+		)
+	})
+	.map_or(0, |date_time| date_time.and_utc().timestamp())
+}
+
+/// The end of a writer stack, like a compressor around a [`File`].
+trait Finish: Write {
+	fn finish(self) -> Result<(), Error>;
+}
+
+impl Finish for File {
+	fn finish(self) -> Result<(), Error> {
+		self.sync_all()
+	}
+}
+
+#[cfg(feature = "gzip")]
+impl Finish for flate2::write::GzEncoder<File> {
+	fn finish(self) -> Result<(), Error> {
+		flate2::write::GzEncoder::finish(self)?.sync_all()
+	}
+}
+
+#[cfg(feature = "zstd")]
+impl Finish for zstd::Encoder<'static, File> {
+	fn finish(self) -> Result<(), Error> {
+		zstd::Encoder::finish(self)?.sync_all()
+	}
+}
+
+/// A POSIX (PAX) TAR archive.
+///
+/// Headers are written directly so that file contents can be streamed without knowing where the archive goes.
+struct Tar<W: Finish> {
+	builder: tar::Builder<W>,
+	/// Size and bytes written so far of the current file.
+	entry: Option<(u64, u64)>,
+}
+
+/// The largest value a ustar size field can hold.
+const MAX_USTAR_SIZE: u64 = 0o777_7777_7777;
+
+impl<W: Finish> Tar<W> {
+	fn new(inner: W) -> Self {
+		Self {
+			builder: tar::Builder::new(inner),
+			entry: None,
+		}
+	}
+
+	/// Pads the current file's contents to a full block.
+	fn end_entry(&mut self) -> Result<(), Error> {
+		if let Some((size, written)) = self.entry.take() {
+			if written != size {
+				return Err(Error::new(
+					ErrorKind::InvalidData,
+					AnError(format!(
+						"Wrote {} bytes for a TAR entry of {} bytes",
+						written, size
+					)),
+				));
+			}
+			let padding = (512 - size % 512) % 512;
+			#[allow(clippy::cast_possible_truncation)]
+			self.builder
+				.get_mut()
+				.write_all(&[0; 512][..padding as usize])?;
+		}
+		Ok(())
+	}
+
+	fn append_header(
+		&mut self,
+		metadata: &Metadata,
+		entry_type: EntryType,
+		size: u64,
+		link: Option<&RawPath>,
+		device: Option<(u32, u32)>,
+	) -> Result<(), Error> {
+		self.end_entry()?;
+
+		let mut header = Header::new_ustar();
+		let mut pax: Vec<(&str, Vec<u8>)> = Vec::new();
+		let old = header.as_old_mut();
+		if !set_name_field(&mut old.name, metadata.path) {
+			pax.push(("path", metadata.path.to_vec()));
+		}
+		if let Some(link) = link {
+			if !set_name_field(&mut old.linkname, link) {
+				pax.push(("linkpath", link.to_vec()));
+			}
+		}
+		if std::str::from_utf8(metadata.path).is_err()
+			|| link.map_or(false, |link| std::str::from_utf8(link).is_err())
+		{
+			pax.push(("hdrcharset", b"BINARY".to_vec()));
+		}
+
+		header.set_entry_type(entry_type);
+		header.set_mode(metadata.permissions);
+		header.set_uid(u64::from(metadata.uid.unwrap_or(0)));
+		header.set_gid(u64::from(metadata.gid.unwrap_or(0)));
+		if let (Some(uid), Some(gid)) = (metadata.uid, metadata.gid) {
+			pax.push((OWNER_PAX_KEY, format!("{}:{}", uid, gid).into_bytes()));
+		}
+		if let Some(context) = metadata.context {
+			pax.push((SELINUX_PAX_KEY, context.to_vec()));
+		}
+		match u64::try_from(metadata.mtime) {
+			Ok(mtime) => header.set_mtime(mtime),
+			Err(_) => pax.push(("mtime", metadata.mtime.to_string().into_bytes())),
+		}
+		if size > MAX_USTAR_SIZE {
+			pax.push(("size", size.to_string().into_bytes()));
+		}
+		header.set_size(size);
+		if let Some((major, minor)) = device {
+			header.set_device_major(major)?;
+			header.set_device_minor(minor)?;
+		}
+		header.set_cksum();
+
+		if !pax.is_empty() {
+			self.builder
+				.append_pax_extensions(pax.iter().map(|(key, value)| (*key, &value[..])))?;
+		}
+		self.builder.get_mut().write_all(header.as_bytes())
+	}
+}
+
+/// Copies `value` into a NUL-padded header field, returning `false` if it had to be truncated.
+fn set_name_field(field: &mut [u8], value: &[u8]) -> bool {
+	let len = value.len().min(field.len());
+	field[..len].copy_from_slice(&value[..len]);
+	value.len() <= field.len()
+}
+
+impl<W: Finish> Write for Tar<W> {
+	fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+		let written = self.builder.get_mut().write(buf)?;
+		if let Some((_, total)) = &mut self.entry {
+			*total += written as u64;
+		}
+		Ok(written)
+	}
+
+	fn flush(&mut self) -> io::Result<()> {
+		self.builder.get_mut().flush()
+	}
+}
+
+impl<W: Finish> Writer for Tar<W> {
+	fn add_directory(&mut self, metadata: &Metadata) -> Result<(), Error> {
+		let mut path = metadata.path.to_owned();
+		path += RawStr::new("/");
+		self.append_header(
+			&Metadata {
+				path: &path,
+				..metadata.clone()
+			},
+			EntryType::Directory,
+			0,
+			None,
+			None,
+		)
+	}
+
+	fn start_file(&mut self, metadata: &Metadata, size: u64) -> Result<&mut dyn Write, Error> {
+		self.append_header(metadata, EntryType::Regular, size, None, None)?;
+		self.entry = Some((size, 0));
+		Ok(self)
+	}
+
+	fn add_symlink(&mut self, metadata: &Metadata, target: &RawPath) -> Result<(), Error> {
+		self.append_header(metadata, EntryType::Symlink, 0, Some(target), None)
+	}
+
+	fn add_special(&mut self, metadata: &Metadata, special: Special) -> Result<bool, Error> {
+		let (entry_type, device) = match special {
+			Special::BlockDevice { major, minor } => (EntryType::Block, Some((major, minor))),
+			Special::CharDevice { major, minor } => (EntryType::Char, Some((major, minor))),
+			Special::Fifo => (EntryType::Fifo, None),
+			Special::Socket => return Ok(false),
+		};
+		self.append_header(metadata, entry_type, 0, None, device)?;
+		Ok(true)
+	}
+
+	fn finish(mut self: Box<Self>) -> Result<(), Error> {
+		self.end_entry()?;
+		self.builder.into_inner()?.finish()
+	}
+}
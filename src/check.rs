@@ -0,0 +1,248 @@
+//! Validates archive volumes against the checksum manifests the dump embedded in them, without the device.
+//! Partition images are validated against the [`PARTITIONS_FILE_NAME`](image::PARTITIONS_FILE_NAME) next to them.
+//!
+//! See [`MANIFEST_FILE_NAME`] for the manifest format.
+
+use crate::{
+	archive::{self, EntryKind},
+	dump::{self, MANIFEST_FILE_NAME},
+	image, manifest, RawPathBuf,
+};
+use sha2::{Digest, Sha256};
+use std::{
+	collections::BTreeMap,
+	fmt::{self, Display, Formatter},
+	fs::File,
+	io::{self, Error, ErrorKind, Write},
+	path::PathBuf,
+};
+
+#[derive(Debug)]
+pub struct Options {
+	/// Where the archive volumes and partition images are.
+	pub output_dir: PathBuf,
+	/// `-1` only prints errors, `1` and above list every volume.
+	pub verbosity: i8,
+}
+
+#[derive(Debug)]
+pub enum Problem {
+	/// Listed in the manifest, but not in the volume, or an image listed in [`PARTITIONS_FILE_NAME`](image::PARTITIONS_FILE_NAME) that's gone.
+	Missing,
+	/// A regular file in the volume that the manifest doesn't list.
+	Unlisted,
+	Size {
+		listed: u64,
+		archived: u64,
+	},
+	Content,
+	/// The manifest places the file in another volume.
+	Volume {
+		listed: usize,
+	},
+	/// The volume contains files, but no manifest.
+	NoManifest,
+	/// The volume or its manifest couldn't be read completely.
+	Unreadable(Error),
+}
+
+#[derive(Debug)]
+pub struct Finding {
+	/// The volume or image.
+	pub volume: PathBuf,
+	/// Relative to the archive root. [`None`] for problems with the volume as a whole.
+	pub path: Option<RawPathBuf>,
+	pub problem: Problem,
+}
+
+impl Display for Finding {
+	/// One tab-separated line of `kind`, volume file name, quoted path (if any) and details.
+	fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+		let kind = match &self.problem {
+			Problem::Missing => "missing",
+			Problem::Unlisted => "unlisted",
+			Problem::Size { .. } => "size",
+			Problem::Content => "content",
+			Problem::Volume { .. } => "volume",
+			Problem::NoManifest => "no-manifest",
+			Problem::Unreadable(_) => "unreadable",
+		};
+		write!(
+			f,
+			"{}\t{}",
+			kind,
+			self.volume
+				.file_name()
+				.unwrap_or_default()
+				.to_string_lossy()
+		)?;
+		if let Some(path) = &self.path {
+			write!(f, "\t{:?}", path.encode_lossless())?;
+		}
+		match &self.problem {
+			Problem::Size { listed, archived } => write!(f, "\t{}\t{}", listed, archived),
+			Problem::Volume { listed } => write!(f, "\t{}", listed),
+			Problem::Unreadable(error) => write!(f, "\t{}", error),
+			_ => Ok(()),
+		}
+	}
+}
+
+#[derive(Debug)]
+pub struct Report {
+	pub volumes: usize,
+	/// The number of files whose size and checksum were compared.
+	pub checked: usize,
+	/// The number of images listed in [`PARTITIONS_FILE_NAME`](image::PARTITIONS_FILE_NAME).
+	pub images: usize,
+	pub findings: Vec<Finding>,
+}
+
+impl Report {
+	#[must_use]
+	pub fn is_ok(&self) -> bool {
+		self.findings.is_empty()
+	}
+}
+
+/// Checks every archive volume in `options.output_dir` against its own manifest, and every image against [`PARTITIONS_FILE_NAME`](image::PARTITIONS_FILE_NAME).
+///
+/// Damaged volumes and images end up in the [`Report`], only failing to list the directory or to read
+/// [`PARTITIONS_FILE_NAME`](image::PARTITIONS_FILE_NAME) is an error.
+pub fn run(options: &Options) -> Result<Report, Error> {
+	let mut report = Report {
+		volumes: 0,
+		checked: 0,
+		images: 0,
+		findings: Vec::new(),
+	};
+	for volume in archive::volumes(&options.output_dir)? {
+		if options.verbosity >= 1 {
+			println!("volume {}", volume.path.display());
+		}
+		report.volumes += 1;
+		let finding = |path, problem| Finding {
+			volume: volume.path.clone(),
+			path,
+			problem,
+		};
+
+		let mut files = BTreeMap::new();
+		let mut manifest = None;
+		let result = archive::read(&volume.path, |path, kind, data| {
+			if **path == MANIFEST_FILE_NAME {
+				let mut text = Vec::new();
+				data.read_to_end(&mut text)?;
+				manifest = Some(text);
+			} else if kind == EntryKind::File && !dump::is_manifest(path) {
+				let mut hasher = Sha256Writer::default();
+				let size = io::copy(data, &mut hasher)?;
+				files.insert(path.to_owned(), (size, hasher.0.finalize().to_vec()));
+			}
+			Ok(())
+		})
+		.and_then(|()| manifest.as_deref().map(manifest::parse).transpose());
+		let manifest = match result {
+			Ok(manifest) => manifest,
+			Err(error) => {
+				report
+					.findings
+					.push(finding(None, Problem::Unreadable(error)));
+				continue;
+			}
+		};
+		let Some(manifest) = manifest else {
+			if files.keys().any(|path| !dump::is_marker(path)) {
+				report.findings.push(finding(None, Problem::NoManifest));
+			}
+			continue;
+		};
+
+		for listed in manifest {
+			let path = listed.path;
+			let Some((size, sha256)) = files.remove(&path) else {
+				report.findings.push(finding(Some(path), Problem::Missing));
+				continue;
+			};
+			report.checked += 1;
+			let problem = if size != listed.size {
+				Problem::Size {
+					listed: listed.size,
+					archived: size,
+				}
+			} else if sha256 != listed.sha256 {
+				Problem::Content
+			} else if listed.volume != volume.number {
+				Problem::Volume {
+					listed: listed.volume,
+				}
+			} else {
+				continue;
+			};
+			report.findings.push(finding(Some(path), problem));
+		}
+		for path in files.into_keys() {
+			if !dump::is_marker(&path) {
+				report.findings.push(finding(Some(path), Problem::Unlisted));
+			}
+		}
+	}
+
+	check_images(options, &mut report)?;
+	Ok(report)
+}
+
+/// Checks every image listed in the [`PARTITIONS_FILE_NAME`](image::PARTITIONS_FILE_NAME) in `options.output_dir`.
+fn check_images(options: &Options, report: &mut Report) -> Result<(), Error> {
+	for image in image::listed_images(&options.output_dir)? {
+		let path = options.output_dir.join(&image.file_name);
+		if options.verbosity >= 1 {
+			println!("image {}", path.display());
+		}
+		report.images += 1;
+		let finding = |problem| Finding {
+			volume: path.clone(),
+			path: None,
+			problem,
+		};
+
+		let mut hasher = Sha256Writer::default();
+		let size = match File::open(&path).and_then(|mut file| io::copy(&mut file, &mut hasher)) {
+			Ok(size) => size,
+			Err(error) if error.kind() == ErrorKind::NotFound => {
+				report.findings.push(finding(Problem::Missing));
+				continue;
+			}
+			Err(error) => {
+				report.findings.push(finding(Problem::Unreadable(error)));
+				continue;
+			}
+		};
+		let problem = if size != image.size {
+			Problem::Size {
+				listed: image.size,
+				archived: size,
+			}
+		} else if hasher.0.finalize().to_vec() != image.sha256 {
+			Problem::Content
+		} else {
+			continue;
+		};
+		report.findings.push(finding(problem));
+	}
+	Ok(())
+}
+
+#[derive(Default)]
+struct Sha256Writer(Sha256);
+
+impl Write for Sha256Writer {
+	fn write(&mut self, buf: &[u8]) -> Result<usize, Error> {
+		self.0.update(buf);
+		Ok(buf.len())
+	}
+
+	fn flush(&mut self) -> Result<(), Error> {
+		Ok(())
+	}
+}
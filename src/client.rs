@@ -0,0 +1,202 @@
+//! A minimal client for the ADB server's smart-socket protocol.
+//!
+//! Requests are sent as four lowercase hex digits of length followed by the service name.
+//! The server answers with `OKAY`, or with `FAIL` followed by a length-prefixed message.
+
+use crate::{
+	device::{Device, DeviceInfo},
+	error::AdbError,
+	AnError, RawStr, RawString, SerialNumber,
+};
+use std::{
+	env,
+	io::{Error, ErrorKind, Read, Write},
+	net::{Ipv4Addr, SocketAddr, SocketAddrV4, TcpStream},
+	process::Command,
+};
+
+pub const DEFAULT_PORT: u16 = 5037;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AdbClient {
+	addr: SocketAddr,
+	start_server: bool,
+}
+
+impl Default for AdbClient {
+	/// Targets the local ADB server, honouring `ANDROID_ADB_SERVER_PORT` like `adb` itself does.
+	///
+	/// If nothing is listening yet, the server is started once via `adb start-server`.
+	fn default() -> Self {
+		let port = env::var("ANDROID_ADB_SERVER_PORT")
+			.ok()
+			.and_then(|port| port.parse().ok())
+			.unwrap_or(DEFAULT_PORT);
+		Self {
+			addr: SocketAddrV4::new(Ipv4Addr::LOCALHOST, port).into(),
+			start_server: true,
+		}
+	}
+}
+
+impl AdbClient {
+	/// Targets the ADB server at `addr`, without ever trying to start one.
+	#[must_use]
+	pub fn new(addr: impl Into<SocketAddr>) -> Self {
+		Self {
+			addr: addr.into(),
+			start_server: false,
+		}
+	}
+
+	#[must_use]
+	pub fn addr(&self) -> SocketAddr {
+		self.addr
+	}
+
+	pub fn connect(&self) -> Result<Connection, Error> {
+		match TcpStream::connect(self.addr) {
+			Ok(stream) => Ok(Connection(stream)),
+			Err(error) if self.start_server && error.kind() == ErrorKind::ConnectionRefused => {
+				let status = Command::new("adb").arg("start-server").status()?;
+				if !status.success() {
+					return Err(Error::new(
+						ErrorKind::ConnectionRefused,
+						AnError(format!("`adb start-server` failed: {}", status)),
+					));
+				}
+				Ok(Connection(TcpStream::connect(self.addr)?))
+			}
+			Err(error) => Err(error),
+		}
+	}
+
+	/// Sends a `host:` request and returns its length-prefixed reply.
+	pub fn host_query(&self, service: &(impl AsRef<[u8]> + ?Sized)) -> Result<RawString, Error> {
+		let mut connection = self.connect()?;
+		connection.request(service)?;
+		connection.read_length_prefixed()
+	}
+
+	/// Opens a connection that is switched over to the device with the given serial number.
+	pub fn transport(&self, serial_number: &SerialNumber) -> Result<Connection, Error> {
+		let mut connection = self.connect()?;
+		let mut service = b"host:transport:".to_vec();
+		service.extend_from_slice(serial_number);
+		connection.request(&service)?;
+		Ok(connection)
+	}
+
+	/// Opens a device service like `shell:…`, `exec:…` or `sync:`.
+	pub fn open(
+		&self,
+		serial_number: &SerialNumber,
+		service: &(impl AsRef<[u8]> + ?Sized),
+	) -> Result<Connection, Error> {
+		let mut connection = self.transport(serial_number)?;
+		connection.request(service)?;
+		Ok(connection)
+	}
+
+	/// Lists attached devices, like `adb devices -l`.
+	pub fn devices(&self) -> Result<Vec<DeviceInfo>, Error> {
+		let listing = self.host_query("host:devices-l")?;
+		listing
+			.lines()
+			.filter(|line| !line.iter().all(u8::is_ascii_whitespace))
+			.map(|line| DeviceInfo::parse(*self, line))
+			.collect()
+	}
+
+	/// Creates a handle for the device with the given serial number, without checking that it's attached.
+	#[must_use]
+	pub fn device(&self, serial_number: SerialNumber) -> Device {
+		Device::new(*self, serial_number)
+	}
+
+	pub fn get_serialno(&self) -> Result<SerialNumber, Error> {
+		let serial_number = self.host_query("host:get-serialno")?;
+		if serial_number.is_empty() {
+			return Err(AdbError::Parse {
+				what: "serial number",
+				raw: serial_number,
+			}
+			.into());
+		}
+		Ok(SerialNumber(serial_number))
+	}
+}
+
+/// A socket connected to the ADB server.
+///
+/// After a successful [`request`](`Connection::request`) for a device service, reads and writes go straight to that service.
+#[derive(Debug)]
+pub struct Connection(TcpStream);
+impl Connection {
+	/// Sends a smart-socket request and waits for `OKAY`.
+	pub fn request(&mut self, service: &(impl AsRef<[u8]> + ?Sized)) -> Result<(), Error> {
+		let service = service.as_ref();
+		if service.len() > 0xffff {
+			return Err(Error::new(
+				ErrorKind::InvalidInput,
+				AnError(format!("ADB request too long: {:?}", RawStr::new(service))),
+			));
+		}
+		let mut request = format!("{:04x}", service.len()).into_bytes();
+		request.extend_from_slice(service);
+		self.0.write_all(&request)?;
+		self.read_status()
+	}
+
+	fn read_status(&mut self) -> Result<(), Error> {
+		let mut status = [0; 4];
+		self.0.read_exact(&mut status)?;
+		match &status {
+			b"OKAY" => Ok(()),
+			b"FAIL" => {
+				let message = self.read_length_prefixed()?;
+				Err(AdbError::from_server_message(&String::from_utf8_lossy(&message)).into())
+			}
+			_ => Err(AdbError::Protocol(format!(
+				"Unexpected ADB status: {:?}",
+				RawStr::new(&status)
+			))
+			.into()),
+		}
+	}
+
+	pub fn read_length_prefixed(&mut self) -> Result<RawString, Error> {
+		let mut len = [0; 4];
+		self.0.read_exact(&mut len)?;
+		let len = std::str::from_utf8(&len)
+			.ok()
+			.and_then(|len| usize::from_str_radix(len, 16).ok())
+			.ok_or_else(|| {
+				AdbError::Protocol(format!("Invalid ADB length: {:?}", RawStr::new(&len)))
+			})?;
+		let mut data = vec![0; len];
+		self.0.read_exact(&mut data)?;
+		Ok(RawString(data))
+	}
+
+	#[must_use]
+	pub fn into_inner(self) -> TcpStream {
+		self.0
+	}
+}
+
+impl Read for Connection {
+	fn read(&mut self, buf: &mut [u8]) -> Result<usize, Error> {
+		self.0.read(buf)
+	}
+}
+
+impl Write for Connection {
+	fn write(&mut self, buf: &[u8]) -> Result<usize, Error> {
+		self.0.write(buf)
+	}
+
+	fn flush(&mut self) -> Result<(), Error> {
+		self.0.flush()
+	}
+}
@@ -0,0 +1,721 @@
+//! Attached devices and operations on them.
+
+use crate::{
+	access::{Access, RootStrategy, Su},
+	client::{AdbClient, Connection},
+	error::AdbError,
+	sync::{Recv, SyncConnection},
+	Epoch, LsEntry, RawPath, RawPathBuf, RawStr, RawString, SerialNumber, UnixMode,
+};
+use std::{
+	collections::BTreeMap,
+	convert::TryFrom,
+	fmt::{self, Display, Formatter},
+	io::{self, Error, Read, Write},
+	net::Shutdown,
+	sync::{Arc, Mutex},
+	thread,
+	time::Duration,
+};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum DeviceState {
+	Device,
+	Recovery,
+	Sideload,
+	Unauthorized,
+	Offline,
+	Bootloader,
+	/// Anything else `adb` reports, like `no permissions (…)`.
+	Other(String),
+}
+
+impl DeviceState {
+	/// Whether the device can be dumped in this state.
+	#[must_use]
+	pub fn is_usable(&self) -> bool {
+		matches!(self, Self::Device | Self::Recovery)
+	}
+}
+
+impl From<&str> for DeviceState {
+	fn from(state: &str) -> Self {
+		match state {
+			"device" => Self::Device,
+			"recovery" => Self::Recovery,
+			"sideload" => Self::Sideload,
+			"unauthorized" => Self::Unauthorized,
+			"offline" => Self::Offline,
+			"bootloader" => Self::Bootloader,
+			other => Self::Other(other.to_string()),
+		}
+	}
+}
+
+impl Display for DeviceState {
+	fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+		f.write_str(match self {
+			Self::Device => "device",
+			Self::Recovery => "recovery",
+			Self::Sideload => "sideload",
+			Self::Unauthorized => "unauthorized",
+			Self::Offline => "offline",
+			Self::Bootloader => "bootloader",
+			Self::Other(other) => other,
+		})
+	}
+}
+
+/// One line of `adb devices -l`.
+#[derive(Debug, Clone)]
+pub struct DeviceInfo {
+	client: AdbClient,
+	pub serial_number: SerialNumber,
+	pub state: DeviceState,
+	pub product: Option<String>,
+	pub model: Option<String>,
+	pub device: Option<String>,
+	pub transport_id: Option<u64>,
+}
+
+impl DeviceInfo {
+	/// Parses one line of `adb devices -l`, without its line ending.
+	pub fn parse(client: AdbClient, line: &RawStr) -> Result<Self, Error> {
+		let invalid = || AdbError::Parse {
+			what: "device line",
+			raw: line.to_owned(),
+		};
+
+		let serial_end = line
+			.iter()
+			.position(u8::is_ascii_whitespace)
+			.ok_or_else(invalid)?;
+		let rest = String::from_utf8_lossy(&line[serial_end..]);
+
+		let mut info = Self {
+			client,
+			serial_number: SerialNumber(line[..serial_end].to_owned()),
+			state: DeviceState::Other(String::new()),
+			product: None,
+			model: None,
+			device: None,
+			transport_id: None,
+		};
+		let mut state = Vec::new();
+		for token in rest.split_whitespace() {
+			let mut key_value = token.splitn(2, ':');
+			match (key_value.next(), key_value.next()) {
+				(Some("product"), Some(value)) => info.product = Some(value.to_string()),
+				(Some("model"), Some(value)) => info.model = Some(value.to_string()),
+				(Some("device"), Some(value)) => info.device = Some(value.to_string()),
+				(Some("transport_id"), Some(value)) => {
+					info.transport_id = Some(value.parse().map_err(|_| invalid())?);
+				}
+				(Some(_), Some(_)) if !state.is_empty() => (),
+				_ => state.push(token),
+			}
+		}
+		if state.is_empty() {
+			return Err(invalid().into());
+		}
+		info.state = state.join(" ").as_str().into();
+		Ok(info)
+	}
+
+	/// Creates a handle for this device.
+	#[must_use]
+	pub fn handle(&self) -> Device {
+		Device::new(self.client, self.serial_number.clone())
+	}
+}
+
+/// A handle for talking to one device through the ADB server.
+#[derive(Debug, Clone)]
+pub struct Device {
+	client: AdbClient,
+	serial_number: SerialNumber,
+	/// Cached by [`features`](`Device::features`).
+	features: Arc<Mutex<Option<Vec<String>>>>,
+	/// Set by [`gain_root`](`Device::gain_root`) if commands need wrapping to run as root.
+	su: Arc<Mutex<Option<Su>>>,
+}
+
+impl Device {
+	#[must_use]
+	pub fn new(client: AdbClient, serial_number: SerialNumber) -> Self {
+		Self {
+			client,
+			serial_number,
+			features: Arc::default(),
+			su: Arc::default(),
+		}
+	}
+
+	/// The features both the device and the ADB server support, like `ls_v2` or `stat_v2`.
+	///
+	/// Queried once per handle (and its clones).
+	pub fn features(&self) -> Result<Vec<String>, Error> {
+		let mut features = self.features.lock().unwrap();
+		if features.is_none() {
+			let mut service = b"host-serial:".to_vec();
+			service.extend_from_slice(&self.serial_number);
+			service.extend_from_slice(b":features");
+			let reply = self.client.host_query(&service)?;
+			*features = Some(
+				String::from_utf8_lossy(&reply)
+					.split(',')
+					.map(str::trim)
+					.filter(|feature| !feature.is_empty())
+					.map(ToString::to_string)
+					.collect(),
+			);
+		}
+		Ok(features.clone().unwrap())
+	}
+
+	/// The state the ADB server sees the device in, like `device` or `recovery`.
+	pub fn state(&self) -> Result<DeviceState, Error> {
+		let mut service = b"host-serial:".to_vec();
+		service.extend_from_slice(&self.serial_number);
+		service.extend_from_slice(b":get-state");
+		let reply = self.client.host_query(&service)?;
+		Ok(String::from_utf8_lossy(&reply).trim().into())
+	}
+
+	pub fn has_feature(&self, feature: &str) -> Result<bool, Error> {
+		Ok(self.features()?.iter().any(|f| f == feature))
+	}
+
+	#[must_use]
+	pub fn client(&self) -> &AdbClient {
+		&self.client
+	}
+
+	#[must_use]
+	pub fn serial_number(&self) -> &SerialNumber {
+		&self.serial_number
+	}
+
+	/// Opens a device service like `shell:…`, `exec:…` or `sync:`.
+	pub fn open(&self, service: &(impl AsRef<[u8]> + ?Sized)) -> Result<Connection, Error> {
+		self.client.open(&self.serial_number, service)
+	}
+
+	pub fn sync(&self) -> Result<SyncConnection, Error> {
+		self.client.sync(&self.serial_number)
+	}
+
+	/// Runs `command` in the device's shell, returning its standard output.
+	///
+	/// Arguments should be quoted with [`quote`]. Runs as root if [`gain_root`](`Device::gain_root`) resorted to `su`.
+	pub fn exec_out(&self, command: &(impl AsRef<[u8]> + ?Sized)) -> Result<Vec<u8>, Error> {
+		let mut output = Vec::new();
+		self.exec_reader(command)?.read_to_end(&mut output)?;
+		Ok(output)
+	}
+
+	/// Like [`exec_out`](`Device::exec_out`), but streams the output instead of collecting it.
+	pub fn exec_reader(&self, command: &(impl AsRef<[u8]> + ?Sized)) -> Result<Connection, Error> {
+		self.exec(command.as_ref(), self.su())
+	}
+
+	fn exec(&self, command: &[u8], su: Option<Su>) -> Result<Connection, Error> {
+		let mut service = b"exec:".to_vec();
+		match su {
+			Some(su) => service.extend_from_slice(&su.wrap(command)),
+			None => service.extend_from_slice(command),
+		}
+		self.open(&service)
+	}
+
+	pub(crate) fn su(&self) -> Option<Su> {
+		*self.su.lock().unwrap()
+	}
+
+	/// The user ID commands run as, if `id` works.
+	fn uid(&self, su: Option<Su>) -> Result<Option<u32>, Error> {
+		let mut output = Vec::new();
+		self.exec(b"id -u", su)?.read_to_end(&mut output)?;
+		Ok(String::from_utf8_lossy(&output).trim().parse().ok())
+	}
+
+	/// Finds a way for listing and pulling to reach files the `shell` user can't read, and reports which one is used.
+	///
+	/// Nothing needs to be done if adbd already runs as root, like in TWRP.
+	/// Otherwise, depending on `strategy`, tries `adb root`, which restarts adbd and waits up to ten seconds for it,
+	/// then `su`, which may have to be granted on the device's screen.
+	/// With `su`, [`ls`](`Device::ls`) and [`pull_reader`](`Device::pull_reader`) run `stat` and `cat` instead of using SYNC.
+	pub fn gain_root(&self, strategy: RootStrategy) -> Result<Access, Error> {
+		*self.su.lock().unwrap() = None;
+		if self.uid(None)? == Some(0) {
+			let mut version = Vec::new();
+			self.exec(b"getprop ro.twrp.version", None)?
+				.read_to_end(&mut version)?;
+			let version = String::from_utf8_lossy(&version).trim().to_string();
+			return Ok(if version.is_empty() {
+				Access::Root
+			} else {
+				Access::Twrp(version)
+			});
+		}
+		if strategy.tries_adb_root() && self.adb_root()? {
+			return Ok(Access::AdbRoot);
+		}
+		if strategy.tries_su() {
+			for su in Su::ALL {
+				if self.uid(Some(su))? == Some(0) {
+					*self.su.lock().unwrap() = Some(su);
+					return Ok(Access::Su(su));
+				}
+			}
+		}
+		Ok(Access::Shell)
+	}
+
+	/// Asks adbd to restart as root, returning whether it came back as root.
+	fn adb_root(&self) -> Result<bool, Error> {
+		let mut reply = Vec::new();
+		self.open("root:")?.read_to_end(&mut reply)?;
+		// Production builds answer `adbd cannot run as root in production builds`.
+		if !String::from_utf8_lossy(&reply).contains("restarting adbd as root") {
+			return Ok(false);
+		}
+		for _ in 0..20 {
+			// The device is briefly gone while adbd restarts.
+			if let Ok(Some(0)) = self.uid(None) {
+				return Ok(true);
+			}
+			thread::sleep(Duration::from_millis(500));
+		}
+		Ok(false)
+	}
+
+	/// Reads the target of the symlink at `path`.
+	pub fn readlink(&self, path: &(impl AsRef<RawPath> + ?Sized)) -> Result<RawPathBuf, Error> {
+		let path = path.as_ref();
+		let mut command = b"readlink ".to_vec();
+		command.extend_from_slice(&quote(path));
+		let mut target = self.exec_out(&command)?;
+		if target.pop() != Some(b'\n') {
+			return Err(AdbError::Failed {
+				path: Some(path.to_owned()),
+				message: "Could not read symlink".to_string(),
+			}
+			.into());
+		}
+		Ok(RawPathBuf(RawString(target)))
+	}
+
+	/// Runs a checksum tool like `sha256sum` on the file at `path`, returning the digest it printed.
+	///
+	/// Returns [`None`] if the output doesn't start with a hex digest, for example because the tool is missing.
+	pub fn checksum(
+		&self,
+		command: &str,
+		path: &(impl AsRef<RawPath> + ?Sized),
+	) -> Result<Option<Vec<u8>>, Error> {
+		let mut line = format!("{} ", command).into_bytes();
+		line.extend_from_slice(&quote(path.as_ref()));
+		let output = self.exec_out(&line)?;
+		let digest = output
+			.split(u8::is_ascii_whitespace)
+			.next()
+			.unwrap_or_default();
+		Ok(hex::decode(digest).ok().filter(|digest| !digest.is_empty()))
+	}
+
+	/// Reads the major and minor number of the block or character device at `path`.
+	pub fn device_numbers(
+		&self,
+		path: &(impl AsRef<RawPath> + ?Sized),
+	) -> Result<(u32, u32), Error> {
+		let path = path.as_ref();
+		let mut command = b"stat -c '%t %T' ".to_vec();
+		command.extend_from_slice(&quote(path));
+		let output = self.exec_out(&command)?;
+		let text = String::from_utf8_lossy(&output);
+		let mut numbers = text
+			.split_whitespace()
+			.map(|number| u32::from_str_radix(number, 16));
+		match (numbers.next(), numbers.next(), numbers.next()) {
+			(Some(Ok(major)), Some(Ok(minor)), None) => Ok((major, minor)),
+			_ => Err(AdbError::Parse {
+				what: "device numbers",
+				raw: RawString(output),
+			}
+			.into()),
+		}
+	}
+
+	/// Reads all system properties, like `ro.crypto.state`, with `getprop`.
+	pub fn properties(&self) -> Result<BTreeMap<String, String>, Error> {
+		let output = self.exec_out("getprop")?;
+		Ok(RawStr::new(&output)
+			.lines()
+			.filter_map(|line| {
+				let line = std::str::from_utf8(line).ok()?;
+				let (key, value) = line.strip_prefix('[')?.split_once("]: [")?;
+				Some((key.to_string(), value.strip_suffix(']')?.to_string()))
+			})
+			.collect())
+	}
+
+	/// Reads the mounted filesystems from `/proc/mounts`.
+	pub fn mounts(&self) -> Result<Vec<Mount>, Error> {
+		let output = self.exec_out("cat /proc/mounts")?;
+		Ok(RawStr::new(&output)
+			.lines()
+			.filter_map(Mount::parse)
+			.collect())
+	}
+
+	/// Reads the owner and SELinux context of each entry in the directory at `path`, with `find` and `stat`.
+	///
+	/// Entries whose names contain a newline are left out. Devices without SELinux report no contexts.
+	pub fn security(&self, path: &(impl AsRef<RawPath> + ?Sized)) -> Result<Vec<Security>, Error> {
+		let mut command = b"cd ".to_vec();
+		command.extend_from_slice(&quote(path.as_ref()));
+		command.extend_from_slice(
+			b" && find . -mindepth 1 -maxdepth 1 -exec stat -c '%u %g %C %n' {} + 2>/dev/null",
+		);
+		let output = self.exec_out(&command)?;
+		Ok(RawStr::new(&output)
+			.lines()
+			.filter_map(Security::parse)
+			.collect())
+	}
+
+	pub fn ls(
+		&self,
+		path: &(impl AsRef<RawPath> + ?Sized),
+	) -> Result<impl Iterator<Item = LsEntry>, Error> {
+		if self.su().is_some() {
+			return Ok(self.stat_dir(path.as_ref())?.into_iter());
+		}
+		let ls_v2 = self.has_feature("ls_v2")?;
+		let mut sync = self.sync()?;
+		let entries = if ls_v2 {
+			sync.list2(path)?
+				.into_iter()
+				.map(|dent| dent.into_entry(path.as_ref()))
+				.collect::<Result<_, _>>()?
+		} else {
+			sync.list(path)?
+		};
+		sync.quit()?;
+		Ok(entries.into_iter())
+	}
+
+	/// Lists the directory at `path` with `find` and `stat`, for when SYNC runs as a user that can't.
+	///
+	/// Entries whose names contain a newline are left out.
+	fn stat_dir(&self, path: &RawPath) -> Result<Vec<LsEntry>, Error> {
+		let mut command = b"cd ".to_vec();
+		command.extend_from_slice(&quote(path));
+		command.extend_from_slice(
+			b" 2>&1 && find . -mindepth 1 -maxdepth 1 -exec stat -c '%f %s %Y %u %g %n' {} + 2>/dev/null",
+		);
+		let output = self.exec_out(&command)?;
+		let mut lines = RawStr::new(&output).lines().peekable();
+		// Only `cd` prints errors.
+		if let Some(first) = lines.peek() {
+			if parse_stat(first).is_none() {
+				return Err(AdbError::from_device_message(
+					path.to_owned(),
+					&String::from_utf8_lossy(first),
+				)
+				.into());
+			}
+		}
+		Ok(lines.filter_map(parse_stat).collect())
+	}
+
+	pub fn pull(
+		&self,
+		path: &(impl AsRef<RawPath> + ?Sized),
+		expected_size: u64,
+	) -> Result<Vec<u8>, Error> {
+		let mut file = Vec::with_capacity(usize::try_from(expected_size).unwrap_or(0));
+		self.pull_to(path, expected_size, &mut file)?;
+		Ok(file)
+	}
+
+	/// Streams the file at `path` into `target`, returning the number of bytes written.
+	///
+	/// Fails after the transfer if it didn't yield exactly `expected_size` bytes.
+	pub fn pull_to(
+		&self,
+		path: &(impl AsRef<RawPath> + ?Sized),
+		expected_size: u64,
+		mut target: impl Write,
+	) -> Result<u64, Error> {
+		io::copy(&mut self.pull_reader(path, expected_size)?, &mut target)
+	}
+
+	/// Streams `data` into the file at `path`, creating or replacing it along with any missing parent directories.
+	///
+	/// `mode` should include the file type bits, `mtime` is in seconds since the Unix epoch.
+	/// If [`gain_root`](`Device::gain_root`) resorted to `su`, the file is written with `cat` instead of SYNC.
+	pub fn push(
+		&self,
+		path: &(impl AsRef<RawPath> + ?Sized),
+		mode: u32,
+		mtime: u32,
+		data: impl Read,
+	) -> Result<u64, Error> {
+		if let Some(su) = self.su() {
+			return self.push_exec(path.as_ref(), mode, mtime, data, su);
+		}
+		let mut sync = self.sync()?;
+		let written = sync.send(path, mode, mtime, data)?;
+		sync.quit()?;
+		Ok(written)
+	}
+
+	/// Pipes `data` into `cat` run through `su`, then sets the file's mode and mtime and checks its size.
+	fn push_exec(
+		&self,
+		path: &RawPath,
+		mode: u32,
+		mtime: u32,
+		mut data: impl Read,
+		su: Su,
+	) -> Result<u64, Error> {
+		let mut command = Vec::new();
+		if let Some(dir) = path.directory() {
+			command.extend_from_slice(b"mkdir -p ");
+			command.extend_from_slice(&quote(dir));
+			command.extend_from_slice(b" && ");
+		}
+		command.extend_from_slice(b"cat > ");
+		command.extend_from_slice(&quote(path));
+		let mut connection = self.exec(&command, Some(su))?;
+		let written = io::copy(&mut data, &mut connection)?;
+		// Closing stdin ends `cat`, and the connection closes once it exits.
+		let mut stream = connection.into_inner();
+		stream.shutdown(Shutdown::Write)?;
+		io::copy(&mut stream, &mut io::sink())?;
+
+		let mut command = format!("chmod {:o} ", mode & 0o7777).into_bytes();
+		command.extend_from_slice(&quote(path));
+		command.extend_from_slice(format!(" && touch -c -m -d @{} ", mtime).as_bytes());
+		command.extend_from_slice(&quote(path));
+		command.extend_from_slice(b" && stat -c %s ");
+		command.extend_from_slice(&quote(path));
+		command.extend_from_slice(b" 2>&1");
+		let output = self.exec_out(&command)?;
+		let output = String::from_utf8_lossy(&output);
+		let size = output
+			.trim()
+			.parse::<u64>()
+			.map_err(|_| AdbError::from_device_message(path.to_owned(), output.trim()))?;
+		if size != written {
+			return Err(AdbError::SizeMismatch {
+				path: path.to_owned(),
+				expected: written,
+				actual: size,
+			}
+			.into());
+		}
+		Ok(written)
+	}
+
+	pub fn pull_reader(
+		&self,
+		path: &(impl AsRef<RawPath> + ?Sized),
+		expected_size: u64,
+	) -> Result<PullReader, Error> {
+		let path = path.as_ref();
+		let source = if self.su().is_some() {
+			let mut command = b"cat ".to_vec();
+			command.extend_from_slice(&quote(path));
+			command.extend_from_slice(b" 2>/dev/null");
+			Source::Exec(self.exec(&command, self.su())?)
+		} else {
+			Source::Sync(self.sync()?.into_recv(path)?)
+		};
+		Ok(PullReader {
+			source,
+			path: path.to_owned(),
+			expected_size,
+			received: 0,
+		})
+	}
+}
+
+/// The owner and SELinux context of a directory entry, as read by [`Device::security`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct Security {
+	pub name: RawString,
+	pub uid: u32,
+	pub gid: u32,
+	/// The `security.selinux` label, like `u:object_r:media_rw_data_file:s0`.
+	pub context: Option<RawString>,
+}
+
+impl Security {
+	/// Parses a line of `stat -c '%u %g %C %n'`, without its line ending, where the name starts with `./`.
+	#[must_use]
+	pub fn parse(line: &RawStr) -> Option<Self> {
+		let mut fields = line.splitn(4, |b| *b == b' ');
+		let mut number = || std::str::from_utf8(fields.next()?).ok()?.parse().ok();
+		let (uid, gid) = (number()?, number()?);
+		let context = fields.next()?;
+		let name = fields.next()?.strip_prefix(b"./")?;
+		if name.is_empty() || name.contains(&b'/') {
+			return None;
+		}
+		Some(Self {
+			name: RawString(name.to_vec()),
+			uid,
+			gid,
+			// `stat` prints `?` without SELinux.
+			context: (context != b"?" && !context.is_empty()).then(|| RawString(context.to_vec())),
+		})
+	}
+}
+
+/// A mounted filesystem, as listed by [`Device::mounts`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct Mount {
+	/// The block device or other source, like `/dev/block/by-name/userdata` or `tmpfs`.
+	pub source: RawString,
+	pub mount_point: RawPathBuf,
+	pub fs_type: String,
+	/// Comma-separated, like `rw,seclabel,nosuid`.
+	pub options: String,
+}
+
+impl Mount {
+	/// Parses a line of `/proc/mounts`, without its line ending.
+	#[must_use]
+	pub fn parse(line: &RawStr) -> Option<Self> {
+		let mut fields = line.split(|b| *b == b' ');
+		let source = unescape(fields.next()?);
+		let mount_point = unescape(fields.next()?);
+		let mut text = || Some(String::from_utf8_lossy(fields.next()?).into_owned());
+		Some(Self {
+			source,
+			mount_point: RawPathBuf(mount_point),
+			fs_type: text()?,
+			options: text()?,
+		})
+	}
+
+	#[must_use]
+	pub fn is_read_only(&self) -> bool {
+		self.options.split(',').any(|option| option == "ro")
+	}
+}
+
+/// Decodes the octal escapes like `\040` that `/proc/mounts` uses for spaces and the like.
+fn unescape(field: &[u8]) -> RawString {
+	let mut unescaped = Vec::with_capacity(field.len());
+	let mut rest = field;
+	while let Some((&b, tail)) = rest.split_first() {
+		let code = tail
+			.get(..3)
+			.filter(|_| b == b'\\')
+			.and_then(|digits| std::str::from_utf8(digits).ok())
+			.and_then(|digits| u8::from_str_radix(digits, 8).ok());
+		if let Some(code) = code {
+			unescaped.push(code);
+			rest = &tail[3..];
+		} else {
+			unescaped.push(b);
+			rest = tail;
+		}
+	}
+	RawString(unescaped)
+}
+
+/// Parses a line of `stat -c '%f %s %Y %u %g %n'`, where the name starts with `./`.
+fn parse_stat(line: &RawStr) -> Option<LsEntry> {
+	let mut fields = line.splitn(6, |b| *b == b' ');
+	let mut field = || std::str::from_utf8(fields.next()?).ok();
+	let mode = u32::from_str_radix(field()?, 16).ok()?;
+	let size = field()?.parse().ok()?;
+	let mtime = field()?.parse().ok()?;
+	let (uid, gid) = (field()?.parse().ok()?, field()?.parse().ok()?);
+	let name = fields.next()?.strip_prefix(b"./")?;
+	if name.is_empty() || name.contains(&b'/') {
+		return None;
+	}
+	Some(LsEntry {
+		mode: UnixMode::new(mode),
+		size,
+		mtime: Epoch::from_timestamp(mtime),
+		atime: None,
+		ctime: None,
+		uid: Some(uid),
+		gid: Some(gid),
+		context: None,
+		name: RawString(name.to_vec()),
+	})
+}
+
+/// Where a [`PullReader`] gets its data from.
+#[derive(Debug)]
+enum Source {
+	Sync(Recv<SyncConnection>),
+	/// `cat` run through `su`.
+	Exec(Connection),
+}
+
+impl Read for Source {
+	fn read(&mut self, buf: &mut [u8]) -> Result<usize, Error> {
+		match self {
+			Self::Sync(recv) => recv.read(buf),
+			Self::Exec(connection) => connection.read(buf),
+		}
+	}
+}
+
+/// The contents of a file being pulled from the device.
+///
+/// Reaching the end fails with [`AdbError::SizeMismatch`] if the size doesn't match what was expected.
+#[derive(Debug)]
+pub struct PullReader {
+	source: Source,
+	path: RawPathBuf,
+	expected_size: u64,
+	received: u64,
+}
+
+impl PullReader {
+	#[must_use]
+	pub fn received(&self) -> u64 {
+		self.received
+	}
+}
+
+impl Read for PullReader {
+	fn read(&mut self, buf: &mut [u8]) -> Result<usize, Error> {
+		let read = self.source.read(buf)?;
+		self.received += read as u64;
+		if read == 0 && self.received != self.expected_size && !buf.is_empty() {
+			return Err(AdbError::SizeMismatch {
+				path: self.path.clone(),
+				expected: self.expected_size,
+				actual: self.received,
+			}
+			.into());
+		}
+		Ok(read)
+	}
+}
+
+/// Quotes `argument` for the device's shell, byte for byte.
+#[must_use]
+pub fn quote(argument: &[u8]) -> Vec<u8> {
+	let mut quoted = vec![b'\''];
+	for b in argument {
+		if *b == b'\'' {
+			quoted.extend_from_slice(b"'\\''");
+		} else {
+			quoted.push(*b);
+		}
+	}
+	quoted.push(b'\'');
+	quoted
+}
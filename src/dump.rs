@@ -0,0 +1,1194 @@
+//! The dump engine: walks paths on a [`Backend`] and writes what it finds into split archive volumes.
+//!
+//! Progress is recorded in a [`Journal`] in the output directory, so an interrupted dump can be resumed.
+//! Entries that can't be read are handled according to the [`ErrorPolicy`].
+//! Directory listings and small files can be fetched ahead on several threads (see [`Options::jobs`]), while archives are still written in order.
+//! Progress is reported through [`Options::progress`].
+//! Owners and SELinux contexts are recorded along with the other metadata if [`Options::security`] is set.
+
+use crate::{
+	archive::{self, Format, Metadata, Special},
+	device::{Device, Security},
+	journal::{self, Completion, Journal},
+	manifest::{self, Baseline},
+	progress::{Event, Progress},
+	rules::Rules,
+	verify::Algorithm,
+	LsEntry, ModeKind, RawPath, RawPathBuf, RawStr,
+};
+use sha2::{Digest, Sha256};
+use std::{
+	cell::{Cell, RefCell},
+	collections::{HashMap, VecDeque},
+	convert::TryFrom,
+	fmt::{self, Display, Formatter},
+	fs::{self, File, OpenOptions},
+	io::{self, Error, Read, Seek, SeekFrom, Write},
+	path::PathBuf,
+	sync::{mpsc, Mutex},
+	thread,
+	time::{Duration, SystemTime},
+};
+
+/// Where a dump reads from.
+///
+/// Implemented by [`Device`], but anything that can list directories and stream files will do.
+/// Parallel dumps call it from several threads at once.
+pub trait Backend: Sync {
+	fn ls(&self, path: &RawPath) -> Result<Vec<LsEntry>, Error>;
+
+	/// Streams the file at `path` into `target`, failing if it didn't yield exactly `expected_size` bytes.
+	fn pull_to(
+		&self,
+		path: &RawPath,
+		expected_size: u64,
+		target: &mut dyn Write,
+	) -> Result<u64, Error>;
+
+	fn readlink(&self, path: &RawPath) -> Result<RawPathBuf, Error>;
+
+	/// The major and minor number of a block or character device.
+	fn device_numbers(&self, path: &RawPath) -> Result<(u32, u32), Error>;
+
+	/// Hashes the file at `path` without transferring it, if the backend can.
+	///
+	/// Returns [`None`] if `algorithm` isn't available.
+	fn checksum(&self, path: &RawPath, algorithm: Algorithm) -> Result<Option<Vec<u8>>, Error>;
+
+	/// The owner and SELinux context of each entry in the directory at `path`, for [`Options::security`].
+	///
+	/// Entries left out keep what [`ls`](Self::ls) found.
+	fn security(&self, path: &RawPath) -> Result<Vec<Security>, Error>;
+}
+
+impl Backend for Device {
+	fn ls(&self, path: &RawPath) -> Result<Vec<LsEntry>, Error> {
+		Ok(Device::ls(self, path)?.collect())
+	}
+
+	fn pull_to(
+		&self,
+		path: &RawPath,
+		expected_size: u64,
+		target: &mut dyn Write,
+	) -> Result<u64, Error> {
+		Device::pull_to(self, path, expected_size, target)
+	}
+
+	fn readlink(&self, path: &RawPath) -> Result<RawPathBuf, Error> {
+		Device::readlink(self, path)
+	}
+
+	fn device_numbers(&self, path: &RawPath) -> Result<(u32, u32), Error> {
+		Device::device_numbers(self, path)
+	}
+
+	fn checksum(&self, path: &RawPath, algorithm: Algorithm) -> Result<Option<Vec<u8>>, Error> {
+		Ok(Device::checksum(self, algorithm.command(), path)?
+			.filter(|digest| digest.len() == algorithm.len()))
+	}
+
+	fn security(&self, path: &RawPath) -> Result<Vec<Security>, Error> {
+		Device::security(self, path)
+	}
+}
+
+/// What to do when an entry can't be listed or pulled.
+///
+/// Errors writing the archives always abort the dump.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ErrorPolicy {
+	/// Stop the dump.
+	Abort,
+	/// Record the entry as lost and carry on.
+	Skip,
+	/// Try again up to `attempts` more times, waiting `backoff` (doubled each time) in between, then skip.
+	Retry { attempts: u32, backoff: Duration },
+}
+
+/// An entry that couldn't be dumped.
+#[derive(Debug)]
+pub struct Failure {
+	pub path: RawPathBuf,
+	pub error: Error,
+}
+
+impl Display for Failure {
+	fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+		write!(f, "{:?}: {}", self.path, self.error)
+	}
+}
+
+/// The outcome of a dump that wasn't aborted.
+#[derive(Debug, Default)]
+pub struct Summary {
+	/// Entries skipped because of errors, in the order they occurred.
+	pub failures: Vec<Failure>,
+}
+
+impl Summary {
+	/// Whether nothing was lost.
+	#[must_use]
+	pub fn is_complete(&self) -> bool {
+		self.failures.is_empty()
+	}
+}
+
+/// The name of the per-volume list of entries that couldn't be dumped.
+pub const ERRORS_FILE_NAME: &str = "adb-dump.errors.txt";
+
+/// The name of the per-volume list of block and character devices, FIFOs and sockets.
+///
+/// Each line holds the quoted path (encoded with [`RawStr::encode_lossless`](crate::RawStr::encode_lossless) if it isn't UTF-8 or has a `%`), kind, octal mode, mtime and, for devices, `major:minor`, separated by tabs.
+pub const SPECIAL_FILE_NAME: &str = "adb-dump.special.txt";
+
+/// The name of the per-volume checksum manifest, which [`check`](crate::check) validates the volume against.
+///
+/// Each line holds the hex-encoded path, size, octal mode, mtime, hex SHA-256 and volume number of a regular file, separated by tabs.
+pub const MANIFEST_FILE_NAME: &str = "adb-dump.manifest.txt";
+
+/// The name of the per-volume list of files an incremental dump found deleted since its [`Options::since`] baseline.
+///
+/// Each line holds one hex-encoded path.
+pub const DELETED_FILE_NAME: &str = "adb-dump.deleted.txt";
+
+/// Whether `path` (relative to the archive root) is one of the lists each volume may end with.
+#[must_use]
+pub fn is_manifest(path: &RawPath) -> bool {
+	[
+		ERRORS_FILE_NAME,
+		SPECIAL_FILE_NAME,
+		MANIFEST_FILE_NAME,
+		DELETED_FILE_NAME,
+		archive::NAMES_FILE_NAME,
+		archive::SECURITY_FILE_NAME,
+	]
+	.iter()
+	.any(|name| **path == *name)
+}
+
+/// Whether `path` (relative to the archive root) looks like an `IGNORED` marker for an excluded entry.
+///
+/// Markers are told apart by name alone, so a device file that is itself called `IGNORED` or `*.IGNORED`
+/// is taken for one too: `verify` doesn't compare it and `restore` doesn't write it back.
+#[must_use]
+pub fn is_marker(path: &RawPath) -> bool {
+	**path == "IGNORED" || path.ends_with(b"/IGNORED") || path.ends_with(b".IGNORED")
+}
+
+#[derive(Debug, Clone)]
+#[allow(clippy::struct_excessive_bools)]
+pub struct Options {
+	/// Paths on the device to dump.
+	pub paths: Vec<RawPathBuf>,
+	pub output_dir: PathBuf,
+	/// Start a new volume before exceeding this many bytes of file contents.
+	pub split_size: u64,
+	/// Write one volume set per subdirectory of each path.
+	pub split: bool,
+	pub format: Format,
+	/// `-1` only prints errors, `1` and above list every file.
+	pub verbosity: i8,
+	/// List what would be dumped, without pulling files or writing anything.
+	pub dry_run: bool,
+	/// Continue from the journal in `output_dir` instead of refusing to overwrite it.
+	pub resume: bool,
+	pub error_policy: ErrorPolicy,
+	/// Earlier dumps of the same paths (output directories or manifests), oldest first.
+	/// If any are given, only files that are new or changed since are pulled, and deletions are recorded.
+	pub since: Vec<PathBuf>,
+	/// With [`since`](Self::since), compare files of unchanged size by SHA-256 on the device instead of by mtime.
+	pub compare_checksums: bool,
+	/// How many directories to list and small files to pull at once. `1` does everything in order.
+	///
+	/// Archives are written in the same order either way.
+	pub jobs: usize,
+	/// List everything once before dumping, to report totals in an [`Event::Scanned`].
+	///
+	/// Costs a second listing of every directory.
+	pub scan: bool,
+	pub progress: Option<Progress>,
+	/// Record each entry's SELinux context, and its owner if the device doesn't support `LIS2`.
+	///
+	/// Costs a shell command per directory.
+	pub security: bool,
+}
+
+struct Dump<'a> {
+	backend: &'a dyn Backend,
+	rules: &'a Rules,
+	options: &'a Options,
+	journal: RefCell<Journal>,
+	/// Holds each file while it's pulled, so failed attempts don't leave partial entries in the archive.
+	/// Only used if errors don't abort the dump.
+	spool: Option<RefCell<File>>,
+	failures: RefCell<Vec<Failure>>,
+	/// The files of [`Options::since`].
+	baseline: Baseline,
+	/// Whether the backend can hash files for [`Options::compare_checksums`].
+	checksums: bool,
+	prefetch: Option<Prefetch>,
+}
+
+impl Dump<'_> {
+	fn list(&self, path: &RawPath) -> Result<Vec<LsEntry>, Error> {
+		list(self.backend, path, self.options.security)
+	}
+
+	fn report(&self, event: &Event) {
+		if let Some(progress) = &self.options.progress {
+			progress.report(event);
+		}
+	}
+
+	/// Runs `f` according to the error policy, returning [`None`] if `path` was skipped.
+	fn attempt<T>(
+		&self,
+		volumes: &mut Volumes,
+		path: &RawPath,
+		mut f: impl FnMut() -> Result<T, Error>,
+	) -> Result<Option<T>, Error> {
+		let (mut retries, mut backoff) = match self.options.error_policy {
+			ErrorPolicy::Abort => return f().map(Some),
+			ErrorPolicy::Skip => (0, Duration::default()),
+			ErrorPolicy::Retry { attempts, backoff } => (attempts, backoff),
+		};
+		loop {
+			match f() {
+				Ok(value) => return Ok(Some(value)),
+				Err(error) if retries > 0 => {
+					if self.options.verbosity >= 0 {
+						eprintln!("Retrying {:?} in {:?}: {}", path, backoff, error);
+					}
+					thread::sleep(backoff);
+					retries -= 1;
+					backoff *= 2;
+				}
+				Err(error) => {
+					eprintln!("FAILED {:?}: {}", path, error);
+					volumes
+						.errors
+						.push(format!("{:?}\t{}", path.encode_lossless(), error));
+					self.failures.borrow_mut().push(Failure {
+						path: path.to_owned(),
+						error,
+					});
+					return Ok(None);
+				}
+			}
+		}
+	}
+}
+
+impl Dump<'_> {
+	/// Starts prefetching the subdirectories and small files in `entries`, the contents of `path`.
+	fn enter(&self, archive_root: &RawPath, path: &RawPath, entries: &[LsEntry]) {
+		let Some(prefetch) = &self.prefetch else {
+			return;
+		};
+		let mut frame = Frame::default();
+		for entry in entries {
+			if entry.name == "." || entry.name == ".." {
+				continue;
+			}
+			let path = path.join(entry.name.as_str());
+			if self.journal.borrow().is_complete(&path)
+				|| self.rules.excluded_by(&path, Some(entry)).is_some()
+			{
+				continue;
+			}
+			if entry.mode.is_dir() {
+				frame.dirs.push_back(path);
+			} else if entry.mode.is_file()
+				&& entry.size <= MAX_PREFETCH_SIZE
+				&& !self.options.dry_run
+				&& !self.may_be_unchanged(path.without_prefix(archive_root), entry)
+			{
+				frame.queue.push_back((path, entry.size));
+			}
+		}
+		prefetch.frames.borrow_mut().push(frame);
+		prefetch.fill();
+	}
+
+	/// Stops prefetching for the directory last [`enter`](Self::enter)ed.
+	fn leave(&self) {
+		if let Some(prefetch) = &self.prefetch {
+			let frame = prefetch.frames.borrow_mut().pop();
+			if let Some(frame) = frame {
+				let held: u64 = frame.pulls.values().map(|(size, _)| size).sum();
+				prefetch.budget.set(prefetch.budget.get() + held);
+				prefetch
+					.listings_left
+					.set(prefetch.listings_left.get() + frame.listings.len());
+			}
+			prefetch.fill();
+		}
+	}
+
+	/// Whether [`is_unchanged`] could skip the file without pulling it.
+	fn may_be_unchanged(&self, relative: &RawPath, entry: &LsEntry) -> bool {
+		self.baseline.get(relative).map_or(false, |base| {
+			base.size == entry.size && (self.checksums || base.mtime == entry.mtime.timestamp())
+		})
+	}
+
+	/// The listing of `path`, if it was prefetched.
+	fn prefetched_listing(&self, path: &RawPath) -> Option<Result<Vec<LsEntry>, Error>> {
+		let prefetch = self.prefetch.as_ref()?;
+		let receiver = {
+			let mut frames = prefetch.frames.borrow_mut();
+			let receiver = frames
+				.iter_mut()
+				.rev()
+				.find_map(|frame| frame.listings.remove(path));
+			if receiver.is_none() {
+				// Not listed ahead yet, so the walker lists it instead.
+				for frame in frames.iter_mut().rev() {
+					if let Some(i) = frame.dirs.iter().position(|queued| **queued == *path) {
+						frame.dirs.remove(i);
+						break;
+					}
+				}
+			}
+			receiver?
+		};
+		let result = receiver.recv().ok();
+		prefetch.listings_left.set(prefetch.listings_left.get() + 1);
+		prefetch.fill();
+		result
+	}
+
+	/// The contents of the file at `path`, if they were prefetched.
+	fn prefetched_file(&self, path: &RawPath) -> Option<Result<Vec<u8>, Error>> {
+		let prefetch = self.prefetch.as_ref()?;
+		let pull = {
+			let mut frames = prefetch.frames.borrow_mut();
+			let frame = frames.last_mut()?;
+			frame.queue.retain(|(queued, _)| **queued != *path);
+			frame.pulls.remove(path)
+		};
+		let (size, receiver) = pull?;
+		let result = receiver.recv().ok();
+		prefetch.budget.set(prefetch.budget.get() + size);
+		prefetch.fill();
+		result
+	}
+}
+
+/// The most file contents held in memory by prefetching.
+const PREFETCH_BUDGET: u64 = 64 << 20;
+
+/// The most directory listings started or held ahead of the walker.
+const MAX_PREFETCH_LISTINGS: usize = 64;
+
+/// Larger files are pulled when they're written, since they're limited by bandwidth rather than latency.
+const MAX_PREFETCH_SIZE: u64 = 1 << 20;
+
+/// Lists directories and pulls small files ahead of the walker, on worker threads.
+///
+/// Each directory being walked has a [`Frame`] of what's prefetched for it, innermost last.
+struct Prefetch {
+	jobs: mpsc::Sender<Job>,
+	frames: RefCell<Vec<Frame>>,
+	/// How many more bytes may be pulled ahead.
+	budget: Cell<u64>,
+	/// How many more directories may be listed ahead.
+	listings_left: Cell<usize>,
+}
+
+#[derive(Default)]
+struct Frame {
+	/// Subdirectories yet to be listed, in order.
+	dirs: VecDeque<RawPathBuf>,
+	listings: HashMap<RawPathBuf, Pending<Vec<LsEntry>>>,
+	/// Files yet to be pulled, in order, with their size.
+	queue: VecDeque<(RawPathBuf, u64)>,
+	pulls: HashMap<RawPathBuf, (u64, Pending<Vec<u8>>)>,
+}
+
+/// The outcome of a [`Job`], once it's done.
+type Pending<T> = mpsc::Receiver<Result<T, Error>>;
+
+enum Job {
+	Ls(RawPathBuf, mpsc::Sender<Result<Vec<LsEntry>, Error>>),
+	Pull(RawPathBuf, u64, mpsc::Sender<Result<Vec<u8>, Error>>),
+}
+
+impl Prefetch {
+	fn new(jobs: mpsc::Sender<Job>) -> Self {
+		Self {
+			jobs,
+			frames: RefCell::default(),
+			budget: Cell::new(PREFETCH_BUDGET),
+			listings_left: Cell::new(MAX_PREFETCH_LISTINGS),
+		}
+	}
+
+	fn send(&self, job: Job) {
+		// The workers only stop once this is dropped.
+		self.jobs.send(job).unwrap();
+	}
+
+	/// Starts listing queued directories and pulling queued files while the budgets allow, innermost directory first.
+	fn fill(&self) {
+		let mut frames = self.frames.borrow_mut();
+		for frame in frames.iter_mut().rev() {
+			while self.listings_left.get() > 0 {
+				let Some(path) = frame.dirs.pop_front() else {
+					break;
+				};
+				self.listings_left.set(self.listings_left.get() - 1);
+				let (sender, receiver) = mpsc::channel();
+				self.send(Job::Ls(path.clone(), sender));
+				frame.listings.insert(path, receiver);
+			}
+			while let Some((_, size)) = frame.queue.front() {
+				if *size > self.budget.get() {
+					return;
+				}
+				let (path, size) = frame.queue.pop_front().unwrap();
+				self.budget.set(self.budget.get() - size);
+				let (sender, receiver) = mpsc::channel();
+				self.send(Job::Pull(path.clone(), size, sender));
+				frame.pulls.insert(path, (size, receiver));
+			}
+		}
+	}
+}
+
+/// Runs [`Job`]s until the [`Prefetch`] is dropped.
+fn work(backend: &dyn Backend, security: bool, jobs: &Mutex<mpsc::Receiver<Job>>) {
+	loop {
+		let job = jobs.lock().unwrap().recv();
+		match job {
+			Ok(Job::Ls(path, result)) => {
+				let _ = result.send(list(backend, &path, security));
+			}
+			Ok(Job::Pull(path, size, result)) => {
+				let mut data = Vec::with_capacity(usize::try_from(size).unwrap_or_default());
+				let pulled = backend.pull_to(&path, size, &mut data).map(|_| data);
+				let _ = result.send(pulled);
+			}
+			Err(_) => return,
+		}
+	}
+}
+
+/// Lists the directory at `path`, with [`Backend::security`] merged in if `security` is set.
+fn list(backend: &dyn Backend, path: &RawPath, security: bool) -> Result<Vec<LsEntry>, Error> {
+	let mut entries = backend.ls(path)?;
+	if security {
+		let mut found: HashMap<_, _> = backend
+			.security(path)?
+			.into_iter()
+			.map(|security| (security.name.clone(), security))
+			.collect();
+		for entry in &mut entries {
+			if let Some(security) = found.remove(&entry.name) {
+				entry.uid = Some(security.uid);
+				entry.gid = Some(security.gid);
+				entry.context = security.context;
+			}
+		}
+	}
+	Ok(entries)
+}
+
+/// A series of `name.N.zip` (or other [`Format::extension`]) archive volumes, each started on demand.
+struct Volumes {
+	name: String,
+	count: usize,
+	cumulative_file_size: u64,
+	archive: Option<Box<dyn archive::Writer>>,
+	/// Entries written to the current volume, to be journaled once it's finished.
+	completed: Vec<Completion>,
+	/// Lines for the current volume's error manifest.
+	errors: Vec<String>,
+	/// Lines for the current volume's list of special files.
+	specials: Vec<String>,
+	/// Lines for the current volume's checksum manifest.
+	manifest: Vec<String>,
+	/// Lines for the current volume's list of deleted files.
+	deleted: Vec<String>,
+}
+
+impl Volumes {
+	fn new(dump: &Dump, name: &[u8]) -> Self {
+		let name = sanitize_file_name(name);
+		Self {
+			count: dump.journal.borrow().next_volume(&name) - 1,
+			name,
+			cumulative_file_size: 0,
+			archive: None,
+			completed: Vec::new(),
+			errors: Vec::new(),
+			specials: Vec::new(),
+			manifest: Vec::new(),
+			deleted: Vec::new(),
+		}
+	}
+
+	fn current(&mut self, dump: &Dump) -> Result<&mut dyn archive::Writer, Error> {
+		match &mut self.archive {
+			Some(archive) => Ok(archive.as_mut()),
+			archive @ None => {
+				self.count += 1;
+				let writer = dump
+					.options
+					.format
+					.create(&dump.options.output_dir.join(format!(
+						"{}.{}.{}",
+						self.name,
+						self.count,
+						dump.options.format.extension()
+					)))?;
+				dump.journal
+					.borrow_mut()
+					.volume_started(&self.name, self.count)?;
+				Ok(archive.get_or_insert(writer).as_mut())
+			}
+		}
+	}
+
+	/// Makes room for a file of `size` bytes, moving on to the next volume if necessary.
+	fn reserve(&mut self, dump: &Dump, size: u64) -> Result<&mut dyn archive::Writer, Error> {
+		if self.cumulative_file_size > 0
+			&& self.cumulative_file_size + size > dump.options.split_size
+		{
+			self.finish(dump)?;
+		}
+		self.cumulative_file_size += size;
+		self.current(dump)
+	}
+
+	fn complete(&mut self, completion: Completion) {
+		self.completed.push(completion);
+	}
+
+	fn finish(&mut self, dump: &Dump) -> Result<(), Error> {
+		self.cumulative_file_size = 0;
+		for (name, lines) in [
+			(SPECIAL_FILE_NAME, std::mem::take(&mut self.specials)),
+			(ERRORS_FILE_NAME, std::mem::take(&mut self.errors)),
+			(MANIFEST_FILE_NAME, std::mem::take(&mut self.manifest)),
+			(DELETED_FILE_NAME, std::mem::take(&mut self.deleted)),
+		] {
+			if !lines.is_empty() && !dump.options.dry_run {
+				let mut text = lines.join("\n");
+				text.push('\n');
+				self.current(dump)?
+					.add_file(&generated(name.into()), text.as_bytes())?;
+			}
+		}
+		let volume = match self.archive.take() {
+			Some(archive) => {
+				archive.finish()?;
+				Some((self.name.as_str(), self.count))
+			}
+			None if self.completed.is_empty() => return Ok(()),
+			None => None,
+		};
+		dump.journal.borrow_mut().commit(volume, &self.completed)?;
+		self.completed.clear();
+		Ok(())
+	}
+}
+
+/// Dumps `options.paths` from `backend`, skipping whatever `rules` exclude.
+pub fn run(backend: &dyn Backend, rules: &Rules, options: &Options) -> Result<Summary, Error> {
+	let journal_path = options.output_dir.join(journal::FILE_NAME);
+	let journal = if options.dry_run {
+		Journal::disabled()
+	} else if options.resume {
+		Journal::resume(&journal_path)?
+	} else {
+		Journal::create(&journal_path)?
+	};
+	// Declared before the spool, so it's only removed once closed.
+	let mut spool_cleanup = RemoveOnDrop(None);
+	let spool = if options.dry_run || options.error_policy == ErrorPolicy::Abort {
+		None
+	} else {
+		let path = options.output_dir.join("adb-dump.partial");
+		let file = OpenOptions::new()
+			.create(true)
+			.truncate(true)
+			.read(true)
+			.write(true)
+			.open(&path)?;
+		spool_cleanup.0 = Some(path);
+		Some(RefCell::new(file))
+	};
+	let baseline = Baseline::load(&options.since)?;
+	// Errors just mean mtimes are compared instead.
+	let checksums = options.compare_checksums
+		&& !options.since.is_empty()
+		&& matches!(
+			backend.checksum(RawPath::new("/dev/null"), Algorithm::Sha256),
+			Ok(Some(_))
+		);
+	if options.compare_checksums && !checksums && options.verbosity >= 0 {
+		eprintln!("Can't hash files on the device, comparing them by mtime instead");
+	}
+	let (jobs, queue) = mpsc::channel();
+	let queue = Mutex::new(queue);
+	let failures = thread::scope(|scope| {
+		let prefetch = (options.jobs > 1).then(|| {
+			for _ in 0..options.jobs {
+				scope.spawn(|| work(backend, options.security, &queue));
+			}
+			Prefetch::new(jobs)
+		});
+		let dump = &Dump {
+			backend,
+			rules,
+			options,
+			journal: RefCell::new(journal),
+			spool,
+			failures: RefCell::default(),
+			baseline,
+			checksums,
+			prefetch,
+		};
+		if options.scan {
+			let (files, bytes) = scan(dump);
+			dump.report(&Event::Scanned { files, bytes });
+		}
+		walk(dump)?;
+		Ok::<_, Error>(dump.failures.replace(Vec::new()))
+	})?;
+	Ok(Summary { failures })
+}
+
+/// Removes the file at its path, if any, when dropped, so it's gone however the dump ends.
+pub(crate) struct RemoveOnDrop(pub(crate) Option<PathBuf>);
+
+impl Drop for RemoveOnDrop {
+	fn drop(&mut self) {
+		if let Some(path) = &self.0 {
+			let _ = fs::remove_file(path);
+		}
+	}
+}
+
+fn walk(dump: &Dump) -> Result<(), Error> {
+	let options = dump.options;
+
+	if options.split {
+		let mut root_volumes = Volumes::new(dump, b"adb-dump_root");
+		for arg_path in &options.paths {
+			// Root subdirectories are present in the archives.
+			let archive_root = arg_path.join("");
+			let Some(entries) =
+				dump.attempt(&mut root_volumes, arg_path, || dump.list(arg_path))?
+			else {
+				continue;
+			};
+			// With several paths, their entries would be mixed up at the archive root.
+			if options.paths.len() == 1 {
+				record_deletions(dump, &mut root_volumes, "".into(), &entries);
+			}
+			dump.enter(&archive_root, arg_path, &entries);
+			for entry in entries {
+				if entry.name == "." || entry.name == ".." {
+					continue;
+				}
+				let path = arg_path.join(entry.name.as_str());
+				if dump.journal.borrow().is_complete(&path) {
+					continue;
+				}
+				if entry.mode.kind() == ModeKind::Dir
+					&& dump.rules.excluded_by(&path, Some(&entry)).is_none()
+				{
+					let mut volumes = Volumes::new(dump, &entry.name);
+					add_directory(dump, &mut volumes, &archive_root, &path, &entry)?;
+					visit_dir(dump, &mut volumes, &archive_root, &path)?;
+					volumes.finish(dump)?;
+				} else {
+					visit_entry(dump, &mut root_volumes, &archive_root, &path, &entry)?;
+				}
+			}
+			dump.leave();
+		}
+		root_volumes.finish(dump)?;
+	} else {
+		let mut volumes = Volumes::new(dump, b"backup");
+		for arg_path in &options.paths {
+			let prefix = arg_path.directory().unwrap_or_else(|| "".into());
+			if !dump.journal.borrow().is_complete(arg_path)
+				&& !is_excluded(dump, &mut volumes, prefix, arg_path, None)?
+			{
+				visit_dir(dump, &mut volumes, prefix, arg_path)?;
+			}
+		}
+		volumes.finish(dump)?;
+	}
+	Ok(())
+}
+
+/// Counts the regular files and their bytes that [`walk`] will come across, ignoring errors.
+fn scan(dump: &Dump) -> (u64, u64) {
+	let mut totals = (0, 0);
+	for arg_path in &dump.options.paths {
+		if !dump.journal.borrow().is_complete(arg_path)
+			&& dump.rules.excluded_by(arg_path, None).is_none()
+		{
+			scan_dir(dump, arg_path, &mut totals);
+		}
+	}
+	totals
+}
+
+fn scan_dir(dump: &Dump, path: &RawPath, totals: &mut (u64, u64)) {
+	let Ok(entries) = dump.backend.ls(path) else {
+		return;
+	};
+	for entry in entries {
+		if entry.name == "." || entry.name == ".." {
+			continue;
+		}
+		let path = path.join(entry.name.as_str());
+		if dump.journal.borrow().is_complete(&path)
+			|| dump.rules.excluded_by(&path, Some(&entry)).is_some()
+		{
+			continue;
+		}
+		if entry.mode.is_dir() {
+			scan_dir(dump, &path, totals);
+		} else if entry.mode.is_file() {
+			totals.0 += 1;
+			totals.1 += entry.size;
+		}
+	}
+}
+
+/// Replaces everything but ASCII alphanumerics, `.`, `-` and `_` with `_`.
+#[must_use]
+pub fn sanitize_file_name(name: &[u8]) -> String {
+	name.iter()
+		.map(|b| match b {
+			b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'.' | b'-' | b'_' => char::from(*b),
+			_ => '_',
+		})
+		.collect()
+}
+
+/// Metadata for files written by the dump itself, rather than pulled from the device.
+fn generated(path: &RawPath) -> Metadata<'_> {
+	let now = SystemTime::now()
+		.duration_since(SystemTime::UNIX_EPOCH)
+		.map_or(0, |since| {
+			i64::try_from(since.as_secs()).unwrap_or(i64::MAX)
+		});
+	Metadata::new(path, 0o644, now)
+}
+
+fn metadata<'a>(path: &'a RawPath, entry: &'a LsEntry) -> Metadata<'a> {
+	Metadata {
+		uid: entry.uid,
+		gid: entry.gid,
+		context: entry.context.as_deref(),
+		..Metadata::new(path, entry.mode.permissions(), entry.mtime.timestamp())
+	}
+}
+
+fn visit_dir(
+	dump: &Dump,
+	volumes: &mut Volumes,
+	archive_root: &RawPath,
+	path: &RawPath,
+) -> Result<(), Error> {
+	if dump.options.verbosity >= 0 {
+		println!("dir {:?}", &path);
+	}
+	dump.report(&Event::Dir(path));
+
+	let mut prefetched = dump.prefetched_listing(path);
+	let Some(entries) = dump.attempt(volumes, path, || {
+		prefetched.take().unwrap_or_else(|| dump.list(path))
+	})?
+	else {
+		return Ok(());
+	};
+	record_deletions(dump, volumes, path.without_prefix(archive_root), &entries);
+	dump.enter(archive_root, path, &entries);
+	let failures = dump.failures.borrow().len();
+	for entry in entries {
+		if entry.name != "." && entry.name != ".." {
+			visit_entry(
+				dump,
+				volumes,
+				archive_root,
+				&path.join(entry.name.as_str()),
+				&entry,
+			)?;
+		}
+	}
+
+	dump.leave();
+
+	// Directories with lost entries are revisited when resuming.
+	if dump.failures.borrow().len() == failures {
+		volumes.complete(Completion::Dir(path.to_owned()));
+	}
+	Ok(())
+}
+
+/// Lists the baseline's files below `dir` (relative to the archive root) that are gone from its current `entries`.
+fn record_deletions(dump: &Dump, volumes: &mut Volumes, dir: &RawPath, entries: &[LsEntry]) {
+	let prefix_len = if dir.is_empty() { 0 } else { dir.len() + 1 };
+	for file in dump.baseline.below(dir) {
+		let rest: &[u8] = &file.path[prefix_len..];
+		let (name, nested) = match rest.iter().position(|b| *b == b'/') {
+			Some(slash) => (&rest[..slash], true),
+			None => (rest, false),
+		};
+		if !entries
+			.iter()
+			.any(|entry| &**entry.name == name && (entry.mode.is_dir() || !nested))
+		{
+			volumes.deleted.push(hex::encode(&***file.path));
+		}
+	}
+}
+
+/// Whether the file at `path` is the same as in the baseline, so it doesn't have to be pulled again.
+fn is_unchanged(dump: &Dump, relative: &RawPath, path: &RawPath, entry: &LsEntry) -> bool {
+	let Some(base) = dump.baseline.get(relative) else {
+		return false;
+	};
+	if base.size != entry.size {
+		return false;
+	}
+	if dump.checksums {
+		// Errors just mean the file is pulled again.
+		if let Ok(Some(sha256)) = dump.backend.checksum(path, Algorithm::Sha256) {
+			return sha256 == base.sha256;
+		}
+	}
+	base.mtime == entry.mtime.timestamp()
+}
+
+fn visit_entry(
+	dump: &Dump,
+	volumes: &mut Volumes,
+	archive_root: &RawPath,
+	path: &RawPath,
+	entry: &LsEntry,
+) -> Result<(), Error> {
+	if dump.journal.borrow().is_complete(path)
+		|| is_excluded(dump, volumes, archive_root, path, Some(entry))?
+	{
+		return Ok(());
+	}
+
+	match entry.mode.kind() {
+		dir if dir == ModeKind::Dir => {
+			add_directory(dump, volumes, archive_root, path, entry)?;
+			visit_dir(dump, volumes, archive_root, path)
+		}
+		file if file == ModeKind::File => visit_file(dump, volumes, archive_root, path, entry),
+		symlink if symlink == ModeKind::Symlink => {
+			visit_symlink(dump, volumes, archive_root, path, entry)
+		}
+		_ => visit_special(dump, volumes, archive_root, path, entry),
+	}
+}
+
+/// Checks `path` against the rules, recording an `IGNORED` marker in the archive if it's excluded.
+///
+/// For directories, the marker is placed inside. Other entries get a sibling `name.IGNORED`.
+fn is_excluded(
+	dump: &Dump,
+	volumes: &mut Volumes,
+	archive_root: &RawPath,
+	path: &RawPath,
+	entry: Option<&LsEntry>,
+) -> Result<bool, Error> {
+	let Some(rule) = dump.rules.excluded_by(path, entry) else {
+		return Ok(false);
+	};
+
+	if dump.options.verbosity >= 0 {
+		eprintln!("IGNORED {:?}: {}", path, rule);
+	}
+	if !dump.options.dry_run {
+		let relative = path.without_prefix(archive_root);
+		let (marker, completion) = if entry.map_or(true, |entry| entry.mode.is_dir()) {
+			(relative.join("IGNORED"), Completion::Dir(path.to_owned()))
+		} else {
+			let mut marker = relative.to_owned();
+			marker += RawStr::new(".IGNORED");
+			(marker, Completion::File(path.to_owned()))
+		};
+		volumes
+			.current(dump)?
+			.add_file(&generated(&marker), format!("{}\n", rule).as_bytes())?;
+		volumes.complete(completion);
+	}
+	Ok(true)
+}
+
+fn add_directory(
+	dump: &Dump,
+	volumes: &mut Volumes,
+	archive_root: &RawPath,
+	path: &RawPath,
+	entry: &LsEntry,
+) -> Result<(), Error> {
+	if dump.options.dry_run {
+		return Ok(());
+	}
+	volumes
+		.current(dump)?
+		.add_directory(&metadata(path.without_prefix(archive_root), entry))
+}
+
+fn visit_file(
+	dump: &Dump,
+	volumes: &mut Volumes,
+	archive_root: &RawPath,
+	path: &RawPath,
+	entry: &LsEntry,
+) -> Result<(), Error> {
+	let relative = path.without_prefix(archive_root);
+	if is_unchanged(dump, relative, path, entry) {
+		if dump.options.verbosity >= 1 {
+			println!("unchanged {:?}", &path);
+		}
+		if !dump.options.dry_run {
+			volumes.complete(Completion::File(path.to_owned()));
+		}
+		dump.report(&Event::Skipped {
+			path,
+			size: entry.size,
+		});
+		return Ok(());
+	}
+	if dump.options.verbosity >= 1 || dump.options.dry_run {
+		println!("file {:?}", &path);
+	}
+	if dump.options.dry_run {
+		dump.report(&Event::Skipped {
+			path,
+			size: entry.size,
+		});
+		return Ok(());
+	}
+	dump.report(&Event::File {
+		path,
+		size: entry.size,
+	});
+
+	let metadata = metadata(relative, entry);
+	let mut prefetched = dump.prefetched_file(path);
+	let (size, sha256) = if let Some(spool) = &dump.spool {
+		let spool = &mut *spool.borrow_mut();
+		let Some(size) = dump.attempt(volumes, path, || {
+			spool.set_len(0)?;
+			spool.seek(SeekFrom::Start(0))?;
+			match prefetched.take() {
+				Some(data) => {
+					let data = data?;
+					spool.write_all(&data)?;
+					dump.report(&Event::Bytes(data.len() as u64));
+					Ok(data.len() as u64)
+				}
+				None => {
+					dump.backend
+						.pull_to(path, entry.size, &mut Reporting { dump, inner: spool })
+				}
+			}
+		})?
+		else {
+			dump.report(&Event::Skipped {
+				path,
+				size: entry.size,
+			});
+			return Ok(());
+		};
+		spool.seek(SeekFrom::Start(0))?;
+		let mut target =
+			Sha256Writer::new(volumes.reserve(dump, size)?.start_file(&metadata, size)?);
+		io::copy(&mut Read::by_ref(spool).take(size), &mut target)?;
+		(size, target.finish())
+	} else {
+		let size = entry.size;
+		let mut target =
+			Sha256Writer::new(volumes.reserve(dump, size)?.start_file(&metadata, size)?);
+		match prefetched {
+			Some(data) => {
+				let data = data?;
+				target.write_all(&data)?;
+				dump.report(&Event::Bytes(data.len() as u64));
+			}
+			None => {
+				dump.backend.pull_to(
+					path,
+					entry.size,
+					&mut Reporting {
+						dump,
+						inner: &mut target,
+					},
+				)?;
+			}
+		}
+		(size, target.finish())
+	};
+	volumes.manifest.push(
+		manifest::Entry {
+			path: metadata.path.to_owned(),
+			size,
+			permissions: metadata.permissions,
+			mtime: metadata.mtime,
+			sha256,
+			volume: volumes.count,
+		}
+		.to_string(),
+	);
+	volumes.complete(Completion::File(path.to_owned()));
+	dump.report(&Event::Done { path, size });
+	Ok(())
+}
+
+/// Reports [`Event::Bytes`] for everything written through it.
+struct Reporting<'a> {
+	dump: &'a Dump<'a>,
+	inner: &'a mut dyn Write,
+}
+
+impl Write for Reporting<'_> {
+	fn write(&mut self, buf: &[u8]) -> Result<usize, Error> {
+		let written = self.inner.write(buf)?;
+		self.dump.report(&Event::Bytes(written as u64));
+		Ok(written)
+	}
+
+	fn flush(&mut self) -> Result<(), Error> {
+		self.inner.flush()
+	}
+}
+
+/// Hashes everything written through it.
+struct Sha256Writer<'a> {
+	inner: &'a mut dyn Write,
+	hasher: Sha256,
+}
+
+impl<'a> Sha256Writer<'a> {
+	fn new(inner: &'a mut dyn Write) -> Self {
+		Self {
+			inner,
+			hasher: Sha256::new(),
+		}
+	}
+
+	fn finish(self) -> Vec<u8> {
+		self.hasher.finalize().to_vec()
+	}
+}
+
+impl Write for Sha256Writer<'_> {
+	fn write(&mut self, buf: &[u8]) -> Result<usize, Error> {
+		let written = self.inner.write(buf)?;
+		self.hasher.update(&buf[..written]);
+		Ok(written)
+	}
+
+	fn flush(&mut self) -> Result<(), Error> {
+		self.inner.flush()
+	}
+}
+
+fn visit_symlink(
+	dump: &Dump,
+	volumes: &mut Volumes,
+	archive_root: &RawPath,
+	path: &RawPath,
+	entry: &LsEntry,
+) -> Result<(), Error> {
+	if dump.options.verbosity >= 1 || dump.options.dry_run {
+		println!("link {:?}", &path);
+	}
+	if dump.options.dry_run {
+		return Ok(());
+	}
+
+	let Some(target) = dump.attempt(volumes, path, || dump.backend.readlink(path))? else {
+		return Ok(());
+	};
+	volumes
+		.current(dump)?
+		.add_symlink(&metadata(path.without_prefix(archive_root), entry), &target)?;
+	volumes.complete(Completion::File(path.to_owned()));
+	Ok(())
+}
+
+/// Adds block and character devices, FIFOs and sockets to the archive,
+/// or lists them in the volume's [`SPECIAL_FILE_NAME`] if the format can't represent them.
+fn visit_special(
+	dump: &Dump,
+	volumes: &mut Volumes,
+	archive_root: &RawPath,
+	path: &RawPath,
+	entry: &LsEntry,
+) -> Result<(), Error> {
+	let kind = match entry.mode.kind() {
+		kind if kind == ModeKind::BlockDevice => "block",
+		kind if kind == ModeKind::CharDevice => "char",
+		kind if kind == ModeKind::Fifo => "fifo",
+		kind if kind == ModeKind::Socket => "socket",
+		_ => "unknown",
+	};
+	if dump.options.verbosity >= 1 || dump.options.dry_run {
+		println!("{} {:?}", kind, &path);
+	}
+	if dump.options.dry_run {
+		return Ok(());
+	}
+
+	let numbers = if entry.mode.is_block_device() || entry.mode.is_char_device() {
+		match dump.attempt(volumes, path, || dump.backend.device_numbers(path))? {
+			Some(numbers) => Some(numbers),
+			None => return Ok(()),
+		}
+	} else {
+		None
+	};
+	let special = match (kind, numbers) {
+		("block", Some((major, minor))) => Some(Special::BlockDevice { major, minor }),
+		("char", Some((major, minor))) => Some(Special::CharDevice { major, minor }),
+		("fifo", _) => Some(Special::Fifo),
+		("socket", _) => Some(Special::Socket),
+		_ => None,
+	};
+
+	let metadata = metadata(path.without_prefix(archive_root), entry);
+	let added = match special {
+		Some(special) => volumes.current(dump)?.add_special(&metadata, special)?,
+		None => false,
+	};
+	if !added {
+		volumes
+			.specials
+			.push(special_line(&metadata, kind, numbers));
+	}
+	volumes.complete(Completion::File(path.to_owned()));
+	Ok(())
+}
+
+/// A line of the [`SPECIAL_FILE_NAME`] list.
+pub(crate) fn special_line(metadata: &Metadata, kind: &str, numbers: Option<(u32, u32)>) -> String {
+	let mut line = format!(
+		"{:?}\t{}\t{:o}\t{}",
+		metadata.path.encode_lossless(),
+		kind,
+		metadata.permissions,
+		metadata.mtime,
+	);
+	if let Some((major, minor)) = numbers {
+		line = format!("{}\t{}:{}", line, major, minor);
+	}
+	line
+}
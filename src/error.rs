@@ -0,0 +1,162 @@
+//! Classified failures of the ADB server and devices.
+//!
+//! Functions still return [`io::Error`], but those caused by the server or a device carry an [`AdbError`],
+//! which [`AdbError::of`] gets back out. Their [`ErrorKind`] is set to match, too.
+
+use crate::{RawPathBuf, RawString};
+use std::{
+	fmt::{self, Display, Formatter},
+	io::{self, ErrorKind},
+};
+
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum AdbError {
+	/// No device is attached, or none with the requested serial number.
+	NoDevice,
+	/// More than one device is attached and the request didn't say which one to use.
+	MultipleDevices,
+	/// The device hasn't accepted this computer's key yet.
+	Unauthorized,
+	Offline,
+	PermissionDenied {
+		path: RawPathBuf,
+	},
+	NotFound {
+		path: RawPathBuf,
+	},
+	/// A pulled file didn't have the expected size.
+	SizeMismatch {
+		path: RawPathBuf,
+		expected: u64,
+		actual: u64,
+	},
+	/// The server or device answered something this client doesn't understand.
+	Protocol(String),
+	/// Output of the server or a device command that couldn't be made sense of.
+	Parse {
+		/// What was expected, like `device line`.
+		what: &'static str,
+		raw: RawString,
+	},
+	/// Any other failure the server or device reported, with its message.
+	Failed {
+		path: Option<RawPathBuf>,
+		message: String,
+	},
+}
+
+impl AdbError {
+	/// The [`AdbError`] inside `error`, if any.
+	#[must_use]
+	pub fn of(error: &io::Error) -> Option<&Self> {
+		error.get_ref()?.downcast_ref()
+	}
+
+	/// Classifies the message of a `FAIL` response from the ADB server.
+	#[must_use]
+	pub fn from_server_message(message: &str) -> Self {
+		let lowercase = message.to_lowercase();
+		if lowercase.contains("unauthorized") {
+			Self::Unauthorized
+		} else if lowercase.contains("offline") {
+			Self::Offline
+		} else if lowercase.contains("more than one") {
+			Self::MultipleDevices
+		} else if lowercase.contains("no devices")
+			|| (lowercase.starts_with("device") && lowercase.contains("not found"))
+		{
+			Self::NoDevice
+		} else {
+			Self::Failed {
+				path: None,
+				message: message.to_string(),
+			}
+		}
+	}
+
+	/// Classifies the message of a SYNC `FAIL` response, or an error printed by a device command, concerning `path`.
+	#[must_use]
+	pub fn from_device_message(path: RawPathBuf, message: &str) -> Self {
+		if message.contains("Permission denied") || message.contains("Operation not permitted") {
+			Self::PermissionDenied { path }
+		} else if message.contains("No such file or directory") {
+			Self::NotFound { path }
+		} else {
+			Self::Failed {
+				path: Some(path),
+				message: message.to_string(),
+			}
+		}
+	}
+
+	/// Classifies an `errno` value the device reported for `path`, as in a `LIS2` entry.
+	#[must_use]
+	pub fn from_errno(path: RawPathBuf, errno: u32) -> Self {
+		// Linux's numbering, whatever the host's.
+		match errno {
+			1 | 13 => Self::PermissionDenied { path },
+			2 => Self::NotFound { path },
+			_ => Self::Failed {
+				path: Some(path),
+				message: format!("errno {}", errno),
+			},
+		}
+	}
+
+	#[must_use]
+	pub fn kind(&self) -> ErrorKind {
+		match self {
+			Self::NoDevice | Self::NotFound { .. } => ErrorKind::NotFound,
+			Self::MultipleDevices => ErrorKind::InvalidInput,
+			Self::Unauthorized | Self::PermissionDenied { .. } => ErrorKind::PermissionDenied,
+			Self::Offline => ErrorKind::NotConnected,
+			Self::SizeMismatch { .. } | Self::Protocol(_) | Self::Parse { .. } => {
+				ErrorKind::InvalidData
+			}
+			Self::Failed { .. } => ErrorKind::Other,
+		}
+	}
+}
+
+impl Display for AdbError {
+	fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+		match self {
+			Self::NoDevice => f.write_str("No device found"),
+			Self::MultipleDevices => f.write_str("More than one device attached"),
+			Self::Unauthorized => {
+				f.write_str("Device unauthorized, accept the debugging prompt on its screen")
+			}
+			Self::Offline => f.write_str("Device offline"),
+			Self::PermissionDenied { path } => write!(f, "Permission denied: {:?}", path),
+			Self::NotFound { path } => write!(f, "No such file or directory: {:?}", path),
+			Self::SizeMismatch {
+				path,
+				expected,
+				actual,
+			} => write!(
+				f,
+				"Error pulling {:?}: Expected {} bytes, got {}",
+				path, expected, actual
+			),
+			Self::Protocol(message) => write!(f, "Protocol error: {}", message),
+			Self::Parse { what, raw } => write!(f, "Could not make sense of {} {:?}", what, raw),
+			Self::Failed {
+				path: Some(path),
+				message,
+			} => write!(f, "{:?}: {}", path, message),
+			Self::Failed {
+				path: None,
+				message,
+			} => write!(f, "ADB server: {}", message),
+		}
+	}
+}
+
+impl std::error::Error for AdbError {}
+
+impl From<AdbError> for io::Error {
+	fn from(error: AdbError) -> Self {
+		Self::new(error.kind(), error)
+	}
+}
@@ -0,0 +1,308 @@
+//! Raw images of partitions, for what a file-level dump can't restore, like `persist`, `efs`, `modemst` or `nvdata`.
+//!
+//! Partitions are found in `/dev/block/by-name` and `/proc/partitions`, and streamed with `dd` into `<name>.img` files.
+//! Each image's size is checked against the block device's, and the whole partition table is recorded in
+//! [`PARTITIONS_FILE_NAME`] along with the size and SHA-256 of each image, which [`check`](crate::check) validates them against.
+
+use crate::{
+	device::{quote, Device},
+	dump::{sanitize_file_name, RemoveOnDrop},
+	error::AdbError,
+	AnError, RawPathBuf, RawStr,
+};
+use sha2::{Digest, Sha256};
+use std::{
+	collections::BTreeMap,
+	fmt::{self, Display, Formatter},
+	fs::{self, File},
+	io::{Error, ErrorKind, Read, Write},
+	path::{Path, PathBuf},
+};
+
+/// Where partitions' named links are, in order of preference. Older devices only have the second.
+pub const BY_NAME_DIRS: &[&str] = &["/dev/block/by-name", "/dev/block/bootdevice/by-name"];
+
+/// The name of the partition table written next to the images.
+///
+/// Each line holds the partition's name, block device, `major:minor` and size in bytes according to `/proc/partitions`,
+/// then, if it was imaged, the image's file name, size and hex SHA-256, separated by tabs.
+pub const PARTITIONS_FILE_NAME: &str = "adb-dump.partitions.txt";
+
+/// A line of `/proc/partitions`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BlockDevice {
+	/// The kernel's name, like `sda12` or `mmcblk0p5`.
+	pub name: String,
+	pub major: u32,
+	pub minor: u32,
+	/// Rounded down to whole KiB.
+	pub size: u64,
+}
+
+impl BlockDevice {
+	/// Parses a line of `/proc/partitions`, like `   8       12     524288 sda12`, without its line ending.
+	///
+	/// The header and blank lines yield [`None`].
+	#[must_use]
+	pub fn parse(line: &RawStr) -> Option<Self> {
+		let line = std::str::from_utf8(line).ok()?;
+		let fields: Vec<_> = line.split_whitespace().collect();
+		let [major, minor, blocks, name] = fields[..] else {
+			return None;
+		};
+		Some(Self {
+			name: name.to_string(),
+			major: major.parse().ok()?,
+			minor: minor.parse().ok()?,
+			size: blocks.parse::<u64>().ok()?.checked_mul(1024)?,
+		})
+	}
+
+	#[must_use]
+	pub fn path(&self) -> RawPathBuf {
+		format!("/dev/block/{}", self.name).as_str().into()
+	}
+}
+
+/// A block device that can be imaged, as listed by [`partitions`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Partition {
+	/// The name in [`BY_NAME_DIRS`], like `persist` or `modem_a`,
+	/// or the kernel's name for block devices without one.
+	pub name: String,
+	pub device: BlockDevice,
+}
+
+/// Lists the named partitions in the first of [`BY_NAME_DIRS`] that has any, in name order,
+/// followed by the other block devices in `/proc/partitions`, like whole disks.
+pub fn partitions(device: &Device) -> Result<Vec<Partition>, Error> {
+	let output = device.exec_out("cat /proc/partitions")?;
+	let mut unnamed: Vec<_> = RawStr::new(&output)
+		.lines()
+		.filter_map(BlockDevice::parse)
+		.collect();
+
+	let mut named = Vec::new();
+	for dir in BY_NAME_DIRS {
+		let mut links: Vec<_> = match device.ls(*dir) {
+			Ok(entries) => entries.filter(|entry| entry.mode.is_symlink()).collect(),
+			Err(error) if error.kind() == ErrorKind::NotFound => continue,
+			Err(error) => return Err(error),
+		};
+		if links.is_empty() {
+			continue;
+		}
+		links.sort_by(|a, b| a.name.cmp(&b.name));
+		for link in links {
+			let target = device.readlink(&*RawPathBuf::from(*dir).join(&*link.name))?;
+			let kernel_name = target.rsplit(|b| *b == b'/').next().unwrap_or_default();
+			let Some(i) = unnamed
+				.iter()
+				.position(|block| block.name.as_bytes() == kernel_name)
+			else {
+				continue;
+			};
+			named.push(Partition {
+				name: String::from_utf8_lossy(&link.name).into_owned(),
+				device: unnamed.remove(i),
+			});
+		}
+		break;
+	}
+	named.extend(unnamed.into_iter().map(|device| Partition {
+		name: device.name.clone(),
+		device,
+	}));
+	Ok(named)
+}
+
+#[derive(Debug)]
+pub struct Options {
+	/// Where to write the images and [`PARTITIONS_FILE_NAME`].
+	pub output_dir: PathBuf,
+	/// The names of the partitions to image, as in [`Partition::name`] or [`BlockDevice::name`].
+	pub partitions: Vec<String>,
+	/// `-1` only prints errors.
+	pub verbosity: i8,
+}
+
+/// A partition written to an image file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Image {
+	pub partition: Partition,
+	/// The image's file name in the output directory.
+	pub file_name: String,
+	pub size: u64,
+	pub sha256: Vec<u8>,
+}
+
+/// Images `options.partitions` and writes the partition table.
+///
+/// Images of other partitions from earlier runs into the same directory stay listed in the table.
+pub fn run(device: &Device, options: &Options) -> Result<Vec<Image>, Error> {
+	let table = partitions(device)?;
+	let selected = options
+		.partitions
+		.iter()
+		.map(|name| {
+			table
+				.iter()
+				.find(|partition| partition.name == *name)
+				.or_else(|| {
+					table
+						.iter()
+						.find(|partition| partition.device.name == *name)
+				})
+				.ok_or_else(|| {
+					Error::new(
+						ErrorKind::NotFound,
+						AnError(format!("No partition named {:?}", name)),
+					)
+				})
+		})
+		.collect::<Result<Vec<_>, _>>()?;
+
+	fs::create_dir_all(&options.output_dir)?;
+	let mut listed = earlier_images(options)?;
+	let mut images = Vec::new();
+	for partition in selected {
+		let image = image(device, partition, options)?;
+		listed.insert(image.partition.name.clone(), image.clone());
+		images.push(image);
+	}
+
+	let mut text = String::new();
+	for partition in &table {
+		let image = listed
+			.get(&partition.name)
+			.filter(|image| image.partition == *partition);
+		text.push_str(&table_line(partition, image));
+	}
+	fs::write(options.output_dir.join(PARTITIONS_FILE_NAME), text)?;
+	Ok(images)
+}
+
+/// Streams `partition` into its image file, through a temporary file that's only renamed once the size checks out.
+fn image(device: &Device, partition: &Partition, options: &Options) -> Result<Image, Error> {
+	let path = partition.device.path();
+	let size = exact_size(device, &path)?.unwrap_or(partition.device.size);
+	if options.verbosity >= 0 {
+		eprintln!("Imaging {} ({:?}, {} bytes)", partition.name, path, size);
+	}
+
+	let file_name = format!("{}.img", sanitize_file_name(partition.name.as_bytes()));
+	let partial = options.output_dir.join(format!("{}.partial", file_name));
+	let mut file = File::create(&partial)?;
+	// Unless it's renamed below, the partial image is removed however imaging fails.
+	let mut partial_cleanup = RemoveOnDrop(Some(partial.clone()));
+	let mut command = b"dd if=".to_vec();
+	command.extend_from_slice(&quote(&path));
+	command.extend_from_slice(b" bs=1048576 2>/dev/null");
+	let mut data = device.exec_reader(&command)?;
+
+	let mut hasher = Sha256::new();
+	let mut written = 0;
+	let mut buffer = vec![0; 1 << 20];
+	loop {
+		let read = data.read(&mut buffer)?;
+		if read == 0 {
+			break;
+		}
+		file.write_all(&buffer[..read])?;
+		hasher.update(&buffer[..read]);
+		written += read as u64;
+	}
+	file.sync_all()?;
+	drop(file);
+	if written != size {
+		return Err(AdbError::SizeMismatch {
+			path,
+			expected: size,
+			actual: written,
+		}
+		.into());
+	}
+	fs::rename(&partial, options.output_dir.join(&file_name))?;
+	partial_cleanup.0 = None;
+
+	Ok(Image {
+		partition: partition.clone(),
+		file_name,
+		size,
+		sha256: hasher.finalize().to_vec(),
+	})
+}
+
+/// The size of the block device at `path` in bytes, if `blockdev` can tell.
+fn exact_size(device: &Device, path: &RawPathBuf) -> Result<Option<u64>, Error> {
+	let mut command = b"blockdev --getsize64 ".to_vec();
+	command.extend_from_slice(&quote(path));
+	let output = device.exec_out(&command)?;
+	Ok(String::from_utf8_lossy(&output).trim().parse().ok())
+}
+
+/// The images listed in the [`PARTITIONS_FILE_NAME`] in `dir`, if there is one.
+pub fn listed_images(dir: &Path) -> Result<Vec<Image>, Error> {
+	let text = match fs::read_to_string(dir.join(PARTITIONS_FILE_NAME)) {
+		Ok(text) => text,
+		Err(error) if error.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+		Err(error) => return Err(error),
+	};
+	Ok(text.lines().filter_map(parse_table_line).collect())
+}
+
+/// The images listed in an existing [`PARTITIONS_FILE_NAME`] whose files are still there.
+fn earlier_images(options: &Options) -> Result<BTreeMap<String, Image>, Error> {
+	Ok(listed_images(&options.output_dir)?
+		.into_iter()
+		.filter(|image| options.output_dir.join(&image.file_name).is_file())
+		.map(|image| (image.partition.name.clone(), image))
+		.collect())
+}
+
+fn table_line(partition: &Partition, image: Option<&Image>) -> String {
+	match image {
+		Some(image) => format!(
+			"{}\t{}\t{}\t{}\n",
+			partition,
+			image.file_name,
+			image.size,
+			hex::encode(&image.sha256)
+		),
+		None => format!("{}\n", partition),
+	}
+}
+
+/// Parses a line of [`PARTITIONS_FILE_NAME`] with an image.
+fn parse_table_line(line: &str) -> Option<Image> {
+	let fields: Vec<_> = line.split('\t').collect();
+	let [name, path, numbers, device_size, file_name, size, sha256] = fields[..] else {
+		return None;
+	};
+	let (major, minor) = numbers.split_once(':')?;
+	Some(Image {
+		partition: Partition {
+			name: name.to_string(),
+			device: BlockDevice {
+				name: path.strip_prefix("/dev/block/")?.to_string(),
+				major: major.parse().ok()?,
+				minor: minor.parse().ok()?,
+				size: device_size.parse().ok()?,
+			},
+		},
+		file_name: file_name.to_string(),
+		size: size.parse().ok()?,
+		sha256: hex::decode(sha256).ok()?,
+	})
+}
+
+impl Display for Partition {
+	/// One tab-separated line of name, block device, `major:minor` and size in bytes.
+	fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+		write!(
+			f,
+			"{}\t/dev/block/{}\t{}:{}\t{}",
+			self.name, self.device.name, self.device.major, self.device.minor, self.device.size
+		)
+	}
+}
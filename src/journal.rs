@@ -0,0 +1,171 @@
+//! An append-only log of dump progress, so interrupted dumps can be resumed.
+//!
+//! Completed files and directories are only recorded once the archive volume containing them has been finished,
+//! since a ZIP file without its central directory can't be relied upon.
+//! Each line is one of:
+//!
+//! ```text
+//! adb-dump journal 1
+//! volume <name> <index>
+//! file <hex path>
+//! dir <hex path>
+//! commit <name> <index>
+//! ```
+//!
+//! A torn last line (from a crash while appending) is ignored.
+
+use crate::{AnError, RawPath, RawPathBuf};
+use std::{
+	collections::{HashMap, HashSet},
+	fs::{File, OpenOptions},
+	io::{BufRead, BufReader, Error, ErrorKind, Write},
+	path::Path,
+};
+
+const HEADER: &str = "adb-dump journal 1";
+
+/// The default file name, placed next to the archives.
+pub const FILE_NAME: &str = "adb-dump.journal";
+
+#[derive(Debug, Clone)]
+pub enum Completion {
+	File(RawPathBuf),
+	Dir(RawPathBuf),
+}
+
+#[derive(Debug, Default)]
+pub struct Journal {
+	file: Option<File>,
+	completed: HashSet<Vec<u8>>,
+	last_volumes: HashMap<String, usize>,
+}
+
+impl Journal {
+	/// A journal that doesn't record anything, for dry runs.
+	#[must_use]
+	pub fn disabled() -> Self {
+		Self::default()
+	}
+
+	/// Starts a new journal, failing if one already exists at `path`.
+	pub fn create(path: &Path) -> Result<Self, Error> {
+		let mut file = OpenOptions::new()
+			.create_new(true)
+			.write(true)
+			.open(path)
+			.map_err(|error| {
+				if error.kind() == ErrorKind::AlreadyExists {
+					Error::new(
+						ErrorKind::AlreadyExists,
+						AnError(format!(
+							"{} already exists. Resume the previous dump or choose another output directory.",
+							path.display()
+						)),
+					)
+				} else {
+					error
+				}
+			})?;
+		writeln!(file, "{}", HEADER)?;
+		file.sync_data()?;
+		Ok(Self {
+			file: Some(file),
+			..Self::default()
+		})
+	}
+
+	/// Reads the journal at `path` and continues appending to it, or creates a new one if there is none.
+	pub fn resume(path: &Path) -> Result<Self, Error> {
+		let file = match File::open(path) {
+			Ok(file) => file,
+			Err(error) if error.kind() == ErrorKind::NotFound => return Self::create(path),
+			Err(error) => return Err(error),
+		};
+
+		let mut journal = Self::default();
+		let mut pending = Vec::new();
+		let mut lines = BufReader::new(file).split(b'\n');
+		match lines.next().transpose()? {
+			Some(header) if header == HEADER.as_bytes() => (),
+			_ => {
+				return Err(Error::new(
+					ErrorKind::InvalidData,
+					AnError(format!("{} is not an adb-dump journal", path.display())),
+				))
+			}
+		}
+		for line in lines {
+			let line = line?;
+			let line = String::from_utf8_lossy(&line);
+			let mut fields = line.split(' ');
+			match (fields.next(), fields.next(), fields.next()) {
+				(Some("volume"), Some(name), Some(index)) => {
+					if let Ok(index) = index.parse() {
+						let last = journal.last_volumes.entry(name.to_string()).or_insert(0);
+						*last = (*last).max(index);
+					}
+				}
+				(Some("file" | "dir"), Some(path), None) => {
+					if let Ok(path) = hex::decode(path) {
+						pending.push(path);
+					}
+				}
+				(Some("commit"), Some(_), Some(_)) => journal.completed.extend(pending.drain(..)),
+				_ => (), // Most likely torn by a crash.
+			}
+		}
+
+		let mut file = OpenOptions::new().append(true).open(path)?;
+		// Make sure a torn line doesn't swallow the next record.
+		writeln!(file)?;
+		journal.file = Some(file);
+		Ok(journal)
+	}
+
+	#[must_use]
+	pub fn is_complete(&self, path: &RawPath) -> bool {
+		let path: &[u8] = path;
+		self.completed.contains(path)
+	}
+
+	/// The index to give the next volume called `name`, skipping over any left behind by an earlier run.
+	#[must_use]
+	pub fn next_volume(&self, name: &str) -> usize {
+		self.last_volumes.get(name).map_or(1, |last| last + 1)
+	}
+
+	pub fn volume_started(&mut self, name: &str, index: usize) -> Result<(), Error> {
+		let last = self.last_volumes.entry(name.to_string()).or_insert(0);
+		*last = (*last).max(index);
+		self.append(format!("volume {} {}\n", name, index).as_bytes())
+	}
+
+	/// Records `completions` as durable. `volume` is the one that was just finished, if any.
+	pub fn commit(
+		&mut self,
+		volume: Option<(&str, usize)>,
+		completions: &[Completion],
+	) -> Result<(), Error> {
+		let mut text = Vec::new();
+		for completion in completions {
+			let (tag, path) = match completion {
+				Completion::File(path) => ("file", path),
+				Completion::Dir(path) => ("dir", path),
+			};
+			let path: &[u8] = path;
+			writeln!(text, "{} {}", tag, hex::encode(path))?;
+			self.completed.insert(path.to_vec());
+		}
+		let (name, index) = volume.unwrap_or(("-", 0));
+		writeln!(text, "commit {} {}", name, index)?;
+		self.append(&text)
+	}
+
+	fn append(&mut self, text: &[u8]) -> Result<(), Error> {
+		if let Some(file) = &mut self.file {
+			file.write_all(text)?;
+			file.sync_data()?;
+		}
+		Ok(())
+	}
+}
@@ -1,20 +1,42 @@
 #![doc(html_root_url = "https://docs.rs/adb-dump/0.0.1")]
 #![warn(clippy::pedantic)]
-#![allow(clippy::missing_errors_doc)]
+#![allow(
+	clippy::missing_errors_doc,
+	clippy::missing_panics_doc,
+	clippy::uninlined_format_args
+)]
 
-use chrono::NaiveDateTime;
+use chrono::{DateTime, NaiveDateTime};
 use enumflags2::BitFlags;
 use std::{
-	any::type_name,
-	borrow::Borrow,
+	borrow::{Borrow, Cow},
 	convert::{TryFrom, TryInto},
-	ffi::OsString,
+	ffi::{OsStr, OsString},
 	fmt::{Debug, Display, Formatter},
-	io::{Error, ErrorKind},
+	io::{Error, ErrorKind, Read, Write},
 	ops::{AddAssign, Deref, Index, Range, RangeFrom, RangeInclusive, RangeTo},
-	process::{Command, Output},
 };
 
+pub mod access;
+pub mod archive;
+pub mod check;
+pub mod client;
+pub mod device;
+pub mod dump;
+pub mod error;
+pub mod image;
+pub mod journal;
+pub mod manifest;
+pub mod progress;
+pub mod recovery;
+pub mod restore;
+pub mod rules;
+pub mod store;
+pub mod sync;
+pub mod verify;
+use client::AdbClient;
+use device::{Device, DeviceInfo};
+
 #[cfg(doctest)]
 pub mod readme {
 	doc_comment::doctest!("../README.md");
@@ -69,33 +91,32 @@ impl UnixMode {
 	pub fn kind(&self) -> BitFlags<ModeKind> {
 		let mut result = BitFlags::empty();
 		if self.is_block_device() {
-			result |= ModeKind::BlockDevice
+			result |= ModeKind::BlockDevice;
 		}
 		if self.is_char_device() {
-			result |= ModeKind::CharDevice
+			result |= ModeKind::CharDevice;
 		}
 		if self.is_dir() {
-			result |= ModeKind::Dir
+			result |= ModeKind::Dir;
 		}
 		if self.is_fifo() {
-			result |= ModeKind::Fifo
+			result |= ModeKind::Fifo;
 		}
 		if self.is_file() {
-			result |= ModeKind::File
+			result |= ModeKind::File;
 		}
 		if self.is_socket() {
-			result |= ModeKind::Socket
+			result |= ModeKind::Socket;
 		}
 		if self.is_symlink() {
-			result |= ModeKind::Symlink
+			result |= ModeKind::Symlink;
 		}
 		result
 	}
 }
-impl ToString for UnixMode {
-	#[must_use]
-	fn to_string(&self) -> String {
-		unix_mode::to_string(self.0)
+impl Display for UnixMode {
+	fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+		f.write_str(&unix_mode::to_string(self.0))
 	}
 }
 
@@ -108,8 +129,18 @@ impl Debug for UnixMode {
 #[derive(Debug)]
 pub struct LsEntry {
 	pub mode: UnixMode,
-	pub size: u32,
-	pub epoch: Epoch,
+	pub size: u64,
+	pub mtime: Epoch,
+	/// Only known if the device supports `LIS2`.
+	pub atime: Option<Epoch>,
+	/// Only known if the device supports `LIS2`.
+	pub ctime: Option<Epoch>,
+	/// Only known if the device supports `LIS2`, or if [`Security`](device::Security) was collected.
+	pub uid: Option<u32>,
+	/// Only known if the device supports `LIS2`, or if [`Security`](device::Security) was collected.
+	pub gid: Option<u32>,
+	/// The `security.selinux` label, only known if [`Security`](device::Security) was collected.
+	pub context: Option<RawString>,
 	pub name: RawString,
 }
 
@@ -120,12 +151,35 @@ impl UnixMode {
 	}
 }
 
-#[derive(Debug)]
-pub struct Epoch(u32);
+/// A point in time, in nanoseconds since the Unix epoch.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Epoch(i64);
 impl Epoch {
+	/// Saturates outside of the years 1677 to 2262.
+	#[must_use]
+	pub fn from_timestamp(secs: i64) -> Self {
+		Self(secs.saturating_mul(1_000_000_000))
+	}
+
 	#[must_use]
-	pub fn from_timestamp(secs: u32) -> Self {
-		Self(secs)
+	pub fn from_nanos(nanos: i64) -> Self {
+		Self(nanos)
+	}
+
+	#[must_use]
+	pub fn as_nanos(&self) -> i64 {
+		self.0
+	}
+
+	/// Whole seconds since the Unix epoch, rounded down.
+	#[must_use]
+	pub fn timestamp(&self) -> i64 {
+		self.0.div_euclid(1_000_000_000)
+	}
+
+	#[must_use]
+	pub fn subsec_nanos(&self) -> u32 {
+		u32::try_from(self.0.rem_euclid(1_000_000_000)).unwrap()
 	}
 }
 
@@ -156,7 +210,9 @@ where
 impl Epoch {
 	#[must_use]
 	pub fn to_date_time(&self) -> NaiveDateTime {
-		NaiveDateTime::from_timestamp(i64::from(self.0), 0)
+		DateTime::from_timestamp(self.timestamp(), self.subsec_nanos())
+			.expect("`i64` nanoseconds are always in range")
+			.naive_utc()
 	}
 }
 
@@ -172,6 +228,64 @@ impl RawStr {
 	pub fn to_string_panicky(&self) -> String {
 		std::string::String::from_utf8(self.to_vec()).unwrap()
 	}
+
+	/// Returns valid UTF-8 without `%` unchanged. Otherwise, `%` and each byte that isn't part of valid UTF-8 are written as `%XX`.
+	///
+	/// Since every `%` in the result starts an escape, [`RawString::decode_lossless`] always gets the original back.
+	#[must_use]
+	pub fn encode_lossless(&self) -> Cow<'_, str> {
+		let mut rest = &self.0;
+		if let Ok(str) = std::str::from_utf8(rest) {
+			if !str.contains('%') {
+				return Cow::Borrowed(str);
+			}
+		}
+		let mut encoded = String::with_capacity(rest.len() + 8);
+		while !rest.is_empty() {
+			let (valid, invalid) = match std::str::from_utf8(rest) {
+				Ok(str) => (str, 0),
+				Err(err) => (
+					std::str::from_utf8(&rest[..err.valid_up_to()]).unwrap(),
+					err.error_len().unwrap_or(rest.len() - err.valid_up_to()),
+				),
+			};
+			encoded.push_str(&valid.replace('%', "%25"));
+			for b in &rest[valid.len()..valid.len() + invalid] {
+				encoded.push('%');
+				encoded.push_str(&hex::encode_upper([*b]));
+			}
+			rest = &rest[valid.len() + invalid..];
+		}
+		Cow::Owned(encoded)
+	}
+}
+
+impl RawString {
+	/// Reverses [`RawStr::encode_lossless`].
+	pub fn decode_lossless(encoded: &str) -> Result<Self, Error> {
+		let mut decoded = Vec::with_capacity(encoded.len());
+		let mut rest = encoded.as_bytes();
+		while let Some((&b, tail)) = rest.split_first() {
+			if b == b'%' {
+				let byte = tail
+					.get(..2)
+					.and_then(|hex| std::str::from_utf8(hex).ok())
+					.and_then(|hex| u8::from_str_radix(hex, 16).ok())
+					.ok_or_else(|| {
+						Error::new(
+							ErrorKind::InvalidData,
+							AnError(format!("Invalid escape in encoded name: {:?}", encoded)),
+						)
+					})?;
+				decoded.push(byte);
+				rest = &tail[2..];
+			} else {
+				decoded.push(b);
+				rest = tail;
+			}
+		}
+		Ok(Self(decoded))
+	}
 }
 
 impl PartialEq<&str> for RawStr {
@@ -210,11 +324,11 @@ impl RawPath {
 	#[must_use]
 	pub fn join_impl(&self, other: &RawPath) -> RawPathBuf {
 		let mut slash = 1;
-		if self.ends_with(&[b'/']) {
-			slash -= 1
+		if self.ends_with(b"/") {
+			slash -= 1;
 		}
-		if other.ends_with(&[b'/']) {
-			slash -= 1
+		if other.ends_with(b"/") {
+			slash -= 1;
 		}
 		let mut result = Vec::new();
 		result.extend(&self.0 .0);
@@ -246,6 +360,7 @@ impl ToOwned for RawPath {
 	}
 }
 
+#[derive(Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct RawString(Vec<u8>);
 impl Deref for RawString {
 	type Target = RawStr;
@@ -254,6 +369,7 @@ impl Deref for RawString {
 		RawStr::new(&self.0)
 	}
 }
+#[derive(Clone)]
 pub struct SerialNumber(RawString);
 impl Debug for SerialNumber {
 	fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
@@ -270,33 +386,12 @@ impl<T: Debug + Display> Display for AnError<T> {
 }
 impl<T: Debug + Display> std::error::Error for AnError<T> {}
 
+pub fn devices() -> Result<Vec<DeviceInfo>, Error> {
+	AdbClient::default().devices()
+}
+
 pub fn get_serialno() -> Result<SerialNumber, Error> {
-	Ok(SerialNumber(
-		single_line(RawStr::new(&scrape_adb(
-			[RawStr::new("get-serialno")].iter().copied(),
-		)?))?
-		.to_owned(),
-	))
-}
-
-fn single_line(str: &RawStr) -> Result<&RawStr, Error> {
-	let mut lines = str.lines();
-	let line = lines
-		.next()
-		.ok_or_else(|| Error::new(ErrorKind::UnexpectedEof, AnError("No line found")))?;
-	if lines.next().is_some() {
-		return Err(Error::new(
-			ErrorKind::InvalidData,
-			AnError("Unexpected extra line found"),
-		));
-	}
-	if line.is_empty() {
-		return Err(Error::new(
-			ErrorKind::InvalidData,
-			"No serial number found in output",
-		));
-	}
-	Ok(line)
+	AdbClient::default().get_serialno()
 }
 
 impl ToOwned for RawStr {
@@ -314,10 +409,16 @@ impl Borrow<RawStr> for RawString {
 }
 
 pub fn ls(
-	serial_number: &SerialNumber,
+	device: &Device,
 	path: &(impl AsRef<RawPath> + ?Sized),
 ) -> Result<impl Iterator<Item = LsEntry>, Error> {
-	ls_impl(serial_number, path.as_ref())
+	ls_impl(device, path.as_ref())
+}
+
+impl From<&str> for SerialNumber {
+	fn from(str: &str) -> Self {
+		Self(str.into())
+	}
 }
 
 impl Deref for SerialNumber {
@@ -328,83 +429,19 @@ impl Deref for SerialNumber {
 	}
 }
 
-pub fn ls_impl(
-	serial_number: &SerialNumber,
-	path: &RawPath,
-) -> Result<impl Iterator<Item = LsEntry>, Error> {
-	#![allow(clippy::items_after_statements)]
-
-	let ls_out = RawString(scrape_adb(
-		[RawStr::new("-s"), serial_number, RawStr::new("ls"), path]
-			.iter()
-			.copied(),
-	)?);
-
-	let mut lines = Vec::new();
-	fn contains_another_hex_field(line: &mut &RawStr) -> bool {
-		line.split_take(b' ').map(|slice| {
-			slice.len() == 8
-				&& slice
-					.iter()
-					.all(|b| (b'0'..=b'9').contains(b) || (b'a'..=b'f').contains(b))
-		}) == Some(true)
-	}
-	for line in ls_out.lines() {
-		let mut l = line;
-		if contains_another_hex_field(&mut l)
-			&& contains_another_hex_field(&mut l)
-			&& contains_another_hex_field(&mut l)
-		{
-			// This is *probably* the start of a new line.
-			lines.push(line.to_owned())
-		} else if let Some(previous) = lines.last_mut() {
-			previous.0.push(b'\n');
-			*previous += line;
-		} else {
-			return Err(Error::new(
-				ErrorKind::InvalidData,
-				AnError(format!(
-					"Could not make sense of `adb ls` output for path {:?}",
-					path
-				)),
-			));
-		}
-	}
-
-	let entries = lines
-		.into_iter()
-		.map(|l| {
-			let mut l = l.as_str();
-			Result::<_, Error>::Ok(LsEntry {
-				mode: UnixMode(
-					l.split_take(b' ')
-						.ok_or_else(|| {
-							Error::new(ErrorKind::InvalidData, AnError("No mode found"))
-						})?
-						.try_into()?,
-				),
-				size: l
-					.split_take(b' ')
-					.ok_or_else(|| Error::new(ErrorKind::InvalidData, AnError("No mode found")))?
-					.try_into()?,
-				epoch: Epoch(
-					l.split_take(b' ')
-						.ok_or_else(|| {
-							Error::new(ErrorKind::InvalidData, AnError("No mode found"))
-						})?
-						.try_into()?,
-				),
-				name: l.to_owned(),
-			})
-		})
-		.collect::<Result<Vec<_>, _>>()?;
-
-	Ok(entries.into_iter())
+pub fn ls_impl(device: &Device, path: &RawPath) -> Result<impl Iterator<Item = LsEntry>, Error> {
+	device.ls(path)
 }
 
 impl AddAssign<&RawStr> for RawString {
 	fn add_assign(&mut self, rhs: &RawStr) {
-		self.0.extend(rhs.iter())
+		self.0.extend(rhs.iter());
+	}
+}
+
+impl AddAssign<&RawStr> for RawPathBuf {
+	fn add_assign(&mut self, rhs: &RawStr) {
+		self.0 += rhs;
 	}
 }
 
@@ -412,19 +449,12 @@ impl TryFrom<&RawStr> for u32 {
 	type Error = Error;
 
 	fn try_from(value: &RawStr) -> Result<Self, Self::Error> {
-		let str =
-			std::str::from_utf8(value).map_err(|err| Error::new(ErrorKind::InvalidData, err))?;
-		Ok(u32::from_be_bytes(
-			hex::decode(str)
-				.map_err(|err| Error::new(ErrorKind::InvalidData, err))?
-				.try_into()
-				.map_err(|err| {
-					Error::new(
-						ErrorKind::InvalidData,
-						AnError(format!("Wrong number of bytes in hex u32: {:?}", err)),
-					)
-				})?,
-		))
+		let invalid = || error::AdbError::Parse {
+			what: "hex u32",
+			raw: value.to_owned(),
+		};
+		let bytes = hex::decode(&value.0).map_err(|_| invalid())?;
+		Ok(u32::from_be_bytes(bytes.try_into().map_err(|_| invalid())?))
 	}
 }
 
@@ -436,43 +466,13 @@ impl Deref for RawStr {
 	}
 }
 
-fn scrape_adb<'a>(args: impl IntoIterator<Item = &'a RawStr>) -> Result<Vec<u8>, Error> {
-	let args = args
-		.into_iter()
-		.map(OsString::try_from)
-		.collect::<Result<Vec<OsString>, _>>()?;
-	let output = Command::new("adb").args(args.iter()).output()?;
-	if output.status.success() {
-		Ok(output.stdout)
-	} else {
-		Err(Error::new(ErrorKind::Other, ExitError(output)))
-	}
-}
-
-pub struct ExitError(Output);
-impl std::error::Error for ExitError {}
-impl Debug for ExitError {
-	fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-		write!(f, "ExitError ")?;
-		f.debug_struct(type_name::<Self>())
-			.field("status", &self.0.status)
-			.field("stdout", &RawStr::new(&self.0.stdout).as_dbg())
-			.field("stderr", &RawStr::new(&self.0.stderr).as_dbg())
-			.finish()
-	}
-}
-impl Display for ExitError {
-	fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-		Debug::fmt(self, f)
-	}
-}
-
+#[derive(Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
 #[repr(transparent)]
 pub struct RawPathBuf(RawString);
 
 impl Debug for RawPathBuf {
 	fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-		<RawPath as Debug>::fmt(&self, f)
+		<RawPath as Debug>::fmt(self, f)
 	}
 }
 
@@ -496,7 +496,19 @@ impl From<&str> for RawString {
 	}
 }
 
-#[derive(Debug)]
+impl From<Vec<u8>> for RawString {
+	fn from(data: Vec<u8>) -> Self {
+		Self(data)
+	}
+}
+
+impl From<Vec<u8>> for RawPathBuf {
+	fn from(data: Vec<u8>) -> Self {
+		Self(data.into())
+	}
+}
+
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
 #[repr(transparent)]
 pub struct RawPath(RawStr);
 impl RawPath {
@@ -533,6 +545,7 @@ impl AsRef<RawPath> for str {
 	}
 }
 
+#[derive(PartialEq, Eq, PartialOrd, Ord, Hash)]
 #[repr(transparent)]
 pub struct RawStr([u8]);
 impl RawStr {
@@ -562,21 +575,22 @@ impl Debug for RawStr {
 	}
 }
 
-struct Dbg<'a, T: Debug>(&'a T);
-impl<'a, T: Debug> Debug for Dbg<'a, T> {
-	fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-		self.0.fmt(f)
-	}
-}
-
 impl AsRef<RawStr> for RawStr {
 	fn as_ref(&self) -> &RawStr {
 		self
 	}
 }
+/// Never fails on Unix, where [`OsStr`] holds arbitrary bytes.
 impl TryFrom<&RawStr> for OsString {
 	type Error = Error;
 
+	#[cfg(unix)]
+	fn try_from(value: &RawStr) -> Result<Self, Self::Error> {
+		use std::os::unix::ffi::OsStrExt;
+		Ok(OsStr::from_bytes(&value.0).to_owned())
+	}
+
+	#[cfg(not(unix))]
 	fn try_from(value: &RawStr) -> Result<Self, Self::Error> {
 		match std::str::from_utf8(&value.0) {
 			Ok(str) => Ok(str.into()),
@@ -585,25 +599,57 @@ impl TryFrom<&RawStr> for OsString {
 	}
 }
 
+/// Never fails on Unix, where [`OsStr`] holds arbitrary bytes.
+impl TryFrom<&OsStr> for RawPathBuf {
+	type Error = Error;
+
+	#[cfg(unix)]
+	fn try_from(value: &OsStr) -> Result<Self, Self::Error> {
+		use std::os::unix::ffi::OsStrExt;
+		Ok(value.as_bytes().to_vec().into())
+	}
+
+	#[cfg(not(unix))]
+	fn try_from(value: &OsStr) -> Result<Self, Self::Error> {
+		match value.to_str() {
+			Some(str) => Ok(str.into()),
+			None => Err(Error::new(
+				ErrorKind::InvalidData,
+				AnError(format!("Path is not valid Unicode: {:?}", value)),
+			)),
+		}
+	}
+}
+
 impl RawStr {
+	/// Splits command output into lines, without their endings.
+	///
+	/// Lines end at `\n`, dropping any `\r`s right before it, so this handles LF (Linux and macOS hosts, `exec:`),
+	/// CRLF (Windows hosts, `shell:` PTYs) and the `\r\r\n` older devices' PTYs turn CRLF into.
+	/// A last line without an ending is included, an empty one after the last ending isn't.
 	pub fn lines(&self) -> impl Iterator<Item = &'_ Self> {
-		let mut prev = 0;
-		self.0
-			.iter()
-			.copied()
-			.enumerate()
-			.filter_map(move |(i, b)| {
-				if b == b'\r' && self.0[i + 1] == b'\n' {
-					let start = prev;
-					prev = i + 2;
-					Some(&self[start..i])
-				} else {
-					None
+		let mut rest = &self.0;
+		std::iter::from_fn(move || {
+			if rest.is_empty() {
+				return None;
+			}
+			let line = match rest.iter().position(|b| *b == b'\n') {
+				Some(end) => {
+					let line = &rest[..end];
+					rest = &rest[end + 1..];
+					line
 				}
-			})
+				None => std::mem::take(&mut rest),
+			};
+			let end = line
+				.iter()
+				.rposition(|b| *b != b'\r')
+				.map_or(0, |last| last + 1);
+			Some(RawStr::new(&line[..end]))
+		})
 	}
 
-	pub fn split_take(self: &mut &Self, b: u8) -> Option<&RawStr> {
+	pub fn split_take<'a>(self: &mut &'a Self, b: u8) -> Option<&'a RawStr> {
 		match self.0.iter().position(|x| *x == b) {
 			Some(i) => {
 				let result = &self[..i];
@@ -654,61 +700,30 @@ impl Debug for RawString {
 }
 
 pub fn pull(
-	serial_number: &SerialNumber,
+	device: &Device,
 	path: &(impl AsRef<RawPath> + ?Sized),
-	expected_size: u32,
+	expected_size: u64,
 ) -> Result<Vec<u8>, Error> {
-	pull_impl(serial_number, path.as_ref(), expected_size)
+	pull_impl(device, path.as_ref(), expected_size)
 }
 
-pub fn pull_impl(
-	serial_number: &SerialNumber,
-	path: &RawPath,
-	expected_size: u32,
-) -> Result<Vec<u8>, Error> {
-	// let _it_works = scrape(
-	// 	"adb",
-	// 	[
-	// 		RawStr::new("-s"),
-	// 		serial_number,
-	// 		RawStr::new("shell"),
-	// 		RawStr::new("-n"),
-	// 		RawStr::new("-T"),
-	// 		RawStr::new("cat"),
-	// 		RawStr::new(
-	// 			shell_escape::escape(Cow::Owned(path.to_string_panicky()))
-	// 				.replace('\'', "\\'")
-	// 				.as_bytes(),
-	// 		),
-	// 	]
-	// 	.iter()
-	// 	.copied(),
-	// )?;
-
-	let file = scrape_adb(
-		[
-			RawStr::new("-s"),
-			serial_number,
-			RawStr::new("exec-out"),
-			RawStr::new("cat"),
-			// Must not be escaped!
-			path,
-		]
-		.iter()
-		.copied(),
-	)?;
-
-	if file.len() == usize::try_from(expected_size).unwrap() {
-		Ok(file)
-	} else {
-		Err(Error::new(
-			ErrorKind::InvalidData,
-			AnError(format!(
-				"Error pulling {:?}: Exprected {} bytes, got {}",
-				path,
-				expected_size,
-				file.len()
-			)),
-		))
-	}
+pub fn pull_impl(device: &Device, path: &RawPath, expected_size: u64) -> Result<Vec<u8>, Error> {
+	device.pull(path, expected_size)
+}
+
+pub fn pull_to(
+	device: &Device,
+	path: &(impl AsRef<RawPath> + ?Sized),
+	expected_size: u64,
+	target: impl Write,
+) -> Result<u64, Error> {
+	device.pull_to(path, expected_size, target)
+}
+
+pub fn pull_reader(
+	device: &Device,
+	path: &(impl AsRef<RawPath> + ?Sized),
+	expected_size: u64,
+) -> Result<impl Read, Error> {
+	device.pull_reader(path, expected_size)
 }
@@ -1,182 +1,851 @@
 #![warn(clippy::pedantic)]
+#![allow(clippy::uninlined_format_args)]
 
-use adb_dump::{LsEntry, ModeKind, RawPath, SerialNumber};
-use chrono::{Datelike, NaiveDateTime, Timelike};
+use adb_dump::{
+	access::RootStrategy,
+	archive::Format,
+	check,
+	client::AdbClient,
+	device::{Device, DeviceInfo, DeviceState},
+	dump::{self, sanitize_file_name, ErrorPolicy},
+	error::AdbError,
+	image,
+	progress::{Event, Progress, Stats},
+	recovery, restore,
+	rules::{self, Preset, Rule, Rules},
+	store, verify, RawPathBuf,
+};
 use std::{
 	convert::TryFrom,
-	fs::File,
-	io::{Error, Write},
+	ffi::{OsStr, OsString},
+	fmt::Write,
+	io::{Error, ErrorKind},
+	iter,
+	path::{Path, PathBuf},
+	process,
+	str::FromStr,
+	sync::{Arc, Mutex},
+	time::{Duration, Instant},
 };
-use zip::{write::FileOptions, CompressionMethod, DateTime, ZipWriter};
+use structopt::{clap::AppSettings, StructOpt};
+
+/// Dumps as much of a phone's data as is accessible via ADB into reasonably-sized archives.
+#[derive(Debug, StructOpt)]
+#[structopt(set_term_width = 80, setting = AppSettings::SubcommandsNegateReqs)]
+#[allow(clippy::struct_excessive_bools)]
+struct Options {
+	/// Paths on the device to dump.
+	#[structopt(required_unless = "list-devices", parse(from_os_str))]
+	paths: Vec<OsString>,
+
+	/// The serial number of a device to dump. Can be given multiple times.
+	/// Only required if more than one device is attached.
+	#[structopt(short, long, number_of_values = 1, global = true)]
+	serial: Vec<String>,
+
+	/// Dump every attached device in `device` or `recovery` state.
+	#[structopt(long, conflicts_with = "serial")]
+	all_devices: bool,
+
+	/// List attached devices and exit.
+	#[structopt(long)]
+	list_devices: bool,
+
+	/// The directory to write archives into.
+	/// When dumping more than one device, each gets a subdirectory named after its serial number.
+	#[structopt(short, long, default_value = ".", parse(from_os_str), global = true)]
+	output_dir: PathBuf,
+
+	/// Start a new archive volume before exceeding this many bytes of file contents.
+	/// Understands K, M, G, T (powers of 1000) and Ki, Mi, Gi, Ti (powers of 1024) suffixes.
+	#[structopt(long, default_value = "1G", parse(try_from_str = rules::parse_size))]
+	split_size: u64,
+
+	/// Write one archive set per subdirectory of each path, plus one named adb-dump_root for the files directly inside it.
+	#[structopt(long)]
+	split: bool,
+
+	/// Skip paths matching this rule, like `*.tmp`, `size>1G *.mp4` or `@com.example cache`.
+	/// See the README for the full syntax.
+	#[structopt(long, number_of_values = 1)]
+	exclude: Vec<String>,
+
+	/// Don't skip paths matching this rule, even if they are excluded.
+	/// Takes precedence over all other rules.
+	#[structopt(long, number_of_values = 1)]
+	include: Vec<String>,
+
+	/// Load rules from a file, one per line. A leading `!` marks include rules.
+	#[structopt(long, number_of_values = 1, parse(from_os_str))]
+	rules: Vec<PathBuf>,
+
+	/// Disable a built-in rule preset. Available: caches
+	#[structopt(long, number_of_values = 1)]
+	no_preset: Vec<Preset>,
+
+	/// The archive format to write.
+	/// TAR keeps names that aren't valid UTF-8 as well as device nodes and FIFOs, and can be compressed as a whole.
+	#[structopt(long, default_value = "zip", possible_values = Format::NAMES)]
+	format: Format,
+
+	/// Also list each file as it's dumped.
+	#[structopt(short, long, parse(from_occurrences), global = true)]
+	verbose: u8,
+
+	/// Only print errors.
+	#[structopt(short, long, conflicts_with = "verbose", global = true)]
+	quiet: bool,
+
+	/// Count the files to dump first, then show a status line with totals, throughput and ETA instead of listing directories.
+	#[structopt(long, conflicts_with = "verbose")]
+	progress: bool,
+
+	/// How to reach files the shell user can't read, unless adbd already runs as root like in TWRP:
+	/// try `adb root`, then `su` (auto), only one of them, or neither.
+	#[structopt(long, default_value = "auto", possible_values = RootStrategy::NAMES, global = true)]
+	root_access: RootStrategy,
+
+	/// What to do if a device in recovery hasn't mounted the partitions of the paths to dump, or hasn't decrypted /data:
+	/// refuse to dump it, warn and dump anyway, or not check at all.
+	#[structopt(long, default_value = "refuse", possible_values = &["refuse", "warn", "off"])]
+	recovery_check: RecoveryCheck,
+
+	/// In TWRP, mount the partitions of the paths to dump with `twrp mount` if they aren't yet.
+	/// Can't decrypt /data.
+	#[structopt(long)]
+	mount: bool,
 
-fn start_zip(zip_count: &mut usize) -> Result<ZipWriter<File>, Error> {
-	*zip_count += 1;
-	let file = std::fs::OpenOptions::new()
-		.create_new(true)
-		.write(true)
-		.open(&format!("backup.{}.zip", zip_count))?;
-	Ok(ZipWriter::new(file))
+	/// List what would be dumped, without pulling files or writing archives.
+	#[structopt(long)]
+	dry_run: bool,
+
+	/// Continue an interrupted dump, skipping everything its journal records as archived.
+	/// New archive volumes are numbered after the ones already in the output directory.
+	#[structopt(long, conflicts_with = "dry-run")]
+	resume: bool,
+
+	/// Only pull files that are new or changed since this earlier dump (an output directory or an extracted adb-dump.manifest.txt).
+	/// Can be given multiple times, oldest first, to layer incremental dumps on a full one.
+	/// Deleted files are listed in each archive volume's adb-dump.deleted.txt.
+	#[structopt(long, number_of_values = 1, parse(from_os_str))]
+	since: Vec<PathBuf>,
+
+	/// With --since, compare files by SHA-256 on the device instead of by modification time.
+	#[structopt(long, requires = "since")]
+	compare_checksums: bool,
+
+	/// Also record each entry's SELinux context, and its owner on devices too old to report it otherwise.
+	/// Runs `find` and `stat` on the device once per directory.
+	#[structopt(long)]
+	security: bool,
+
+	/// How many directories to list and small files to pull at once.
+	/// The archives are the same regardless.
+	#[structopt(short, long, default_value = "4")]
+	jobs: usize,
+
+	/// Treat the output directory as a content-addressed store and add this dump to it as a new snapshot, instead of writing archives.
+	/// File contents are only stored once, however often and wherever they occur. --format is ignored.
+	#[structopt(long)]
+	store: bool,
+
+	/// The name of the snapshot to write with --store.
+	/// Defaults to the current UTC time, or to the latest snapshot with --resume.
+	#[structopt(long, requires = "store")]
+	snapshot: Option<String>,
+
+	/// What to do when a file or directory can't be read: abort the dump, skip the entry, or retry it before skipping.
+	/// Skipped entries are listed in each archive volume's adb-dump.errors.txt and retried by --resume.
+	#[structopt(long, default_value = "skip", possible_values = &["abort", "skip", "retry"])]
+	on_error: OnError,
+
+	/// How often --on-error retry tries again.
+	#[structopt(long, default_value = "3")]
+	retries: u32,
+
+	/// Seconds to wait before the first retry. Doubles with each further attempt.
+	#[structopt(long, default_value = "1", parse(try_from_str = parse_seconds))]
+	retry_backoff: Duration,
+
+	#[structopt(subcommand)]
+	command: Option<Command>,
+}
+
+#[derive(Debug, StructOpt)]
+enum Command {
+	/// Compare the archives in the output directory against the device.
+	/// Prints one tab-separated line per missing, extra, size- or content-mismatched file.
+	Verify {
+		/// The device directory that archive entry paths are relative to.
+		/// Only needs to be changed for --split dumps of paths other than `/`.
+		#[structopt(long, default_value = "/", parse(from_os_str))]
+		root: OsString,
+	},
+	/// Write a snapshot of the store in the output directory back out as archive volumes, or as plain files.
+	Materialize {
+		/// The name of the snapshot, as listed in the store's snapshots directory.
+		snapshot: String,
+
+		/// The directory to write into.
+		#[structopt(parse(from_os_str))]
+		target: PathBuf,
+
+		/// Write archive volumes of this format instead of plain files.
+		#[structopt(long, possible_values = Format::NAMES)]
+		format: Option<Format>,
+	},
+	/// Push the archives in the output directory back onto the device, with their permissions and modification times.
+	/// Prints one tab-separated line per entry that already exists, can't be restored or failed.
+	Restore {
+		/// The device directory that archive entry paths are relative to.
+		/// Only needs to be changed for --split dumps of paths other than `/`.
+		#[structopt(long, default_value = "/", parse(from_os_str))]
+		root: OsString,
+
+		/// Restore device paths starting with FROM to TO instead, like `/sdcard=/storage/emulated/0`.
+		/// Can be given multiple times. The longest matching FROM applies.
+		#[structopt(long = "map", number_of_values = 1, parse(from_os_str))]
+		map: Vec<OsString>,
+
+		/// Only restore paths matching this pattern, or inside a directory matching it, in the syntax of --exclude.
+		/// Can be given multiple times.
+		#[structopt(long, number_of_values = 1)]
+		only: Vec<String>,
+
+		/// Replace files and symlinks that already exist on the device instead of skipping them.
+		#[structopt(long)]
+		overwrite: bool,
+
+		/// Also reapply the archived owners and SELinux contexts. Needs root on the device.
+		#[structopt(long)]
+		security: bool,
+
+		/// An incremental dump (see --since) to restore on top of the output directory's.
+		/// Files come from the newest dump that has them, and files it found deleted aren't restored.
+		/// Can be given multiple times, oldest first.
+		#[structopt(long = "increment", number_of_values = 1, parse(from_os_str))]
+		increments: Vec<PathBuf>,
+	},
+	/// Image raw partitions into <name>.img files in the output directory, for what a file-level dump can't restore, like persist, efs, modemst or nvdata.
+	/// The partition table is written to adb-dump.partitions.txt, with each image's size and SHA-256. Needs root on the device.
+	Image {
+		/// Names from /dev/block/by-name, like `persist`, or kernel names from /proc/partitions, like `mmcblk0p5`.
+		#[structopt(required_unless = "list")]
+		partitions: Vec<String>,
+
+		/// List the partitions with their block devices and sizes instead of imaging any.
+		#[structopt(long, conflicts_with = "partitions")]
+		list: bool,
+	},
+	/// Check the archives in the output directory against the checksum manifests embedded in them,
+	/// and partition images against the partition table next to them.
+	/// Needs no device. Prints one tab-separated line per damaged, missing or unlisted file or image.
+	Check,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum OnError {
+	Abort,
+	Skip,
+	Retry,
+}
+
+impl FromStr for OnError {
+	type Err = String;
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		match s {
+			"abort" => Ok(Self::Abort),
+			"skip" => Ok(Self::Skip),
+			"retry" => Ok(Self::Retry),
+			other => Err(format!("Unknown error policy: {}", other)),
+		}
+	}
+}
+
+#[derive(Debug, Clone, Copy)]
+enum RecoveryCheck {
+	Refuse,
+	Warn,
+	Off,
+}
+
+impl FromStr for RecoveryCheck {
+	type Err = String;
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		match s {
+			"refuse" => Ok(Self::Refuse),
+			"warn" => Ok(Self::Warn),
+			"off" => Ok(Self::Off),
+			other => Err(format!("Unknown recovery check: {}", other)),
+		}
+	}
+}
+
+impl Options {
+	fn verbosity(&self) -> i8 {
+		if self.quiet {
+			-1
+		} else {
+			i8::try_from(self.verbose).unwrap_or(i8::MAX)
+		}
+	}
+
+	fn error_policy(&self) -> ErrorPolicy {
+		match self.on_error {
+			OnError::Abort => ErrorPolicy::Abort,
+			OnError::Skip => ErrorPolicy::Skip,
+			OnError::Retry => ErrorPolicy::Retry {
+				attempts: self.retries,
+				backoff: self.retry_backoff,
+			},
+		}
+	}
 }
 
 fn main() -> Result<(), Error> {
-	let s_no = dbg!(adb_dump::get_serialno())?;
-
-	let arg_path: &RawPath = "/data".into();
-	let prefix = arg_path.directory().unwrap();
-
-	let mut zip_count = 0;
-	let mut cumulative_file_size = 0;
-	let mut zip = start_zip(&mut zip_count)?;
-
-	visit_dir(
-		&mut zip,
-		&mut zip_count,
-		&mut cumulative_file_size,
-		&s_no,
-		prefix,
-		arg_path,
-	)?;
+	let options = Options::from_args();
+	let rules = rules(&options)?;
+
+	if let Some(Command::Check) = &options.command {
+		return check(&options);
+	}
+
+	if options.list_devices {
+		for info in adb_dump::devices()? {
+			println!(
+				"{}\t{}\t{}",
+				String::from_utf8_lossy(&info.serial_number),
+				info.state,
+				info.model.as_deref().unwrap_or("")
+			);
+		}
+		return Ok(());
+	}
 
-	zip.finish()?;
+	if let Some(Command::Verify { root }) = &options.command {
+		return verify(&options, root);
+	}
+	if let Some(Command::Restore {
+		root,
+		map,
+		only,
+		overwrite,
+		security,
+		increments,
+	}) = &options.command
+	{
+		return restore(&options, root, map, only, *overwrite, *security, increments);
+	}
+	if let Some(Command::Image { partitions, list }) = &options.command {
+		return image(&options, partitions, *list);
+	}
+	if let Some(Command::Materialize {
+		snapshot,
+		target,
+		format,
+	}) = &options.command
+	{
+		return store::materialize(&store::MaterializeOptions {
+			store: options.output_dir.clone(),
+			snapshot: snapshot.clone(),
+			target: target.clone(),
+			format: *format,
+			verbosity: options.verbosity(),
+		});
+	}
+
+	let now = chrono::Utc::now().format("%Y-%m-%dT%H%M%SZ").to_string();
+
+	let devices = select_devices(&options)?;
+	let mut failures = Vec::new();
+	let mut failed_devices = Vec::new();
+	for device in &devices {
+		match dump_device(&options, &rules, device, devices.len() > 1, &now) {
+			Ok(summary) => failures.extend(summary.failures),
+			Err(error) if devices.len() == 1 => return Err(error),
+			// Carry on with the other devices, which needn't share the problem.
+			Err(error) => {
+				eprintln!(
+					"Dumping from {:?} failed: {}",
+					device.serial_number(),
+					error
+				);
+				failed_devices.push(device.serial_number());
+			}
+		}
+	}
 
+	if !failures.is_empty() {
+		eprintln!("{} entries could not be dumped:", failures.len());
+		for failure in &failures {
+			eprintln!("\t{}", failure);
+		}
+	}
+	if !failed_devices.is_empty() {
+		eprintln!(
+			"{} of {} devices could not be dumped: {:?}",
+			failed_devices.len(),
+			devices.len(),
+			failed_devices
+		);
+	}
+	if !failures.is_empty() || !failed_devices.is_empty() {
+		process::exit(1);
+	}
 	Ok(())
 }
 
-fn convert_date_time(date_time: &NaiveDateTime) -> DateTime {
-	let time = date_time.time();
-	DateTime::from_date_and_time(
-		u16::try_from(date_time.year()).unwrap(),
-		u8::try_from(date_time.month()).unwrap(),
-		u8::try_from(date_time.day()).unwrap(),
-		u8::try_from(time.hour()).unwrap(),
-		u8::try_from(time.minute()).unwrap(),
-		u8::try_from(time.second()).unwrap(),
-	)
-	.unwrap_or_else(|()| {
-		let default = DateTime::default();
-		eprintln! {"Ignoring date: {:?}, using {:?} instead", date_time,default};
-		default
-	})
-}
-
-fn visit_dir(
-	zip: &mut ZipWriter<File>,
-	zip_count: &mut usize,
-	cumulative_file_size: &mut usize,
-	serial_number: &SerialNumber,
-	archive_root: &RawPath,
-	path: &RawPath,
-) -> Result<(), Error> {
-	println!("dir {:?}", &path);
-
-	let ignore = &[
-		"/BrowserMetrics", // LineageOS used to generate a very large (unbounded) amount of these files via web embed, and they're quite large too.
-		"/HTTP Cache",
-		"/com.google.android.googlequicksearchbox", // This caches A LOT of data and you probably don't want to keep it.
-		"/.com.google.firebase.crashlytics-ndk",    // Twitter creates a huge amount of crash reports :(
-		"/org.mozilla.firefox/cache",               // It's just a ton of pretty useless files.
-		"/com.crashlytics.sdk.android.crashlytics-core", // Notify crashes a lot.
-		"/lib/python2.7",                           // Ren'Py uses this.
-		"/cache/image_manager_disk_cache", // Various programs use this, so while it's usually not huge, there might be a lot cumulatively.
-		"/cache:memrise.offline.assets",   // Many files, and quite possibly large.
-		"/org.mozilla.firefox_beta/cache",
-		"/com.ecosia.android/cache",            // Another browser cache.
-		"/com.duckduckgo.mobile.android/cache", // Quack.
-		"/org.mozilla.fenix/cache",             // Firefox Nightly
-		"/com.google.android.apps.photos/cache/glide_cache", // Google Photos thumbnail cache, probably. It can take ages to back this up.
-		"/com.twitter.android/cache",                        // Twitter cache
-		"/org.telegram.messenger/cache",                     // Telegram cache
-		"/com.google.android.inputmethod.latin/cache",       // GBoard cache, probably
-		"/com.discord/cache",                                // Discord cache
-		"/de.nebenan.app/cache",                             // Nebenan.de cache
-		"/data/crdroid_updates", // Huge system image files that you probably don't need.
-	];
-	for ignore in ignore {
-		if path.to_string_panicky().ends_with(ignore) {
-			zip.start_file(
-				path.without_prefix(archive_root)
-					.join("IGNORED")
-					.to_string_panicky(),
-				FileOptions::default(),
-			)?;
-			eprintln!("IGNORED");
-			return Ok(());
+/// Prepares `device` and dumps it into the output directory, or into a subdirectory named after it with `per_device`.
+fn dump_device(
+	options: &Options,
+	rules: &Rules,
+	device: &Device,
+	per_device: bool,
+	now: &str,
+) -> Result<dump::Summary, Error> {
+	let output_dir = if per_device {
+		let output_dir = options
+			.output_dir
+			.join(sanitize_file_name(device.serial_number()));
+		std::fs::create_dir_all(&output_dir)?;
+		output_dir
+	} else {
+		options.output_dir.clone()
+	};
+	let output_dir = if options.store {
+		snapshot_dir(options, &output_dir, now)?
+	} else {
+		output_dir
+	};
+	check_recovery(options, device)?;
+	gain_root(options, device)?;
+	if options.verbosity() >= 0 {
+		eprintln!("Dumping from {:?}", device.serial_number());
+	}
+	dump(options, rules, device, output_dir)
+}
+
+/// Dumps from `device` into `output_dir`, showing a status line with --progress.
+fn dump(
+	options: &Options,
+	rules: &Rules,
+	device: &Device,
+	output_dir: PathBuf,
+) -> Result<dump::Summary, Error> {
+	let status = options
+		.progress
+		.then(|| Arc::new(Mutex::new(StatusLine::default())));
+	let summary = dump::run(
+		device,
+		rules,
+		&dump::Options {
+			paths: options
+				.paths
+				.iter()
+				.map(|path| RawPathBuf::try_from(path.as_os_str()))
+				.collect::<Result<_, _>>()?,
+			output_dir,
+			split_size: options.split_size,
+			split: options.split,
+			format: if options.store {
+				Format::Tree
+			} else {
+				options.format
+			},
+			verbosity: if options.progress {
+				-1
+			} else {
+				options.verbosity()
+			},
+			dry_run: options.dry_run,
+			resume: options.resume,
+			error_policy: options.error_policy(),
+			since: options.since.clone(),
+			compare_checksums: options.compare_checksums,
+			jobs: options.jobs,
+			scan: options.progress,
+			security: options.security,
+			progress: status
+				.clone()
+				.map(|status| Progress::new(move |event| status.lock().unwrap().update(event))),
+		},
+	);
+	if let Some(status) = status {
+		status.lock().unwrap().finish();
+	}
+	summary
+}
+
+/// Renders dump progress as one line on stderr, redrawn at most ten times a second.
+#[derive(Default)]
+struct StatusLine {
+	stats: Stats,
+	/// When the dump proper (after the pre-scan) started.
+	started: Option<Instant>,
+	drawn: Option<Instant>,
+}
+
+impl StatusLine {
+	fn update(&mut self, event: &Event) {
+		self.stats.update(event);
+		let now = Instant::now();
+		let started = *self.started.get_or_insert(now);
+		if let Event::Scanned { .. } = event {
+			self.started = Some(now);
+		} else if self
+			.drawn
+			.map_or(false, |drawn| now - drawn < Duration::from_millis(100))
+			|| now == started
+		{
+			return;
 		}
+		self.drawn = Some(now);
+		self.draw();
 	}
 
-	for entry in adb_dump::ls(serial_number, path)? {
-		match entry.mode.kind() {
-			dir if dir == ModeKind::Dir => {
-				if entry.name != "." && entry.name != ".." {
-					let timestamp = convert_date_time(&entry.epoch.to_date_time());
-					zip.add_directory(
-						path.without_prefix(archive_root)
-							.join(entry.name.as_str())
-							.to_string_panicky(),
-						FileOptions::default()
-							.compression_method(CompressionMethod::Stored)
-							.last_modified_time(timestamp)
-							.unix_permissions(entry.mode.permissions()),
-					)?;
-					//TODO: Add dir!
-					visit_dir(
-						zip,
-						zip_count,
-						cumulative_file_size,
-						serial_number,
-						archive_root,
-						&path.join(entry.name.as_str()),
-					)?
-				}
-			}
-			file if file == ModeKind::File => visit_file(
-				zip,
-				zip_count,
-				cumulative_file_size,
-				serial_number,
-				archive_root,
-				&path.join(entry.name.as_str()),
-				&entry,
-			)?,
-			// other => todo!("{:?}", other),
-			other => {
-				eprintln!("{:?}", (other, entry.name));
+	#[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+	fn draw(&self) {
+		let stats = &self.stats;
+		let elapsed = self
+			.started
+			.map_or(Duration::ZERO, |started| started.elapsed());
+		let mut line = match (stats.total_files, stats.total_bytes, stats.fraction()) {
+			(Some(files), Some(bytes), Some(fraction)) => format!(
+				"{}/{} files, {}/{} ({:.0}%)",
+				stats.files,
+				files,
+				format_size(stats.bytes),
+				format_size(bytes),
+				fraction * 100.0
+			),
+			_ => format!("{} files, {}", stats.files, format_size(stats.bytes)),
+		};
+		write!(
+			line,
+			", {:.1} files/s, {}/s",
+			stats.files_per_second(elapsed),
+			format_size(stats.bytes_per_second(elapsed) as u64)
+		)
+		.unwrap();
+		if let Some(eta) = stats.eta(elapsed) {
+			let secs = eta.as_secs();
+			write!(
+				line,
+				", ETA {}:{:02}:{:02}",
+				secs / 3600,
+				secs / 60 % 60,
+				secs % 60
+			)
+			.unwrap();
+		}
+		if let Some(current) = &stats.current {
+			let current = String::from_utf8_lossy(current);
+			let chars = current.chars().count();
+			line += ", ";
+			if chars > 40 {
+				line.push('…');
+				line.extend(current.chars().skip(chars - 39));
+			} else {
+				line += &current;
 			}
 		}
+		eprint!("\r\x1b[K{}", line);
+	}
+
+	/// Draws the final state and ends the line.
+	fn finish(&self) {
+		self.draw();
+		eprintln!();
+	}
+}
+
+/// Formats `bytes` with a power-of-1000 unit, like --split-size accepts.
+#[allow(clippy::cast_precision_loss)]
+fn format_size(bytes: u64) -> String {
+	let mut size = bytes as f64;
+	for unit in ["B", "kB", "MB", "GB"] {
+		if size < 1000.0 {
+			return format!("{:.1} {}", size, unit);
+		}
+		size /= 1000.0;
 	}
+	format!("{:.1} TB", size)
+}
 
+fn verify(options: &Options, root: &OsStr) -> Result<(), Error> {
+	let devices = select_devices(options)?;
+	if devices.len() != 1 {
+		return Err(Error::new(
+			ErrorKind::InvalidInput,
+			"Can only verify one device at a time, pick with --serial",
+		));
+	}
+	gain_root(options, &devices[0])?;
+	let report = verify::run(
+		&devices[0],
+		&verify::Options {
+			output_dir: options.output_dir.clone(),
+			root: RawPathBuf::try_from(root)?,
+			verbosity: options.verbosity(),
+		},
+	)?;
+	for finding in &report.findings {
+		println!("{}", finding);
+	}
+	if options.verbosity() >= 0 {
+		eprintln!(
+			"Checked {} files, found {} problems",
+			report.checked,
+			report.findings.len()
+		);
+	}
+	if !report.is_ok() {
+		process::exit(1);
+	}
 	Ok(())
 }
 
-fn visit_file(
-	zip: &mut ZipWriter<File>,
-	zip_count: &mut usize,
-	cumulative_file_size: &mut usize,
-	serial_number: &SerialNumber,
-	archive_root: &RawPath,
-	path: &RawPath,
-	entry: &LsEntry,
+fn restore(
+	options: &Options,
+	root: &OsStr,
+	map: &[OsString],
+	only: &[String],
+	overwrite: bool,
+	security: bool,
+	increments: &[PathBuf],
 ) -> Result<(), Error> {
-	println!("file {:?}", &path);
+	let devices = select_devices(options)?;
+	if devices.len() != 1 {
+		return Err(Error::new(
+			ErrorKind::InvalidInput,
+			"Can only restore to one device at a time, pick with --serial",
+		));
+	}
+	gain_root(options, &devices[0])?;
+	let report = restore::run(
+		&devices[0],
+		&restore::Options {
+			output_dirs: iter::once(&options.output_dir)
+				.chain(increments)
+				.cloned()
+				.collect(),
+			root: RawPathBuf::try_from(root)?,
+			remap: map
+				.iter()
+				.map(|mapping| parse_mapping(mapping))
+				.collect::<Result<_, _>>()?,
+			only: only
+				.iter()
+				.map(|pattern| Rule::parse(pattern, "--only"))
+				.collect::<Result<_, _>>()?,
+			overwrite,
+			security,
+			verbosity: options.verbosity(),
+		},
+	)?;
+	for finding in &report.findings {
+		println!("{}", finding);
+	}
+	if options.verbosity() >= 0 {
+		eprintln!(
+			"Restored {} files, found {} problems",
+			report.restored,
+			report.findings.len()
+		);
+	}
+	if !report.is_ok() {
+		process::exit(1);
+	}
+	Ok(())
+}
 
-	let file = adb_dump::pull(serial_number, path, entry.size)?;
+fn image(options: &Options, partitions: &[String], list: bool) -> Result<(), Error> {
+	let devices = select_devices(options)?;
+	if devices.len() != 1 {
+		return Err(Error::new(
+			ErrorKind::InvalidInput,
+			"Can only image one device at a time, pick with --serial",
+		));
+	}
+	gain_root(options, &devices[0])?;
+	if list {
+		for partition in image::partitions(&devices[0])? {
+			println!("{}", partition);
+		}
+		return Ok(());
+	}
+	let images = image::run(
+		&devices[0],
+		&image::Options {
+			output_dir: options.output_dir.clone(),
+			partitions: partitions.to_vec(),
+			verbosity: options.verbosity(),
+		},
+	)?;
+	if options.verbosity() >= 0 {
+		eprintln!("Imaged {} partitions", images.len());
+	}
+	Ok(())
+}
+
+/// Parses a non-negative, finite number of seconds such as `0.5`.
+fn parse_seconds(seconds: &str) -> Result<Duration, String> {
+	let value = seconds.parse::<f64>().map_err(|error| error.to_string())?;
+	Duration::try_from_secs_f64(value)
+		.map_err(|_| format!("Expected a non-negative number of seconds, got {}", seconds))
+}
 
-	*cumulative_file_size += file.len();
-	if *cumulative_file_size > 1_000_000_000 {
-		*cumulative_file_size = file.len();
+/// Splits a --map argument at its first `=`.
+fn parse_mapping(mapping: &OsStr) -> Result<(RawPathBuf, RawPathBuf), Error> {
+	let mapping = RawPathBuf::try_from(mapping)?;
+	let Some(i) = mapping.iter().position(|b| *b == b'=') else {
+		return Err(Error::new(
+			ErrorKind::InvalidInput,
+			format!("Expected FROM=TO, got {:?}", mapping),
+		));
+	};
+	Ok((mapping[..i].to_owned(), mapping[i + 1..].to_owned()))
+}
 
-		zip.finish()?;
-		*zip = start_zip(zip_count)?;
+fn rules(options: &Options) -> Result<Rules, Error> {
+	let mut rules = Rules::new();
+	for preset in &[Preset::Caches] {
+		if !options.no_preset.contains(preset) {
+			rules.extend(preset.rules());
+		}
+	}
+	for path in &options.rules {
+		rules.extend(Rules::load(path)?);
+	}
+	for exclude in &options.exclude {
+		rules.push(Rule::parse(exclude, "--exclude")?);
 	}
+	for include in &options.include {
+		rules.push(Rule::parse(&format!("!{}", include), "--include")?);
+	}
+	Ok(rules)
+}
 
-	zip.start_file(
-		path.without_prefix(archive_root).to_string_panicky(),
-		FileOptions::default()
-			.compression_method(CompressionMethod::STORE)
-			.last_modified_time(convert_date_time(&entry.epoch.to_date_time()))
-			.unix_permissions(entry.mode.permissions()),
-	)?;
-	zip.write_all(&file)?;
-	zip.flush()?;
+/// Picks the snapshot to dump into with --store, and creates its directory.
+fn snapshot_dir(options: &Options, store: &Path, now: &str) -> Result<PathBuf, Error> {
+	let latest = if options.resume {
+		store::snapshots(store)?.pop()
+	} else {
+		None
+	};
+	let snapshot = options
+		.snapshot
+		.clone()
+		.or(latest)
+		.unwrap_or_else(|| now.to_string());
+	let dir = store::snapshot_dir(store, &snapshot);
+	std::fs::create_dir_all(&dir)?;
+	Ok(dir)
+}
+
+fn check(options: &Options) -> Result<(), Error> {
+	let report = check::run(&check::Options {
+		output_dir: options.output_dir.clone(),
+		verbosity: options.verbosity(),
+	})?;
+	for finding in &report.findings {
+		println!("{}", finding);
+	}
+	if options.verbosity() >= 0 {
+		eprintln!(
+			"Checked {} files in {} volumes and {} images, found {} problems",
+			report.checked,
+			report.volumes,
+			report.images,
+			report.findings.len()
+		);
+	}
+	if !report.is_ok() {
+		process::exit(1);
+	}
 	Ok(())
 }
+
+/// Checks that `device`, if in recovery, has the partitions to dump mounted and decrypted, mounting them first with --mount.
+fn check_recovery(options: &Options, device: &Device) -> Result<(), Error> {
+	if let RecoveryCheck::Off = options.recovery_check {
+		return Ok(());
+	}
+	let paths = options
+		.paths
+		.iter()
+		.map(|path| RawPathBuf::try_from(path.as_os_str()))
+		.collect::<Result<Vec<_>, _>>()?;
+	let mut status = recovery::Status::probe(device)?;
+	let unmounted = status.unmounted(&paths);
+	if options.mount && status.is_twrp() && !unmounted.is_empty() {
+		if options.verbosity() >= 0 {
+			eprintln!("Mounting {}", unmounted.join(", "));
+		}
+		recovery::mount(device, &unmounted)?;
+		status = recovery::Status::probe(device)?;
+	}
+	let problems = status.problems(&paths);
+	for problem in &problems {
+		eprintln!("{:?}: {}", device.serial_number(), problem);
+	}
+	match (problems.is_empty(), options.recovery_check) {
+		(false, RecoveryCheck::Refuse) => Err(Error::new(
+			ErrorKind::Other,
+			"Refusing to dump. Mount and decrypt in recovery first, try --mount, or pass --recovery-check warn",
+		)),
+		_ => Ok(()),
+	}
+}
+
+/// Gains root on `device` as far as --root-access allows, and says how.
+fn gain_root(options: &Options, device: &Device) -> Result<(), Error> {
+	let access = device.gain_root(options.root_access)?;
+	if options.verbosity() >= 0 {
+		eprintln!("Accessing {:?} as {}", device.serial_number(), access);
+	}
+	Ok(())
+}
+
+fn select_devices(options: &Options) -> Result<Vec<Device>, Error> {
+	let client = AdbClient::default();
+	if !options.serial.is_empty() {
+		return Ok(options
+			.serial
+			.iter()
+			.map(|serial| client.device(serial.as_str().into()))
+			.collect());
+	}
+
+	let (usable, unusable): (Vec<_>, Vec<_>) = client
+		.devices()?
+		.into_iter()
+		.partition(|info| info.state.is_usable());
+	for info in &unusable {
+		eprintln!("Skipping {:?}: {}", info.serial_number, info.state);
+	}
+	if usable.is_empty() {
+		let any = |state| unusable.iter().any(|info| info.state == state);
+		return Err(if any(DeviceState::Unauthorized) {
+			AdbError::Unauthorized
+		} else if any(DeviceState::Offline) {
+			AdbError::Offline
+		} else {
+			AdbError::NoDevice
+		}
+		.into());
+	}
+	if usable.len() > 1 && !options.all_devices {
+		eprintln!(
+			"Pick one of {:?} with --serial, or use --all-devices",
+			usable
+				.iter()
+				.map(|info| &info.serial_number)
+				.collect::<Vec<_>>()
+		);
+		return Err(AdbError::MultipleDevices.into());
+	}
+	Ok(usable.iter().map(DeviceInfo::handle).collect())
+}
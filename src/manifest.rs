@@ -0,0 +1,169 @@
+//! Reading back the checksum manifests and deletion lists a dump leaves in its volumes.
+//!
+//! See [`MANIFEST_FILE_NAME`] and [`DELETED_FILE_NAME`] for the formats.
+
+use crate::{
+	archive,
+	dump::{DELETED_FILE_NAME, MANIFEST_FILE_NAME},
+	AnError, RawPath, RawPathBuf,
+};
+use std::{
+	collections::BTreeMap,
+	fmt::{self, Display, Formatter},
+	fs,
+	io::{Error, ErrorKind},
+	path::Path,
+};
+
+/// One line of a checksum manifest, describing a regular file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Entry {
+	/// Relative to the archive root.
+	pub path: RawPathBuf,
+	pub size: u64,
+	pub permissions: u32,
+	pub mtime: i64,
+	pub sha256: Vec<u8>,
+	/// The number of the volume the file was written to.
+	pub volume: usize,
+}
+
+impl Display for Entry {
+	fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+		write!(
+			f,
+			"{}\t{}\t{:o}\t{}\t{}\t{}",
+			hex::encode(&***self.path),
+			self.size,
+			self.permissions,
+			self.mtime,
+			hex::encode(&self.sha256),
+			self.volume,
+		)
+	}
+}
+
+impl Entry {
+	/// Parses one line as written by [`Display`].
+	pub fn parse(line: &str) -> Result<Self, Error> {
+		let invalid = || {
+			Error::new(
+				ErrorKind::InvalidData,
+				AnError(format!(
+					"Invalid line in {}: {:?}",
+					MANIFEST_FILE_NAME, line
+				)),
+			)
+		};
+		let fields: Vec<_> = line.split('\t').collect();
+		let [path, size, permissions, mtime, sha256, volume] = fields[..] else {
+			return Err(invalid());
+		};
+		Ok(Self {
+			path: hex::decode(path).map_err(|_| invalid())?.into(),
+			size: size.parse().map_err(|_| invalid())?,
+			permissions: u32::from_str_radix(permissions, 8).map_err(|_| invalid())?,
+			mtime: mtime.parse().map_err(|_| invalid())?,
+			sha256: hex::decode(sha256).map_err(|_| invalid())?,
+			volume: volume.parse().map_err(|_| invalid())?,
+		})
+	}
+}
+
+/// Parses the contents of a [`MANIFEST_FILE_NAME`].
+pub fn parse(text: &[u8]) -> Result<Vec<Entry>, Error> {
+	String::from_utf8_lossy(text)
+		.lines()
+		.map(Entry::parse)
+		.collect()
+}
+
+/// Parses the contents of a [`DELETED_FILE_NAME`].
+pub fn parse_deleted(text: &[u8]) -> Result<Vec<RawPathBuf>, Error> {
+	String::from_utf8_lossy(text)
+		.lines()
+		.map(|line| {
+			hex::decode(line).map(Into::into).map_err(|_| {
+				Error::new(
+					ErrorKind::InvalidData,
+					AnError(format!("Invalid line in {}: {:?}", DELETED_FILE_NAME, line)),
+				)
+			})
+		})
+		.collect()
+}
+
+/// The regular files of one or more earlier dumps, layered on top of each other.
+#[derive(Debug, Clone, Default)]
+pub struct Baseline {
+	files: BTreeMap<RawPathBuf, Entry>,
+}
+
+impl Baseline {
+	/// Loads the dumps at `paths`, oldest first.
+	///
+	/// Each path is either an output directory, whose volumes are read in order, or a bare manifest file.
+	/// Volumes that can't be read completely, like the last one of an interrupted dump, are skipped with a warning.
+	pub fn load(paths: &[impl AsRef<Path>]) -> Result<Self, Error> {
+		let mut baseline = Self::default();
+		for path in paths {
+			let path = path.as_ref();
+			if !path.is_dir() {
+				baseline.add(parse(&fs::read(path)?)?);
+				continue;
+			}
+			for volume in archive::volumes(path)? {
+				let mut manifest = Vec::new();
+				let mut deleted = Vec::new();
+				let result = archive::read(&volume.path, |path, _, data| {
+					if **path == MANIFEST_FILE_NAME {
+						manifest = parse(&read_to_end(data)?)?;
+					} else if **path == DELETED_FILE_NAME {
+						deleted = parse_deleted(&read_to_end(data)?)?;
+					}
+					Ok(())
+				});
+				if let Err(error) = result {
+					eprintln!("Skipping {}: {}", volume.path.display(), error);
+					continue;
+				}
+				for path in &deleted {
+					baseline.files.remove(path);
+				}
+				baseline.add(manifest);
+			}
+		}
+		Ok(baseline)
+	}
+
+	fn add(&mut self, entries: Vec<Entry>) {
+		for entry in entries {
+			self.files.insert(entry.path.clone(), entry);
+		}
+	}
+
+	/// The file at `path`, relative to the archive root.
+	#[must_use]
+	pub fn get(&self, path: &RawPath) -> Option<&Entry> {
+		self.files.get(path)
+	}
+
+	/// All files below the directory at `path`, relative to the archive root. `""` is the root itself.
+	pub fn below<'a>(&'a self, path: &RawPath) -> impl Iterator<Item = &'a Entry> + 'a {
+		let prefix = if path.is_empty() {
+			RawPathBuf::from("")
+		} else {
+			path.join("")
+		};
+		self.files
+			.range(prefix.clone()..)
+			.take_while(move |(path, _)| path.starts_with(&prefix))
+			.map(|(_, entry)| entry)
+	}
+}
+
+fn read_to_end(data: &mut dyn std::io::Read) -> Result<Vec<u8>, Error> {
+	let mut buffer = Vec::new();
+	data.read_to_end(&mut buffer)?;
+	Ok(buffer)
+}
@@ -0,0 +1,155 @@
+//! Progress reporting for long dumps.
+//!
+//! A dump sends [`Event`]s to the [`Progress`] callback in its [`Options`](crate::dump::Options) as it goes.
+//! [`Stats`] adds them up into totals, throughput and an ETA, for embedders that don't want to keep count themselves.
+
+use crate::{RawPath, RawPathBuf};
+use std::{
+	fmt::{self, Debug, Formatter},
+	sync::Arc,
+	time::Duration,
+};
+
+/// Something a dump did.
+#[derive(Debug, Clone, Copy)]
+pub enum Event<'a> {
+	/// The pre-scan finished, having found `files` regular files totalling `bytes`.
+	///
+	/// Only sent with [`Options::scan`](crate::dump::Options::scan), before anything else.
+	Scanned { files: u64, bytes: u64 },
+	/// Listing a directory.
+	Dir(&'a RawPath),
+	/// About to pull a regular file.
+	File { path: &'a RawPath, size: u64 },
+	/// This many more bytes of the current file arrived.
+	///
+	/// Failed attempts count too, so a retried file can report more than its size.
+	Bytes(u64),
+	/// The regular file was archived.
+	Done { path: &'a RawPath, size: u64 },
+	/// The regular file wasn't pulled, because it's unchanged, was lost to an error or this is a dry run.
+	Skipped { path: &'a RawPath, size: u64 },
+}
+
+/// A callback for [`Event`]s.
+///
+/// It's called on the thread that runs the dump, so it should return quickly.
+#[derive(Clone)]
+pub struct Progress(Arc<dyn Fn(&Event) + Send + Sync>);
+
+impl Progress {
+	pub fn new(callback: impl Fn(&Event) + Send + Sync + 'static) -> Self {
+		Self(Arc::new(callback))
+	}
+
+	pub fn report(&self, event: &Event) {
+		(self.0)(event);
+	}
+}
+
+impl Debug for Progress {
+	fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+		f.write_str("Progress(..)")
+	}
+}
+
+/// Running totals of the [`Event`]s seen so far.
+#[derive(Debug, Clone, Default)]
+pub struct Stats {
+	/// From [`Event::Scanned`], if there was a pre-scan.
+	pub total_files: Option<u64>,
+	/// From [`Event::Scanned`], if there was a pre-scan.
+	pub total_bytes: Option<u64>,
+	/// Regular files archived or skipped.
+	pub files: u64,
+	/// The sizes of the files counted in [`files`](Self::files), plus what arrived of the current one.
+	pub bytes: u64,
+	/// Bytes actually transferred from the device.
+	pub pulled: u64,
+	/// The directory or file being worked on.
+	pub current: Option<RawPathBuf>,
+	/// The bytes of the current file counted in [`bytes`](Self::bytes).
+	partial: u64,
+	/// The size of the current file.
+	size: u64,
+}
+
+impl Stats {
+	#[must_use]
+	pub fn new() -> Self {
+		Self::default()
+	}
+
+	pub fn update(&mut self, event: &Event) {
+		match *event {
+			Event::Scanned { files, bytes } => {
+				self.total_files = Some(files);
+				self.total_bytes = Some(bytes);
+			}
+			Event::Dir(path) => self.current = Some(path.to_owned()),
+			Event::File { path, size } => {
+				self.current = Some(path.to_owned());
+				self.size = size;
+			}
+			Event::Bytes(bytes) => {
+				self.pulled += bytes;
+				let partial = (self.partial + bytes).min(self.size);
+				self.bytes += partial - self.partial;
+				self.partial = partial;
+			}
+			Event::Done { size, .. } | Event::Skipped { size, .. } => {
+				self.files += 1;
+				self.bytes += size;
+				self.bytes -= self.partial;
+				self.partial = 0;
+				self.size = 0;
+			}
+		}
+	}
+
+	/// How much of [`total_bytes`](Self::total_bytes) is done, from `0.0` to `1.0`.
+	#[must_use]
+	#[allow(clippy::cast_precision_loss)]
+	pub fn fraction(&self) -> Option<f64> {
+		let total = self.total_bytes?;
+		Some(if total == 0 {
+			1.0
+		} else {
+			(self.bytes as f64 / total as f64).min(1.0)
+		})
+	}
+
+	#[must_use]
+	#[allow(clippy::cast_precision_loss)]
+	pub fn files_per_second(&self, elapsed: Duration) -> f64 {
+		per_second(self.files as f64, elapsed)
+	}
+
+	/// Transfer speed, from [`pulled`](Self::pulled).
+	#[must_use]
+	#[allow(clippy::cast_precision_loss)]
+	pub fn bytes_per_second(&self, elapsed: Duration) -> f64 {
+		per_second(self.pulled as f64, elapsed)
+	}
+
+	/// The time left at the average speed so far, if there was a pre-scan and anything to go by yet.
+	#[must_use]
+	#[allow(clippy::cast_precision_loss)]
+	pub fn eta(&self, elapsed: Duration) -> Option<Duration> {
+		let left = self.total_bytes?.saturating_sub(self.bytes);
+		if left == 0 {
+			return Some(Duration::ZERO);
+		}
+		let speed = self.bytes as f64 / elapsed.as_secs_f64();
+		(speed.is_finite() && speed > 0.0).then(|| Duration::from_secs_f64(left as f64 / speed))
+	}
+}
+
+fn per_second(count: f64, elapsed: Duration) -> f64 {
+	let seconds = elapsed.as_secs_f64();
+	if seconds > 0.0 {
+		count / seconds
+	} else {
+		0.0
+	}
+}
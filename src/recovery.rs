@@ -0,0 +1,189 @@
+//! Whether a device in recovery, usually TWRP, has the partitions to dump mounted and decrypted.
+//!
+//! Dumping an unmounted partition only yields the empty directory it would be mounted on,
+//! and a `/data` with file-based encryption that wasn't decrypted yields ciphertext file names.
+
+use crate::{
+	device::{quote, Device, DeviceState, Mount},
+	RawPath, RawPathBuf, RawStr,
+};
+use std::{
+	collections::BTreeMap,
+	fmt::{self, Display, Formatter},
+	io::Error,
+};
+
+/// Partitions recovery may leave unmounted. Paths outside of them aren't checked.
+pub const PARTITIONS: &[&str] = &[
+	"/cache",
+	"/data",
+	"/metadata",
+	"/odm",
+	"/persist",
+	"/product",
+	"/system",
+	"/system_ext",
+	"/vendor",
+];
+
+/// How `/data` is encrypted, according to `ro.crypto.state` and `ro.crypto.type`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Encryption {
+	/// `ro.crypto.type=block`, or older devices without it. Can't be mounted before it's decrypted.
+	FullDisk,
+	/// `ro.crypto.type=file`. Mounts either way, but names and contents stay encrypted until decrypted.
+	FileBased,
+}
+
+/// What [`Status::probe`] found.
+#[derive(Debug, Clone)]
+pub struct Status {
+	pub state: DeviceState,
+	/// The `ro.twrp.*` properties, like `ro.twrp.version`. Empty outside of TWRP.
+	pub twrp: BTreeMap<String, String>,
+	pub mounts: Vec<Mount>,
+	pub encryption: Option<Encryption>,
+	/// Whether the names in `/data/data` are readable, if `/data` is mounted and uses [`Encryption::FileBased`].
+	pub decrypted: Option<bool>,
+}
+
+impl Status {
+	/// Asks the ADB server for the device's state, and reads its properties and `/proc/mounts`.
+	pub fn probe(device: &Device) -> Result<Self, Error> {
+		let state = device.state()?;
+		let properties = device.properties()?;
+		let encryption = match properties.get("ro.crypto.state").map(String::as_str) {
+			Some("encrypted") => Some(match properties.get("ro.crypto.type").map(String::as_str) {
+				Some("file") => Encryption::FileBased,
+				_ => Encryption::FullDisk,
+			}),
+			_ => None,
+		};
+		let mut status = Self {
+			state,
+			twrp: properties
+				.into_iter()
+				.filter(|(key, _)| key.starts_with("ro.twrp."))
+				.collect(),
+			mounts: device.mounts()?,
+			encryption,
+			decrypted: None,
+		};
+		if status.encryption == Some(Encryption::FileBased) && status.is_mounted("/data") {
+			status.decrypted = is_decrypted(device)?;
+		}
+		Ok(status)
+	}
+
+	#[must_use]
+	pub fn is_twrp(&self) -> bool {
+		!self.twrp.is_empty()
+	}
+
+	#[must_use]
+	pub fn is_mounted(&self, mount_point: &str) -> bool {
+		self.mounts
+			.iter()
+			.any(|mount| **mount.mount_point == mount_point)
+	}
+
+	/// What keeps `paths` from being dumped properly. Always empty outside of recovery.
+	#[must_use]
+	pub fn problems(&self, paths: &[RawPathBuf]) -> Vec<Problem> {
+		if self.state != DeviceState::Recovery {
+			return Vec::new();
+		}
+		let mut partitions: Vec<_> = paths.iter().filter_map(|path| partition(path)).collect();
+		partitions.sort_unstable();
+		partitions.dedup();
+		partitions
+			.into_iter()
+			.filter_map(|partition| {
+				let encryption = self.encryption.filter(|_| partition == "/data");
+				if !self.is_mounted(partition) {
+					Some(match encryption {
+						Some(Encryption::FullDisk) => Problem::Encrypted(Encryption::FullDisk),
+						_ => Problem::NotMounted(partition),
+					})
+				} else if encryption == Some(Encryption::FileBased) && self.decrypted == Some(false)
+				{
+					Some(Problem::Encrypted(Encryption::FileBased))
+				} else {
+					None
+				}
+			})
+			.collect()
+	}
+
+	/// The partitions of `paths` that [`mount`] could help with.
+	#[must_use]
+	pub fn unmounted(&self, paths: &[RawPathBuf]) -> Vec<&'static str> {
+		self.problems(paths)
+			.into_iter()
+			.filter_map(|problem| match problem {
+				Problem::NotMounted(partition) => Some(partition),
+				Problem::Encrypted(_) => None,
+			})
+			.collect()
+	}
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Problem {
+	NotMounted(&'static str),
+	/// `/data` is still encrypted.
+	Encrypted(Encryption),
+}
+
+impl Display for Problem {
+	fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+		match self {
+			Self::NotMounted(partition) => write!(
+				f,
+				"{} is not mounted, only the empty directory it's mounted on would be dumped",
+				partition
+			),
+			Self::Encrypted(Encryption::FullDisk) => {
+				f.write_str("/data is encrypted and can't be mounted before it's decrypted")
+			}
+			Self::Encrypted(Encryption::FileBased) => f.write_str(
+				"/data is still encrypted, file names and contents would be dumped as ciphertext",
+			),
+		}
+	}
+}
+
+/// The partition `path` is on in recovery, if it's one of [`PARTITIONS`].
+///
+/// `/sdcard` and `/storage` are on `/data`, and so is `/` as far as this is concerned.
+fn partition(path: &RawPath) -> Option<&'static str> {
+	match path.split(|b| *b == b'/').find(|name| !name.is_empty()) {
+		None | Some(b"sdcard" | b"storage") => Some("/data"),
+		Some(name) => PARTITIONS
+			.iter()
+			.copied()
+			.find(|partition| &partition.as_bytes()[1..] == name),
+	}
+}
+
+/// Whether `/data/data` lists package names, which always contain a `.` while encrypted names never do.
+fn is_decrypted(device: &Device) -> Result<Option<bool>, Error> {
+	let output = device.exec_out("ls /data/data 2>/dev/null")?;
+	let names: Vec<_> = RawStr::new(&output)
+		.lines()
+		.filter(|name| !name.is_empty())
+		.collect();
+	Ok((!names.is_empty()).then(|| names.iter().any(|name| name.contains(&b'.'))))
+}
+
+/// Mounts `partitions` with TWRP's `twrp mount`.
+///
+/// Doesn't check whether that worked, [probe](`Status::probe`) again for that.
+pub fn mount(device: &Device, partitions: &[&str]) -> Result<(), Error> {
+	for partition in partitions {
+		let mut command = b"twrp mount ".to_vec();
+		command.extend_from_slice(&quote(partition.as_bytes()));
+		device.exec_out(&command)?;
+	}
+	Ok(())
+}
@@ -0,0 +1,470 @@
+//! Pushes the contents of archive volumes back onto a device.
+//!
+//! Regular files are sent with SYNC `SEND`, which also sets their permissions and mtime.
+//! Directories and symlinks are created through the device's shell instead, and directories get their metadata once their contents are in place.
+//! Block and character devices, FIFOs and sockets aren't restored.
+//! Owners and SELinux contexts are only reapplied if [`Options::security`] is set, since that takes root on the device.
+//!
+//! Incremental dumps are restored on top of the dumps they're based on: files and symlinks come from the newest dump
+//! that has them, and files an incremental dump found deleted aren't restored from older ones.
+
+use crate::{
+	archive::{self, EntryKind, Metadata},
+	device::{quote, Device},
+	dump::{self, DELETED_FILE_NAME},
+	manifest,
+	rules::Rule,
+	AnError, RawPath, RawPathBuf, RawStr, RawString,
+};
+use std::{
+	collections::BTreeSet,
+	convert::TryFrom,
+	fmt::{self, Display, Formatter},
+	io::{Error, ErrorKind, Read},
+	path::PathBuf,
+};
+
+/// Where a restore writes to.
+///
+/// Implemented by [`Device`].
+pub trait Destination {
+	/// Whether anything exists at `path`.
+	fn exists(&self, path: &RawPath) -> Result<bool, Error>;
+
+	/// Creates the directory at `path`, along with any missing parents.
+	fn create_dir(&self, path: &RawPath) -> Result<(), Error>;
+
+	/// Creates or replaces the regular file at `path`, along with any missing parent directories.
+	fn push(
+		&self,
+		path: &RawPath,
+		permissions: u32,
+		mtime: i64,
+		data: &mut dyn Read,
+	) -> Result<u64, Error>;
+
+	/// Creates or replaces the symlink at `path`, along with any missing parent directories.
+	fn symlink(&self, path: &RawPath, target: &RawPath) -> Result<(), Error>;
+
+	fn set_metadata(&self, path: &RawPath, permissions: u32, mtime: i64) -> Result<(), Error>;
+
+	/// Changes the owner and SELinux context of `path`, where given. Symlinks are changed themselves, not their targets.
+	fn set_security(
+		&self,
+		path: &RawPath,
+		owner: Option<(u32, u32)>,
+		context: Option<&RawStr>,
+	) -> Result<(), Error>;
+}
+
+impl Destination for Device {
+	fn exists(&self, path: &RawPath) -> Result<bool, Error> {
+		if self.su().is_some() {
+			// SYNC's `STAT` reports what the `shell` user can't see as missing.
+			let mut command = b"stat -c %f ".to_vec();
+			command.extend_from_slice(&quote(path));
+			command.extend_from_slice(b" 2>/dev/null");
+			return Ok(self.exec_out(&command)?.iter().any(u8::is_ascii_hexdigit));
+		}
+		let mut sync = self.sync()?;
+		let stat = sync.stat(path)?;
+		sync.quit()?;
+		Ok(stat.exists())
+	}
+
+	fn create_dir(&self, path: &RawPath) -> Result<(), Error> {
+		let mut command = b"mkdir -p ".to_vec();
+		command.extend_from_slice(&quote(path));
+		shell(self, &command)
+	}
+
+	fn push(
+		&self,
+		path: &RawPath,
+		permissions: u32,
+		mtime: i64,
+		data: &mut dyn Read,
+	) -> Result<u64, Error> {
+		let mode = 0o100_000 | permissions;
+		if let Ok(mtime) = u32::try_from(mtime) {
+			return Device::push(self, path, mode, mtime, data);
+		}
+		// `SEND` can't represent this mtime.
+		let written = Device::push(self, path, mode, 0, data)?;
+		self.set_metadata(path, permissions, mtime)?;
+		Ok(written)
+	}
+
+	fn symlink(&self, path: &RawPath, target: &RawPath) -> Result<(), Error> {
+		let mut command = Vec::new();
+		if let Some(dir) = path.directory() {
+			command.extend_from_slice(b"mkdir -p ");
+			command.extend_from_slice(&quote(dir));
+			command.extend_from_slice(b" && ");
+		}
+		command.extend_from_slice(b"ln -sfn ");
+		command.extend_from_slice(&quote(target));
+		command.push(b' ');
+		command.extend_from_slice(&quote(path));
+		shell(self, &command)
+	}
+
+	fn set_metadata(&self, path: &RawPath, permissions: u32, mtime: i64) -> Result<(), Error> {
+		let mut command = format!("chmod {:o} ", permissions).into_bytes();
+		command.extend_from_slice(&quote(path));
+		command.extend_from_slice(format!(" && touch -c -m -d @{} ", mtime).as_bytes());
+		command.extend_from_slice(&quote(path));
+		shell(self, &command)
+	}
+
+	fn set_security(
+		&self,
+		path: &RawPath,
+		owner: Option<(u32, u32)>,
+		context: Option<&RawStr>,
+	) -> Result<(), Error> {
+		let mut commands = Vec::new();
+		if let Some((uid, gid)) = owner {
+			let mut command = format!("chown -h {}:{} ", uid, gid).into_bytes();
+			command.extend_from_slice(&quote(path));
+			commands.push(command);
+		}
+		if let Some(context) = context {
+			let mut command = b"chcon -h ".to_vec();
+			command.extend_from_slice(&quote(context));
+			command.push(b' ');
+			command.extend_from_slice(&quote(path));
+			commands.push(command);
+		}
+		if commands.is_empty() {
+			return Ok(());
+		}
+		shell(self, &commands.join(&b" && "[..]))
+	}
+}
+
+/// Runs `command` in the device's shell, failing with its output if it exits unsuccessfully.
+fn shell(device: &Device, command: &[u8]) -> Result<(), Error> {
+	let mut line = b"(".to_vec();
+	line.extend_from_slice(command);
+	line.extend_from_slice(b") 2>&1; echo $?");
+	let output = device.exec_out(&line)?;
+	let output = String::from_utf8_lossy(&output);
+	let output = output.trim_end();
+	let (message, status) = output.rsplit_once('\n').unwrap_or(("", output));
+	if status == "0" {
+		Ok(())
+	} else {
+		Err(Error::new(
+			ErrorKind::Other,
+			AnError(format!(
+				"`{}` failed: {}",
+				String::from_utf8_lossy(command),
+				message.trim()
+			)),
+		))
+	}
+}
+
+#[derive(Debug)]
+pub struct Options {
+	/// Where the archive volumes are: a dump, followed by any incremental dumps on top of it, oldest first.
+	/// All volumes in each are read, ordered by set name and number.
+	pub output_dirs: Vec<PathBuf>,
+	/// The device directory archive entry paths are relative to.
+	pub root: RawPathBuf,
+	/// Device path prefixes to replace, like `/sdcard` with `/storage/emulated/0`. The longest matching one applies.
+	///
+	/// Symlink targets are restored as they are.
+	pub remap: Vec<(RawPathBuf, RawPathBuf)>,
+	/// If not empty, only entries whose device path (before remapping) or a directory containing them matches one of these are restored.
+	///
+	/// `size` and `mtime` conditions never match.
+	pub only: Vec<Rule>,
+	/// Replace files and symlinks that already exist on the device, and apply the archived metadata to existing directories.
+	pub overwrite: bool,
+	/// Reapply archived owners and SELinux contexts. Entries without them are left as created.
+	pub security: bool,
+	/// `-1` only prints errors, `1` and above list every entry.
+	pub verbosity: i8,
+}
+
+#[derive(Debug)]
+pub enum Problem {
+	/// Already on the device, and [`Options::overwrite`] wasn't set.
+	Exists,
+	/// A device node, FIFO or socket.
+	Unsupported,
+	/// The entry couldn't be written.
+	Error(Error),
+}
+
+#[derive(Debug)]
+pub struct Finding {
+	/// The path on the device, after remapping.
+	pub path: RawPathBuf,
+	pub problem: Problem,
+}
+
+impl Display for Finding {
+	/// One tab-separated line of `kind`, quoted path and details.
+	fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+		let path = self.path.encode_lossless();
+		match &self.problem {
+			Problem::Exists => write!(f, "exists\t{:?}", path),
+			Problem::Unsupported => write!(f, "unsupported\t{:?}", path),
+			Problem::Error(error) => write!(f, "error\t{:?}\t{}", path, error),
+		}
+	}
+}
+
+#[derive(Debug)]
+pub struct Report {
+	/// The number of files and symlinks written.
+	pub restored: usize,
+	pub findings: Vec<Finding>,
+}
+
+impl Report {
+	#[must_use]
+	pub fn is_ok(&self) -> bool {
+		self.findings.is_empty()
+	}
+}
+
+/// Restores the archive volumes in `options.output_dirs` to `destination`.
+///
+/// Problems with individual entries end up in the [`Report`], while errors reading the archives abort.
+pub fn run(destination: &dyn Destination, options: &Options) -> Result<Report, Error> {
+	let mut restore = Restore {
+		destination,
+		options,
+		report: Report {
+			restored: 0,
+			findings: Vec::new(),
+		},
+		written: BTreeSet::new(),
+		dirs: Vec::new(),
+	};
+	// Everything but directories in newer dumps, and what they found deleted, which older dumps don't restore.
+	let mut shadowed = BTreeSet::new();
+
+	// The newest dump first, so its directories are the ones created with their metadata.
+	for output_dir in options.output_dirs.iter().rev() {
+		let mut layer = BTreeSet::new();
+		for volume in archive::volumes(output_dir)? {
+			archive::read_entries(&volume.path, |metadata, kind, data| {
+				if **metadata.path == DELETED_FILE_NAME {
+					let mut text = Vec::new();
+					data.read_to_end(&mut text)?;
+					for deleted in manifest::parse_deleted(&text)? {
+						layer.insert(remap(&options.remap, &options.root.join(&*deleted)));
+					}
+					return Ok(());
+				}
+				if dump::is_manifest(metadata.path) || dump::is_marker(metadata.path) {
+					return Ok(());
+				}
+				let original = options.root.join(metadata.path);
+				if !selected(&options.only, &original) {
+					return Ok(());
+				}
+				let path = remap(&options.remap, &original);
+				if kind != EntryKind::Directory {
+					if shadowed.contains(&path) {
+						return Ok(());
+					}
+					layer.insert(path.clone());
+				}
+				restore.entry(path, metadata, kind, data);
+				Ok(())
+			})?;
+		}
+		shadowed.extend(layer);
+	}
+
+	let Restore {
+		mut report, dirs, ..
+	} = restore;
+	// Innermost first, since setting a directory's metadata doesn't touch its parent's mtime.
+	for (path, permissions, mtime, security) in dirs.into_iter().rev() {
+		// Owners first, since `chown` clears the setgid bit.
+		let result = match security {
+			Some((owner, context)) => destination.set_security(&path, owner, context.as_deref()),
+			None => Ok(()),
+		}
+		.and_then(|()| destination.set_metadata(&path, permissions, mtime));
+		if let Err(error) = result {
+			report.findings.push(Finding {
+				path,
+				problem: Problem::Error(error),
+			});
+		}
+	}
+	Ok(report)
+}
+
+struct Restore<'a> {
+	destination: &'a dyn Destination,
+	options: &'a Options,
+	report: Report,
+	/// Paths this restore wrote, which later volumes may replace regardless of `options.overwrite`.
+	written: BTreeSet<RawPathBuf>,
+	/// Directories to apply metadata to, outermost first.
+	dirs: Vec<(RawPathBuf, u32, i64, Option<Security<RawString>>)>,
+}
+
+impl Restore<'_> {
+	/// Restores one entry to `path`, recording the outcome in the report.
+	fn entry(
+		&mut self,
+		path: RawPathBuf,
+		metadata: &Metadata,
+		kind: EntryKind,
+		data: &mut dyn Read,
+	) {
+		let (destination, options) = (self.destination, self.options);
+		if options.verbosity >= 1 {
+			let kind = match kind {
+				EntryKind::Directory => "dir",
+				EntryKind::File => "file",
+				EntryKind::Symlink => "link",
+				EntryKind::Other => "other",
+			};
+			println!("{} {:?}", kind, &path);
+		}
+
+		let result = match kind {
+			EntryKind::Directory => {
+				restore_dir(destination, options, &path).map(|apply_metadata| {
+					if apply_metadata {
+						let security = security(options, metadata)
+							.map(|(owner, context)| (owner, context.map(ToOwned::to_owned)));
+						self.dirs.push((
+							path.clone(),
+							metadata.permissions,
+							metadata.mtime,
+							security,
+						));
+					}
+					None
+				})
+			}
+			EntryKind::File | EntryKind::Symlink => {
+				let result = restore_leaf(
+					destination,
+					options,
+					&self.written,
+					&path,
+					metadata,
+					kind,
+					data,
+				);
+				if let Ok(None) = result {
+					self.written.insert(path.clone());
+					self.report.restored += 1;
+				}
+				result
+			}
+			EntryKind::Other => Ok(Some(Problem::Unsupported)),
+		};
+		match result {
+			Ok(None) => (),
+			Ok(Some(problem)) => self.report.findings.push(Finding { path, problem }),
+			Err(error) => self.report.findings.push(Finding {
+				path,
+				problem: Problem::Error(error),
+			}),
+		}
+	}
+}
+
+/// Creates the directory at `path`, returning whether its metadata should be applied.
+fn restore_dir(
+	destination: &dyn Destination,
+	options: &Options,
+	path: &RawPath,
+) -> Result<bool, Error> {
+	if destination.exists(path)? {
+		return Ok(options.overwrite);
+	}
+	destination.create_dir(path)?;
+	Ok(true)
+}
+
+/// Writes a regular file or symlink, unless it's in the way of something.
+fn restore_leaf(
+	destination: &dyn Destination,
+	options: &Options,
+	written: &BTreeSet<RawPathBuf>,
+	path: &RawPath,
+	metadata: &Metadata,
+	kind: EntryKind,
+	data: &mut dyn Read,
+) -> Result<Option<Problem>, Error> {
+	if !options.overwrite && !written.contains(path) && destination.exists(path)? {
+		return Ok(Some(Problem::Exists));
+	}
+	if kind == EntryKind::Symlink {
+		let mut target = Vec::new();
+		data.read_to_end(&mut target)?;
+		destination.symlink(path, RawPath::new(&target))?;
+	} else {
+		destination.push(path, metadata.permissions, metadata.mtime, data)?;
+	}
+	if let Some((owner, context)) = security(options, metadata) {
+		destination.set_security(path, owner, context)?;
+	}
+	Ok(None)
+}
+
+/// An owner (UID and GID) and SELinux context to reapply.
+type Security<Context> = (Option<(u32, u32)>, Option<Context>);
+
+/// The owner and context to reapply to an entry, if [`Options::security`] is set.
+fn security<'a>(options: &Options, metadata: &Metadata<'a>) -> Option<Security<&'a RawStr>> {
+	options
+		.security
+		.then(|| (metadata.uid.zip(metadata.gid), metadata.context))
+}
+
+/// Whether `path` or a directory containing it matches one of `only`, or `only` is empty.
+fn selected(only: &[Rule], path: &RawPath) -> bool {
+	if only.is_empty() {
+		return true;
+	}
+	let mut path = path;
+	while !path.is_empty() {
+		if only.iter().any(|rule| rule.matches(path, None)) {
+			return true;
+		}
+		path = path
+			.directory()
+			.map_or_else(|| "".into(), |dir| &dir[..dir.len() - 1]);
+	}
+	false
+}
+
+/// Replaces the longest prefix of `path` found in `remap`.
+fn remap(remap: &[(RawPathBuf, RawPathBuf)], path: &RawPath) -> RawPathBuf {
+	let below = |prefix: &RawPath| {
+		path.starts_with(prefix)
+			&& (path.len() == prefix.len()
+				|| prefix.ends_with(b"/")
+				|| path.get(prefix.len()) == Some(&b'/'))
+	};
+	let Some((from, to)) = remap
+		.iter()
+		.filter(|(from, _)| below(from))
+		.max_by_key(|(from, _)| from.len())
+	else {
+		return path.to_owned();
+	};
+	let rest: &[u8] = &path[from.len()..];
+	let rest = rest.strip_prefix(b"/").unwrap_or(rest);
+	if rest.is_empty() {
+		to.clone()
+	} else {
+		to.join(RawPath::new(rest))
+	}
+}
@@ -0,0 +1,410 @@
+//! Exclude/include rules deciding which device paths are dumped.
+//!
+//! Each rule is a single line:
+//!
+//! ```text
+//! [!][@package] [size<N|size>N] [mtime<DATE|mtime>DATE] pattern
+//! ```
+//!
+//! - A leading `!` re-includes paths excluded by earlier rules. The last matching rule wins.
+//! - `@package` restricts the pattern to paths below a directory named after that package,
+//!   so `@org.telegram.messenger cache` matches both `/data/data/org.telegram.messenger/cache`
+//!   and `/sdcard/Android/data/org.telegram.messenger/cache`. An empty pattern then matches the package directory itself.
+//! - `size` only matches regular files, `mtime` compares against `YYYY-MM-DD` or `YYYY-MM-DDTHH:MM:SS` in UTC.
+//!   Sizes understand K, M, G, T and Ki, Mi, Gi, Ti suffixes.
+//! - Patterns starting with `/` are anchored to the start of the device path.
+//!   All others match any trailing run of path components, like `HTTP Cache` or `org.mozilla.firefox/cache`.
+//!   A trailing `/` only matches directories.
+//! - `*` matches within one path component, `**` across components, `?` a single byte and `[a-z]`/`[!a-z]` a byte class.
+//! - A pattern starting with `\` is taken literally from the next character on, so `\!important` or `\size>1` match those names.
+//!
+//! Empty lines and lines starting with `#` are ignored in rule files.
+
+use crate::{AnError, LsEntry, RawPath};
+use chrono::{NaiveDate, NaiveDateTime};
+use std::{
+	fmt::{self, Display, Formatter},
+	fs,
+	io::{Error, ErrorKind},
+	path::Path,
+	str::FromStr,
+};
+
+/// Built-in rule sets.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Preset {
+	/// Caches and other bulky data you probably don't want to keep. Enabled by default.
+	Caches,
+}
+
+const CACHES: &[&str] = &[
+	"BrowserMetrics", // LineageOS used to generate a very large (unbounded) amount of these files via web embed, and they're quite large too.
+	"HTTP Cache",
+	"com.google.android.googlequicksearchbox", // This caches A LOT of data and you probably don't want to keep it.
+	".com.google.firebase.crashlytics-ndk",    // Twitter creates a huge amount of crash reports :(
+	"org.mozilla.firefox/cache",               // It's just a ton of pretty useless files.
+	"com.crashlytics.sdk.android.crashlytics-core", // Notify crashes a lot.
+	"lib/python2.7",                           // Ren'Py uses this.
+	"cache/image_manager_disk_cache", // Various programs use this, so while it's usually not huge, there might be a lot cumulatively.
+	"cache:memrise.offline.assets",   // Many files, and quite possibly large.
+	"org.mozilla.firefox_beta/cache",
+	"com.ecosia.android/cache",            // Another browser cache.
+	"com.duckduckgo.mobile.android/cache", // Quack.
+	"org.mozilla.fenix/cache",             // Firefox Nightly
+	"com.google.android.apps.photos/cache/glide_cache", // Google Photos thumbnail cache, probably. It can take ages to back this up.
+	"com.twitter.android/cache",                        // Twitter cache
+	"org.telegram.messenger/cache",                     // Telegram cache
+	"com.google.android.inputmethod.latin/cache",       // GBoard cache, probably
+	"com.discord/cache",                                // Discord cache
+	"de.nebenan.app/cache",                             // Nebenan.de cache
+	"data/crdroid_updates", // Huge system image files that you probably don't need.
+];
+
+impl Preset {
+	#[must_use]
+	pub fn name(self) -> &'static str {
+		match self {
+			Self::Caches => "caches",
+		}
+	}
+
+	#[must_use]
+	pub fn rules(self) -> Rules {
+		let lines = match self {
+			Self::Caches => CACHES,
+		};
+		let origin = format!("preset {}", self.name());
+		Rules(
+			lines
+				.iter()
+				.map(|line| Rule::parse(line, &origin).expect("Invalid preset rule"))
+				.collect(),
+		)
+	}
+}
+
+impl FromStr for Preset {
+	type Err = Error;
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		match s {
+			"caches" => Ok(Self::Caches),
+			other => Err(Error::new(
+				ErrorKind::InvalidInput,
+				AnError(format!("Unknown preset: {}", other)),
+			)),
+		}
+	}
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Rules(Vec<Rule>);
+impl Rules {
+	#[must_use]
+	pub fn new() -> Self {
+		Self::default()
+	}
+
+	/// Parses one rule per line. `origin` is used to describe where each rule came from.
+	pub fn parse(text: &str, origin: &str) -> Result<Self, Error> {
+		text.lines()
+			.enumerate()
+			.map(|(i, line)| (i, line.trim_end_matches('\r')))
+			.filter(|(_, line)| !line.trim().is_empty() && !line.starts_with('#'))
+			.map(|(i, line)| Rule::parse(line, &format!("{}:{}", origin, i + 1)))
+			.collect::<Result<_, _>>()
+			.map(Self)
+	}
+
+	pub fn load(path: &Path) -> Result<Self, Error> {
+		Self::parse(&fs::read_to_string(path)?, &path.to_string_lossy())
+	}
+
+	pub fn push(&mut self, rule: Rule) {
+		self.0.push(rule);
+	}
+
+	pub fn extend(&mut self, rules: Rules) {
+		self.0.extend(rules.0);
+	}
+
+	/// Returns the rule that excludes `path`, if any.
+	///
+	/// `entry` should be the listing entry for `path` if available. Without it, `size` and `mtime` conditions never match.
+	#[must_use]
+	pub fn excluded_by(&self, path: &RawPath, entry: Option<&LsEntry>) -> Option<&Rule> {
+		self.0
+			.iter()
+			.rev()
+			.find(|rule| rule.matches(path, entry))
+			.filter(|rule| !rule.include)
+	}
+}
+
+#[derive(Debug, Clone)]
+pub struct Rule {
+	include: bool,
+	package: Option<Vec<u8>>,
+	min_size: Option<u64>,
+	max_size: Option<u64>,
+	newer_than: Option<i64>,
+	older_than: Option<i64>,
+	anchored: bool,
+	dir_only: bool,
+	pattern: Vec<u8>,
+	text: String,
+	origin: String,
+}
+
+impl Rule {
+	pub fn parse(line: &str, origin: &str) -> Result<Self, Error> {
+		let invalid = |what: String| {
+			Error::new(
+				ErrorKind::InvalidInput,
+				AnError(format!("{} in rule {:?} ({})", what, line, origin)),
+			)
+		};
+
+		let mut rule = Self {
+			include: false,
+			package: None,
+			min_size: None,
+			max_size: None,
+			newer_than: None,
+			older_than: None,
+			anchored: false,
+			dir_only: false,
+			pattern: Vec::new(),
+			text: line.to_string(),
+			origin: origin.to_string(),
+		};
+
+		let mut rest = line;
+		if let Some(stripped) = rest.strip_prefix("!") {
+			rule.include = true;
+			rest = stripped;
+		}
+		loop {
+			let token_end = rest.find(' ').unwrap_or(rest.len());
+			let (token, after) = (&rest[..token_end], rest[token_end..].trim_start());
+			if let Some(package) = token.strip_prefix("@") {
+				rule.package = Some(package.as_bytes().to_vec());
+			} else if let Some(size) = token.strip_prefix("size>") {
+				rule.min_size = Some(parse_size(size).map_err(invalid)?.saturating_add(1));
+			} else if let Some(size) = token.strip_prefix("size<") {
+				rule.max_size = Some(parse_size(size).map_err(invalid)?.saturating_sub(1));
+			} else if let Some(date) = token.strip_prefix("mtime>") {
+				rule.newer_than = Some(parse_date(date).map_err(invalid)?);
+			} else if let Some(date) = token.strip_prefix("mtime<") {
+				rule.older_than = Some(parse_date(date).map_err(invalid)?);
+			} else {
+				break;
+			}
+			rest = after;
+		}
+
+		let mut pattern = rest.strip_prefix("\\").unwrap_or(rest);
+		if let Some(stripped) = pattern.strip_prefix("/") {
+			rule.anchored = true;
+			pattern = stripped;
+		}
+		if pattern.len() > 1 {
+			if let Some(stripped) = pattern.strip_suffix('/') {
+				rule.dir_only = true;
+				pattern = stripped;
+			}
+		}
+		rule.pattern = pattern.as_bytes().to_vec();
+
+		if rule.pattern.is_empty()
+			&& rule.package.is_none()
+			&& rule.min_size.is_none()
+			&& rule.max_size.is_none()
+			&& rule.newer_than.is_none()
+			&& rule.older_than.is_none()
+			&& !rule.anchored
+		{
+			return Err(invalid("Missing pattern".to_string()));
+		}
+		Ok(rule)
+	}
+
+	#[must_use]
+	pub fn is_include(&self) -> bool {
+		self.include
+	}
+
+	#[must_use]
+	pub fn matches(&self, path: &RawPath, entry: Option<&LsEntry>) -> bool {
+		if self.dir_only && !entry.map_or(true, |entry| entry.mode.is_dir()) {
+			return false;
+		}
+		if self.min_size.is_some() || self.max_size.is_some() {
+			match entry {
+				Some(entry) if entry.mode.is_file() => {
+					let size = entry.size;
+					if self.min_size.map_or(false, |min| size < min)
+						|| self.max_size.map_or(false, |max| size > max)
+					{
+						return false;
+					}
+				}
+				_ => return false,
+			}
+		}
+		if self.newer_than.is_some() || self.older_than.is_some() {
+			match entry {
+				Some(entry) => {
+					let mtime = entry.mtime.timestamp();
+					if self.newer_than.map_or(false, |newer| mtime <= newer)
+						|| self.older_than.map_or(false, |older| mtime >= older)
+					{
+						return false;
+					}
+				}
+				None => return false,
+			}
+		}
+
+		let mut path: &[u8] = path;
+		if path.len() > 1 && path.ends_with(b"/") {
+			path = &path[..path.len() - 1];
+		}
+		match &self.package {
+			Some(package) => component_starts(path).any(|start| {
+				let rest = &path[start..];
+				rest.starts_with(package)
+					&& match rest.get(package.len()) {
+						None => self.pattern.is_empty(),
+						Some(b'/') => {
+							let rest = &rest[package.len() + 1..];
+							if self.pattern.is_empty() {
+								true
+							} else if self.anchored {
+								glob(&self.pattern, rest)
+							} else {
+								component_starts(rest)
+									.any(|start| glob(&self.pattern, &rest[start..]))
+							}
+						}
+						Some(_) => false,
+					}
+			}),
+			None if self.anchored => glob(&self.pattern, strip_prefix(path, b"/").unwrap_or(path)),
+			None if self.pattern.is_empty() => true,
+			None => component_starts(path).any(|start| glob(&self.pattern, &path[start..])),
+		}
+	}
+}
+
+impl Display for Rule {
+	fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+		write!(f, "{} ({})", self.text, self.origin)
+	}
+}
+
+fn strip_prefix<'a>(bytes: &'a [u8], prefix: &[u8]) -> Option<&'a [u8]> {
+	if bytes.starts_with(prefix) {
+		Some(&bytes[prefix.len()..])
+	} else {
+		None
+	}
+}
+
+/// Offsets at which path components start.
+fn component_starts(path: &[u8]) -> impl Iterator<Item = usize> + '_ {
+	(0..path.len()).filter(move |i| path[*i] != b'/' && (*i == 0 || path[i - 1] == b'/'))
+}
+
+/// Parses sizes like `1000`, `1G` or `4GiB`.
+pub fn parse_size(size: &str) -> Result<u64, String> {
+	let size = size.trim_end_matches('B');
+	let (digits, factor) = match size.char_indices().find(|(_, c)| !c.is_ascii_digit()) {
+		None => (size, 1),
+		Some((i, _)) => (
+			&size[..i],
+			match &size[i..] {
+				"K" | "k" => 1000,
+				"M" => 1000_u64.pow(2),
+				"G" => 1000_u64.pow(3),
+				"T" => 1000_u64.pow(4),
+				"Ki" => 1 << 10,
+				"Mi" => 1 << 20,
+				"Gi" => 1 << 30,
+				"Ti" => 1 << 40,
+				suffix => return Err(format!("Unknown size suffix: {}", suffix)),
+			},
+		),
+	};
+	digits
+		.parse::<u64>()
+		.map_err(|error| error.to_string())?
+		.checked_mul(factor)
+		.ok_or_else(|| "Size too large".to_string())
+}
+
+fn parse_date(date: &str) -> Result<i64, String> {
+	NaiveDateTime::parse_from_str(date, "%Y-%m-%dT%H:%M:%S")
+		.or_else(|_| {
+			NaiveDate::parse_from_str(date, "%Y-%m-%d")
+				.map(|date| date.and_hms_opt(0, 0, 0).expect("Midnight is valid"))
+		})
+		.map(|date_time| date_time.and_utc().timestamp())
+		.map_err(|error| format!("Invalid date {:?}: {}", date, error))
+}
+
+/// Matches `text` against the whole of `pattern`.
+fn glob(pattern: &[u8], text: &[u8]) -> bool {
+	match pattern.split_first() {
+		None => text.is_empty(),
+		Some((b'*', rest)) if rest.first() == Some(&b'*') => {
+			let rest = &rest[1..];
+			// `**/` may also match zero directories.
+			strip_prefix(rest, b"/").map_or(false, |after_slash| glob(after_slash, text))
+				|| (0..=text.len()).any(|i| glob(rest, &text[i..]))
+		}
+		Some((b'*', rest)) => (0..=text.len())
+			.take_while(|i| *i == 0 || text[i - 1] != b'/')
+			.any(|i| glob(rest, &text[i..])),
+		Some((b'?', rest)) => match text.split_first() {
+			Some((b, text)) if *b != b'/' => glob(rest, text),
+			_ => false,
+		},
+		Some((b'[', rest)) => match (text.split_first(), class(rest)) {
+			(Some((b, text)), Some((matches, rest))) => {
+				*b != b'/' && matches(*b) && glob(rest, text)
+			}
+			(_, None) => text.first() == Some(&b'[') && glob(rest, &text[1..]),
+			(None, Some(_)) => false,
+		},
+		Some((p, rest)) => text.first() == Some(p) && glob(rest, &text[1..]),
+	}
+}
+
+/// Parses a byte class after its opening `[`, returning a matcher and the remaining pattern.
+fn class(pattern: &[u8]) -> Option<(impl Fn(u8) -> bool + '_, &[u8])> {
+	let (negated, body) = match pattern.first() {
+		Some(b'!' | b'^') => (true, &pattern[1..]),
+		_ => (false, pattern),
+	};
+	// A `]` right at the start is literal.
+	let end = 1 + body.get(1..)?.iter().position(|b| *b == b']')?;
+	let (set, rest) = (&body[..end], &body[end + 1..]);
+	Some((
+		move |b: u8| {
+			let mut i = 0;
+			let mut found = false;
+			while i < set.len() {
+				if i + 2 < set.len() && set[i + 1] == b'-' {
+					found |= (set[i]..=set[i + 2]).contains(&b);
+					i += 3;
+				} else {
+					found |= set[i] == b;
+					i += 1;
+				}
+			}
+			found != negated
+		},
+		rest,
+	))
+}
@@ -0,0 +1,520 @@
+//! A content-addressed backup store, where each file's contents are kept once however often they're dumped.
+//!
+//! A store is a directory with two subdirectories:
+//!
+//! - `blobs/`, holding file contents named by their hex SHA-256 (as `blobs/ab/abcdef…`), and
+//! - `snapshots/`, with one directory per dump holding its journal and its volumes as [`Format::Tree`] files.
+//!
+//! Tree volumes list one entry per line, as tab-separated kind (`dir`, `file`, `link`, `block`, `char`, `fifo` or `socket`),
+//! hex-encoded path, octal permissions and mtime, followed by the size and hex SHA-256 for files,
+//! the hex-encoded target for symlinks and `major:minor` for devices.
+//! Entries with a known owner or SELinux context end with `uid:gid` (empty if unknown) and the hex-encoded context (possibly empty).
+//!
+//! Since tree volumes are read by [`archive::read`] like any other, snapshots can be checked, verified and dumped incrementally against.
+
+use crate::{
+	archive::{self, EntryKind, Format, Metadata, Special},
+	dump::{self, SPECIAL_FILE_NAME},
+	AnError, RawPath, RawPathBuf, RawString,
+};
+use sha2::{Digest, Sha256};
+use std::{
+	convert::TryFrom,
+	ffi::OsString,
+	fs::{self, File, OpenOptions},
+	io::{self, BufRead, BufReader, BufWriter, Error, ErrorKind, Read, Write},
+	path::{Component, Path, PathBuf},
+};
+
+pub const BLOBS_DIR_NAME: &str = "blobs";
+pub const SNAPSHOTS_DIR_NAME: &str = "snapshots";
+
+/// The directory of the snapshot `name` in the store at `store`.
+#[must_use]
+pub fn snapshot_dir(store: &Path, name: &str) -> PathBuf {
+	store.join(SNAPSHOTS_DIR_NAME).join(name)
+}
+
+/// The names of the snapshots in the store at `store`, sorted.
+pub fn snapshots(store: &Path) -> Result<Vec<String>, Error> {
+	let mut names = Vec::new();
+	match fs::read_dir(store.join(SNAPSHOTS_DIR_NAME)) {
+		Ok(entries) => {
+			for entry in entries {
+				let entry = entry?;
+				if entry.file_type()?.is_dir() {
+					names.push(entry.file_name().to_string_lossy().into_owned());
+				}
+			}
+		}
+		Err(error) if error.kind() == ErrorKind::NotFound => (),
+		Err(error) => return Err(error),
+	}
+	names.sort();
+	Ok(names)
+}
+
+/// Where the contents with this SHA-256 are kept in the store at `store`.
+#[must_use]
+pub fn blob_path(store: &Path, sha256: &[u8]) -> PathBuf {
+	let name = hex::encode(sha256);
+	store.join(BLOBS_DIR_NAME).join(&name[..2]).join(name)
+}
+
+/// The store a tree volume at `path` belongs to, three levels up.
+fn store_of(path: &Path) -> Result<&Path, Error> {
+	path.parent()
+		.and_then(Path::parent)
+		.and_then(Path::parent)
+		.ok_or_else(|| {
+			Error::new(
+				ErrorKind::InvalidInput,
+				AnError(format!(
+					"{} isn't in a store's snapshot directory",
+					path.display()
+				)),
+			)
+		})
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Node {
+	Dir,
+	File { size: u64, sha256: Vec<u8> },
+	Symlink(RawPathBuf),
+	Special(Special),
+}
+
+/// One line of a tree volume.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Entry {
+	/// Relative to the archive root.
+	pub path: RawPathBuf,
+	pub permissions: u32,
+	pub mtime: i64,
+	pub node: Node,
+	pub owner: Option<(u32, u32)>,
+	/// The `security.selinux` label.
+	pub context: Option<RawString>,
+}
+
+impl Entry {
+	fn to_line(&self) -> String {
+		let kind = match &self.node {
+			Node::Dir => "dir",
+			Node::File { .. } => "file",
+			Node::Symlink(_) => "link",
+			Node::Special(special) => special.kind(),
+		};
+		let mut line = format!(
+			"{}\t{}\t{:o}\t{}",
+			kind,
+			hex::encode(&***self.path),
+			self.permissions,
+			self.mtime
+		);
+		match &self.node {
+			Node::File { size, sha256 } => {
+				line = format!("{}\t{}\t{}", line, size, hex::encode(sha256));
+			}
+			Node::Symlink(target) => line = format!("{}\t{}", line, hex::encode(&****target)),
+			Node::Special(special) => {
+				if let Some((major, minor)) = special.numbers() {
+					line = format!("{}\t{}:{}", line, major, minor);
+				}
+			}
+			Node::Dir => (),
+		}
+		if self.owner.is_some() || self.context.is_some() {
+			let owner = self
+				.owner
+				.map(|(uid, gid)| format!("{}:{}", uid, gid))
+				.unwrap_or_default();
+			let context = self
+				.context
+				.as_ref()
+				.map(|context| hex::encode(&***context))
+				.unwrap_or_default();
+			line = format!("{}\t{}\t{}", line, owner, context);
+		}
+		line
+	}
+
+	fn parse(line: &str) -> Result<Self, Error> {
+		let invalid = || {
+			Error::new(
+				ErrorKind::InvalidData,
+				AnError(format!("Invalid line in tree volume: {:?}", line)),
+			)
+		};
+		let hex = |field: &str| hex::decode(field).map_err(|_| invalid());
+		let numbers = |field: &str| {
+			let (major, minor) = field.split_once(':').ok_or_else(invalid)?;
+			Ok::<_, Error>((
+				major.parse().map_err(|_| invalid())?,
+				minor.parse().map_err(|_| invalid())?,
+			))
+		};
+
+		let fields: Vec<_> = line.split('\t').collect();
+		let [kind, path, permissions, mtime, ref rest @ ..] = fields[..] else {
+			return Err(invalid());
+		};
+		let node_fields = match kind {
+			"file" => 2,
+			"link" | "block" | "char" => 1,
+			_ => 0,
+		};
+		let (rest, security) = rest.split_at(node_fields.min(rest.len()));
+		let (owner, context) = match *security {
+			[] => (None, None),
+			[owner, context] => (
+				match owner {
+					"" => None,
+					owner => Some(numbers(owner)?),
+				},
+				match context {
+					"" => None,
+					context => Some(hex(context)?.into()),
+				},
+			),
+			_ => return Err(invalid()),
+		};
+		let node = match (kind, rest) {
+			("dir", []) => Node::Dir,
+			("file", [size, sha256]) => Node::File {
+				size: size.parse().map_err(|_| invalid())?,
+				sha256: hex(sha256)?,
+			},
+			("link", [target]) => Node::Symlink(hex(target)?.into()),
+			("block", [device]) => {
+				let (major, minor) = numbers(device)?;
+				Node::Special(Special::BlockDevice { major, minor })
+			}
+			("char", [device]) => {
+				let (major, minor) = numbers(device)?;
+				Node::Special(Special::CharDevice { major, minor })
+			}
+			("fifo", []) => Node::Special(Special::Fifo),
+			("socket", []) => Node::Special(Special::Socket),
+			_ => return Err(invalid()),
+		};
+		Ok(Self {
+			path: hex(path)?.into(),
+			permissions: u32::from_str_radix(permissions, 8).map_err(|_| invalid())?,
+			mtime: mtime.parse().map_err(|_| invalid())?,
+			node,
+			owner,
+			context,
+		})
+	}
+
+	fn metadata(&self) -> Metadata<'_> {
+		Metadata {
+			uid: self.owner.map(|(uid, _)| uid),
+			gid: self.owner.map(|(_, gid)| gid),
+			context: self.context.as_deref(),
+			..Metadata::new(&self.path, self.permissions, self.mtime)
+		}
+	}
+}
+
+/// Reads the entries of the tree volume at `path`.
+pub fn read_tree(path: &Path) -> Result<Vec<Entry>, Error> {
+	BufReader::new(File::open(path)?)
+		.lines()
+		.map(|line| Entry::parse(&line?))
+		.collect()
+}
+
+/// [`archive::read_entries`] for tree volumes, with file contents read from the store's blobs.
+pub(crate) fn read(
+	path: &Path,
+	mut f: impl FnMut(&Metadata, EntryKind, &mut dyn Read) -> Result<(), Error>,
+) -> Result<(), Error> {
+	let store = store_of(path)?;
+	for entry in read_tree(path)? {
+		let metadata = entry.metadata();
+		match &entry.node {
+			Node::Dir => f(&metadata, EntryKind::Directory, &mut io::empty())?,
+			Node::File { sha256, .. } => f(
+				&metadata,
+				EntryKind::File,
+				&mut File::open(blob_path(store, sha256))?,
+			)?,
+			Node::Symlink(target) => f(&metadata, EntryKind::Symlink, &mut &****target)?,
+			Node::Special(_) => f(&metadata, EntryKind::Other, &mut io::empty())?,
+		}
+	}
+	Ok(())
+}
+
+/// Writes a tree volume, adding file contents to the store it's in.
+pub(crate) struct Tree {
+	lines: BufWriter<File>,
+	store: PathBuf,
+	/// The file currently being written.
+	pending: Option<Pending>,
+}
+
+struct Pending {
+	entry: Entry,
+	blob: File,
+	blob_path: PathBuf,
+	hasher: Sha256,
+	written: u64,
+}
+
+impl Write for Pending {
+	fn write(&mut self, buf: &[u8]) -> Result<usize, Error> {
+		let written = self.blob.write(buf)?;
+		self.hasher.update(&buf[..written]);
+		self.written += written as u64;
+		Ok(written)
+	}
+
+	fn flush(&mut self) -> Result<(), Error> {
+		self.blob.flush()
+	}
+}
+
+impl Tree {
+	pub(crate) fn new(path: &Path, tree: File) -> Result<Self, Error> {
+		let store = store_of(path)?.to_owned();
+		fs::create_dir_all(store.join(BLOBS_DIR_NAME))?;
+		Ok(Self {
+			lines: BufWriter::new(tree),
+			store,
+			pending: None,
+		})
+	}
+
+	fn add(&mut self, entry: &Entry) -> Result<(), Error> {
+		self.end_file()?;
+		writeln!(self.lines, "{}", entry.to_line())
+	}
+
+	/// Moves the current file's contents into place as a blob, unless it's already there.
+	fn end_file(&mut self) -> Result<(), Error> {
+		let Some(mut pending) = self.pending.take() else {
+			return Ok(());
+		};
+		let Node::File { size, sha256 } = &mut pending.entry.node else {
+			unreachable!()
+		};
+		if pending.written != *size {
+			return Err(Error::new(
+				ErrorKind::InvalidData,
+				AnError(format!(
+					"Wrote {} bytes for a file of {} bytes",
+					pending.written, size
+				)),
+			));
+		}
+		pending.blob.sync_all()?;
+		*sha256 = pending.hasher.finalize().to_vec();
+		let blob = blob_path(&self.store, sha256);
+		if blob.exists() {
+			fs::remove_file(&pending.blob_path)?;
+		} else {
+			fs::create_dir_all(blob.parent().unwrap())?;
+			fs::rename(&pending.blob_path, blob)?;
+		}
+		writeln!(self.lines, "{}", pending.entry.to_line())
+	}
+}
+
+impl archive::Writer for Tree {
+	fn add_directory(&mut self, metadata: &Metadata) -> Result<(), Error> {
+		self.add(&entry(metadata, Node::Dir))
+	}
+
+	fn start_file(&mut self, metadata: &Metadata, size: u64) -> Result<&mut dyn Write, Error> {
+		self.end_file()?;
+		let blob_path = self
+			.store
+			.join(BLOBS_DIR_NAME)
+			.join(format!("partial.{}", std::process::id()));
+		let blob = OpenOptions::new()
+			.create(true)
+			.truncate(true)
+			.write(true)
+			.open(&blob_path)?;
+		Ok(self.pending.insert(Pending {
+			entry: entry(
+				metadata,
+				Node::File {
+					size,
+					sha256: Vec::new(),
+				},
+			),
+			blob,
+			blob_path,
+			hasher: Sha256::new(),
+			written: 0,
+		}))
+	}
+
+	fn add_symlink(&mut self, metadata: &Metadata, target: &RawPath) -> Result<(), Error> {
+		self.add(&entry(metadata, Node::Symlink(target.to_owned())))
+	}
+
+	fn add_special(&mut self, metadata: &Metadata, special: Special) -> Result<bool, Error> {
+		self.add(&entry(metadata, Node::Special(special)))?;
+		Ok(true)
+	}
+
+	fn finish(mut self: Box<Self>) -> Result<(), Error> {
+		self.end_file()?;
+		self.lines
+			.into_inner()
+			.map_err(io::IntoInnerError::into_error)?
+			.sync_all()
+	}
+}
+
+fn entry(metadata: &Metadata, node: Node) -> Entry {
+	Entry {
+		path: metadata.path.to_owned(),
+		permissions: metadata.permissions,
+		mtime: metadata.mtime,
+		node,
+		owner: metadata.uid.zip(metadata.gid),
+		context: metadata.context.map(ToOwned::to_owned),
+	}
+}
+
+#[derive(Debug)]
+pub struct MaterializeOptions {
+	pub store: PathBuf,
+	pub snapshot: String,
+	/// Where to write the volumes or files.
+	pub target: PathBuf,
+	/// Write one archive volume of this format per tree volume, or plain files if [`None`].
+	pub format: Option<Format>,
+	/// `-1` only prints errors, `1` and above list every volume.
+	pub verbosity: i8,
+}
+
+/// Writes a snapshot back out as archive volumes or into a directory.
+///
+/// Volumes keep their names and contents, including the lists the dump added.
+/// Plain files are written without those lists, and without special files or (outside Unix) symlinks.
+pub fn materialize(options: &MaterializeOptions) -> Result<(), Error> {
+	let dir = snapshot_dir(&options.store, &options.snapshot);
+	if !dir.is_dir() {
+		return Err(Error::new(
+			ErrorKind::NotFound,
+			AnError(format!("No snapshot {:?} in the store", options.snapshot)),
+		));
+	}
+	fs::create_dir_all(&options.target)?;
+	for volume in archive::volumes(&dir)? {
+		if options.verbosity >= 1 {
+			println!("volume {}", volume.path.display());
+		}
+		let entries = read_tree(&volume.path)?;
+		match options.format {
+			Some(format) => {
+				let path = options.target.join(format!(
+					"{}.{}.{}",
+					volume.set,
+					volume.number,
+					format.extension()
+				));
+				write_archive(&options.store, format.create(&path)?, &entries)?;
+			}
+			None => write_files(&options.store, &options.target, &entries, options.verbosity)?,
+		}
+	}
+	Ok(())
+}
+
+fn write_archive(
+	store: &Path,
+	mut writer: Box<dyn archive::Writer>,
+	entries: &[Entry],
+) -> Result<(), Error> {
+	let mut specials = Vec::new();
+	for entry in entries {
+		let metadata = entry.metadata();
+		match &entry.node {
+			Node::Dir => writer.add_directory(&metadata)?,
+			Node::File { size, sha256 } => {
+				let mut blob = File::open(blob_path(store, sha256))?;
+				io::copy(&mut blob, writer.start_file(&metadata, *size)?)?;
+			}
+			Node::Symlink(target) => writer.add_symlink(&metadata, target)?,
+			Node::Special(special) => {
+				if !writer.add_special(&metadata, *special)? {
+					specials.push(dump::special_line(
+						&metadata,
+						special.kind(),
+						special.numbers(),
+					));
+				}
+			}
+		}
+	}
+	if !specials.is_empty() {
+		let mut text = specials.join("\n");
+		text.push('\n');
+		let metadata = Metadata::new(RawPath::new(SPECIAL_FILE_NAME), 0o644, 0);
+		writer.add_file(&metadata, text.as_bytes())?;
+	}
+	writer.finish()
+}
+
+fn write_files(store: &Path, target: &Path, entries: &[Entry], verbosity: i8) -> Result<(), Error> {
+	for entry in entries {
+		let path = local_path(target, &entry.path)?;
+		match &entry.node {
+			Node::Dir => fs::create_dir_all(&path)?,
+			Node::File { .. } if dump::is_manifest(&entry.path) => (),
+			Node::File { sha256, .. } => {
+				if let Some(parent) = path.parent() {
+					fs::create_dir_all(parent)?;
+				}
+				fs::copy(blob_path(store, sha256), &path)?;
+				set_permissions(&path, entry.permissions)?;
+			}
+			#[cfg(unix)]
+			Node::Symlink(link) => {
+				let _ = fs::remove_file(&path);
+				std::os::unix::fs::symlink(OsString::try_from(&***link)?, &path)?;
+			}
+			_ => {
+				if verbosity >= 0 {
+					eprintln!("Skipping {:?}", entry.path);
+				}
+			}
+		}
+	}
+	Ok(())
+}
+
+/// `path` below `target`, refusing anything that would end up outside of it.
+fn local_path(target: &Path, path: &RawPath) -> Result<PathBuf, Error> {
+	let relative = PathBuf::from(OsString::try_from(&**path)?);
+	if !relative
+		.components()
+		.all(|component| matches!(component, Component::Normal(_)))
+	{
+		return Err(Error::new(
+			ErrorKind::InvalidData,
+			AnError(format!("Refusing to write outside the target: {:?}", path)),
+		));
+	}
+	Ok(target.join(relative))
+}
+
+#[cfg(unix)]
+fn set_permissions(path: &Path, permissions: u32) -> Result<(), Error> {
+	use std::os::unix::fs::PermissionsExt;
+	fs::set_permissions(path, fs::Permissions::from_mode(permissions))
+}
+
+#[cfg(not(unix))]
+fn set_permissions(_: &Path, _: u32) -> Result<(), Error> {
+	Ok(())
+}
@@ -0,0 +1,389 @@
+//! The binary SYNC sub-protocol, as spoken after requesting the `sync:` service.
+//!
+//! Every request is a four-byte ID followed by a little-endian `u32` length and that many bytes of payload.
+//! Responses share the ID/`u32` framing, but their layout depends on the request.
+
+use crate::{
+	client::{AdbClient, Connection},
+	error::AdbError,
+	AnError, Epoch, LsEntry, RawPath, RawPathBuf, RawStr, RawString, SerialNumber, UnixMode,
+};
+use std::{
+	convert::{TryFrom, TryInto},
+	io::{Error, ErrorKind, Read, Write},
+};
+
+/// The largest payload of a single `DATA` frame.
+pub const MAX_DATA: usize = 64 * 1024;
+
+impl AdbClient {
+	pub fn sync(&self, serial_number: &SerialNumber) -> Result<SyncConnection, Error> {
+		self.open(serial_number, "sync:").map(SyncConnection)
+	}
+}
+
+/// The result of a legacy `STAT` request.
+///
+/// All fields are zero if the path doesn't exist.
+#[derive(Debug)]
+pub struct Stat {
+	pub mode: UnixMode,
+	pub size: u32,
+	pub epoch: Epoch,
+}
+
+impl Stat {
+	#[must_use]
+	pub fn exists(&self) -> bool {
+		self.mode.to_u32() != 0
+	}
+}
+
+/// The result of a `STA2` or `LST2` request.
+#[derive(Debug)]
+pub struct Stat2 {
+	/// An `errno` value, or `0` on success.
+	pub error: u32,
+	pub dev: u64,
+	pub ino: u64,
+	pub mode: UnixMode,
+	pub nlink: u32,
+	pub uid: u32,
+	pub gid: u32,
+	pub size: u64,
+	pub atime: i64,
+	pub mtime: i64,
+	pub ctime: i64,
+}
+
+impl Stat2 {
+	/// Parses the fields following the ID of a `STA2`, `LST2` or `DNT2` response.
+	fn parse(response: &[u8]) -> Self {
+		Self {
+			error: u32_at(response, 4),
+			dev: u64_at(response, 8),
+			ino: u64_at(response, 16),
+			mode: UnixMode(u32_at(response, 24)),
+			nlink: u32_at(response, 28),
+			uid: u32_at(response, 32),
+			gid: u32_at(response, 36),
+			size: u64_at(response, 40),
+			atime: i64_at(response, 48),
+			mtime: i64_at(response, 56),
+			ctime: i64_at(response, 64),
+		}
+	}
+}
+
+/// One entry of a `LIS2` listing.
+///
+/// The timestamps are in seconds. If the entry couldn't be examined, `stat.error` is set and the other fields are zero.
+#[derive(Debug)]
+pub struct Dent2 {
+	pub stat: Stat2,
+	pub name: RawString,
+}
+
+impl Dent2 {
+	/// Converts the entry of a listing of `dir`, failing if it couldn't be examined.
+	pub fn into_entry(self, dir: &RawPath) -> Result<LsEntry, AdbError> {
+		if self.stat.error != 0 {
+			return Err(AdbError::from_errno(
+				dir.join(self.name.as_str()),
+				self.stat.error,
+			));
+		}
+		Ok(LsEntry {
+			mode: self.stat.mode,
+			size: self.stat.size,
+			mtime: Epoch::from_timestamp(self.stat.mtime),
+			atime: Some(Epoch::from_timestamp(self.stat.atime)),
+			ctime: Some(Epoch::from_timestamp(self.stat.ctime)),
+			uid: Some(self.stat.uid),
+			gid: Some(self.stat.gid),
+			context: None,
+			name: self.name,
+		})
+	}
+}
+
+#[derive(Debug)]
+pub struct SyncConnection(Connection);
+impl SyncConnection {
+	fn request(&mut self, id: [u8; 4], payload: &[u8]) -> Result<(), Error> {
+		let len = u32::try_from(payload.len()).map_err(|_| {
+			Error::new(
+				ErrorKind::InvalidInput,
+				AnError(format!(
+					"Sync request payload too long: {:?}",
+					RawStr::new(payload)
+				)),
+			)
+		})?;
+		self.0.write_all(&id)?;
+		self.0.write_all(&len.to_le_bytes())?;
+		self.0.write_all(payload)
+	}
+
+	pub fn list(&mut self, path: &(impl AsRef<RawPath> + ?Sized)) -> Result<Vec<LsEntry>, Error> {
+		let path = path.as_ref();
+		self.request(*b"LIST", path)?;
+
+		let mut entries = Vec::new();
+		loop {
+			let mut header = [0; 20];
+			// `FAIL` is only followed by the message's length and the message.
+			self.0.read_exact(&mut header[..8])?;
+			if &header[..4] != b"FAIL" {
+				self.0.read_exact(&mut header[8..])?;
+			}
+			match &header[..4] {
+				b"DENT" => {
+					let mut name = vec![0; usize::try_from(u32_at(&header, 16)).unwrap()];
+					self.0.read_exact(&mut name)?;
+					entries.push(LsEntry {
+						mode: UnixMode(u32_at(&header, 4)),
+						size: u64::from(u32_at(&header, 8)),
+						mtime: Epoch::from_timestamp(i64::from(u32_at(&header, 12))),
+						atime: None,
+						ctime: None,
+						uid: None,
+						gid: None,
+						context: None,
+						name: RawString(name),
+					});
+				}
+				b"DONE" => break,
+				b"FAIL" => return Err(fail(&mut self.0, &header[4..8], path)?),
+				id => return Err(unexpected(id, path)),
+			}
+		}
+		Ok(entries)
+	}
+
+	/// Like [`list`](`SyncConnection::list`), but with the 64-bit fields of [`lstat2`](`SyncConnection::lstat2`).
+	///
+	/// Only available if the device advertises the `ls_v2` feature.
+	pub fn list2(&mut self, path: &(impl AsRef<RawPath> + ?Sized)) -> Result<Vec<Dent2>, Error> {
+		let path = path.as_ref();
+		self.request(*b"LIS2", path)?;
+
+		let mut entries = Vec::new();
+		loop {
+			let mut header = [0; 76];
+			// `FAIL` is only followed by the message's length and the message.
+			self.0.read_exact(&mut header[..8])?;
+			if &header[..4] != b"FAIL" {
+				self.0.read_exact(&mut header[8..])?;
+			}
+			match &header[..4] {
+				b"DNT2" => {
+					let mut name = vec![0; usize::try_from(u32_at(&header, 72)).unwrap()];
+					self.0.read_exact(&mut name)?;
+					entries.push(Dent2 {
+						stat: Stat2::parse(&header),
+						name: RawString(name),
+					});
+				}
+				b"DONE" => break,
+				b"FAIL" => return Err(fail(&mut self.0, &header[4..8], path)?),
+				id => return Err(unexpected(id, path)),
+			}
+		}
+		Ok(entries)
+	}
+
+	pub fn stat(&mut self, path: &(impl AsRef<RawPath> + ?Sized)) -> Result<Stat, Error> {
+		let path = path.as_ref();
+		self.request(*b"STAT", path)?;
+
+		let mut response = [0; 16];
+		self.0.read_exact(&mut response)?;
+		match &response[..4] {
+			b"STAT" => Ok(Stat {
+				mode: UnixMode(u32_at(&response, 4)),
+				size: u32_at(&response, 8),
+				epoch: Epoch::from_timestamp(i64::from(u32_at(&response, 12))),
+			}),
+			id => Err(unexpected(id, path)),
+		}
+	}
+
+	/// Like [`stat`](`SyncConnection::stat`), but with 64-bit fields. Follows symlinks.
+	///
+	/// Only available if the device advertises the `stat_v2` feature.
+	pub fn stat2(&mut self, path: &(impl AsRef<RawPath> + ?Sized)) -> Result<Stat2, Error> {
+		self.stat2_impl(*b"STA2", path.as_ref())
+	}
+
+	/// Like [`stat2`](`SyncConnection::stat2`), but doesn't follow symlinks.
+	pub fn lstat2(&mut self, path: &(impl AsRef<RawPath> + ?Sized)) -> Result<Stat2, Error> {
+		self.stat2_impl(*b"LST2", path.as_ref())
+	}
+
+	fn stat2_impl(&mut self, id: [u8; 4], path: &RawPath) -> Result<Stat2, Error> {
+		self.request(id, path)?;
+
+		let mut response = [0; 72];
+		self.0.read_exact(&mut response)?;
+		if response[..4] != id {
+			return Err(unexpected(&response[..4], path));
+		}
+		Ok(Stat2::parse(&response))
+	}
+
+	/// Starts streaming the contents of the file at `path`.
+	///
+	/// The returned [`Recv`] must be read to its end before this connection can be used again.
+	pub fn recv(
+		&mut self,
+		path: &(impl AsRef<RawPath> + ?Sized),
+	) -> Result<Recv<&mut Self>, Error> {
+		self.request(*b"RECV", path.as_ref())?;
+		Ok(Recv::new(self, path.as_ref()))
+	}
+
+	/// Like [`recv`](`SyncConnection::recv`), but consumes the connection.
+	pub fn into_recv(mut self, path: &(impl AsRef<RawPath> + ?Sized)) -> Result<Recv<Self>, Error> {
+		self.request(*b"RECV", path.as_ref())?;
+		Ok(Recv::new(self, path.as_ref()))
+	}
+
+	/// Pushes `data` to `path` on the device, creating or replacing the file there.
+	///
+	/// `mode` should include the file type bits, `mtime` is in seconds since the Unix epoch.
+	pub fn send(
+		&mut self,
+		path: &(impl AsRef<RawPath> + ?Sized),
+		mode: u32,
+		mtime: u32,
+		mut data: impl Read,
+	) -> Result<u64, Error> {
+		let path = path.as_ref();
+		let mut target = path.to_vec();
+		target.extend_from_slice(format!(",{}", mode).as_bytes());
+		self.request(*b"SEND", &target)?;
+
+		let mut buffer = vec![0; MAX_DATA];
+		let mut total = 0;
+		loop {
+			let read = match data.read(&mut buffer) {
+				Ok(0) => break,
+				Ok(read) => read,
+				Err(error) if error.kind() == ErrorKind::Interrupted => continue,
+				Err(error) => return Err(error),
+			};
+			self.request(*b"DATA", &buffer[..read])?;
+			total += read as u64;
+		}
+		self.0.write_all(b"DONE")?;
+		self.0.write_all(&mtime.to_le_bytes())?;
+
+		let mut response = [0; 8];
+		self.0.read_exact(&mut response)?;
+		match &response[..4] {
+			b"OKAY" => Ok(total),
+			b"FAIL" => Err(fail(&mut self.0, &response[4..], path)?),
+			id => Err(unexpected(id, path)),
+		}
+	}
+
+	pub fn quit(mut self) -> Result<(), Error> {
+		self.request(*b"QUIT", &[])
+	}
+}
+
+impl Read for SyncConnection {
+	fn read(&mut self, buf: &mut [u8]) -> Result<usize, Error> {
+		self.0.read(buf)
+	}
+}
+
+/// The contents of a file being received through [`SyncConnection::recv`] or [`SyncConnection::into_recv`].
+///
+/// Reading fails if the device reports an error partway through.
+#[derive(Debug)]
+pub struct Recv<C: Read> {
+	connection: C,
+	path: RawPathBuf,
+	remaining: usize,
+	done: bool,
+}
+
+impl<C: Read> Recv<C> {
+	fn new(connection: C, path: &RawPath) -> Self {
+		Self {
+			connection,
+			path: path.to_owned(),
+			remaining: 0,
+			done: false,
+		}
+	}
+
+	pub fn into_inner(self) -> C {
+		self.connection
+	}
+}
+
+impl<C: Read> Read for Recv<C> {
+	fn read(&mut self, buf: &mut [u8]) -> Result<usize, Error> {
+		if buf.is_empty() {
+			return Ok(0);
+		}
+		while self.remaining == 0 {
+			if self.done {
+				return Ok(0);
+			}
+			let mut header = [0; 8];
+			self.connection.read_exact(&mut header)?;
+			match &header[..4] {
+				b"DATA" => self.remaining = usize::try_from(u32_at(&header, 4)).unwrap(),
+				b"DONE" => self.done = true,
+				b"FAIL" => {
+					self.done = true;
+					return Err(fail(&mut self.connection, &header[4..], &self.path)?);
+				}
+				id => return Err(unexpected(id, &self.path)),
+			}
+		}
+
+		let len = buf.len().min(self.remaining);
+		let read = self.connection.read(&mut buf[..len])?;
+		if read == 0 {
+			return Err(Error::new(
+				ErrorKind::UnexpectedEof,
+				AnError(format!("Connection closed while receiving {:?}", self.path)),
+			));
+		}
+		self.remaining -= read;
+		Ok(read)
+	}
+}
+
+fn u32_at(bytes: &[u8], at: usize) -> u32 {
+	u32::from_le_bytes(bytes[at..at + 4].try_into().unwrap())
+}
+
+fn u64_at(bytes: &[u8], at: usize) -> u64 {
+	u64::from_le_bytes(bytes[at..at + 8].try_into().unwrap())
+}
+
+fn i64_at(bytes: &[u8], at: usize) -> i64 {
+	i64::from_le_bytes(bytes[at..at + 8].try_into().unwrap())
+}
+
+/// Reads the message of a `FAIL` response whose length field was `len`.
+fn fail(connection: &mut impl Read, len: &[u8], path: &RawPath) -> Result<Error, Error> {
+	let mut message = vec![0; usize::try_from(u32_at(len, 0)).unwrap()];
+	connection.read_exact(&mut message)?;
+	Ok(AdbError::from_device_message(path.to_owned(), &String::from_utf8_lossy(&message)).into())
+}
+
+fn unexpected(id: &[u8], path: &RawPath) -> Error {
+	AdbError::Protocol(format!(
+		"Unexpected sync response for {:?}: {:?}",
+		path,
+		RawStr::new(id)
+	))
+	.into()
+}
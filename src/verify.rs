@@ -0,0 +1,346 @@
+//! Compares finished archive volumes against the device they were dumped from.
+//!
+//! Only regular files are compared, first by size and then by content.
+//! Contents are hashed on the device with `sha256sum` or `md5sum` where available, and pulled otherwise.
+//! Entries the dump excluded (see its `IGNORED` markers) aren't reported as missing.
+
+use crate::{
+	archive::{self, EntryKind},
+	dump::{self, Backend},
+	AnError, LsEntry, RawPath, RawPathBuf,
+};
+use md5::Md5;
+use sha2::{Digest, Sha256};
+use std::{
+	collections::{BTreeMap, BTreeSet},
+	fmt::{self, Display, Formatter},
+	io::{self, Error, ErrorKind, Write},
+	path::{Path, PathBuf},
+};
+
+/// A checksum tool the device may have.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Algorithm {
+	Sha256,
+	Md5,
+}
+
+impl Algorithm {
+	/// In order of preference.
+	pub const ALL: &'static [Self] = &[Self::Sha256, Self::Md5];
+
+	/// The device command printing this kind of digest.
+	#[must_use]
+	pub fn command(self) -> &'static str {
+		match self {
+			Self::Sha256 => "sha256sum",
+			Self::Md5 => "md5sum",
+		}
+	}
+
+	/// The length of a digest in bytes.
+	#[must_use]
+	#[allow(clippy::len_without_is_empty)]
+	pub fn len(self) -> usize {
+		match self {
+			Self::Sha256 => 32,
+			Self::Md5 => 16,
+		}
+	}
+}
+
+#[derive(Debug)]
+pub struct Options {
+	/// Where the archive volumes are. All of them are read, with later volumes of a set taking precedence.
+	pub output_dir: PathBuf,
+	/// The device directory archive entry paths are relative to.
+	pub root: RawPathBuf,
+	/// `-1` only prints errors, `1` and above list every file.
+	pub verbosity: i8,
+}
+
+#[derive(Debug)]
+pub enum Problem {
+	/// On the device, but not in the archives.
+	Missing,
+	/// In the archives, but not on the device.
+	Extra,
+	Size {
+		archived: u64,
+		device: u64,
+	},
+	Content,
+	/// The device side couldn't be checked.
+	Error(Error),
+}
+
+#[derive(Debug)]
+pub struct Finding {
+	/// The path on the device.
+	pub path: RawPathBuf,
+	pub problem: Problem,
+}
+
+impl Display for Finding {
+	/// One tab-separated line of `kind`, quoted path and details.
+	fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+		let path = self.path.encode_lossless();
+		match &self.problem {
+			Problem::Missing => write!(f, "missing\t{:?}", path),
+			Problem::Extra => write!(f, "extra\t{:?}", path),
+			Problem::Size { archived, device } => {
+				write!(f, "size\t{:?}\t{}\t{}", path, archived, device)
+			}
+			Problem::Content => write!(f, "content\t{:?}", path),
+			Problem::Error(error) => write!(f, "error\t{:?}\t{}", path, error),
+		}
+	}
+}
+
+#[derive(Debug)]
+pub struct Report {
+	/// The number of archived files that were compared.
+	pub checked: usize,
+	pub findings: Vec<Finding>,
+}
+
+impl Report {
+	#[must_use]
+	pub fn is_ok(&self) -> bool {
+		self.findings.is_empty()
+	}
+}
+
+struct Archived {
+	size: u64,
+	sha256: Vec<u8>,
+	md5: Vec<u8>,
+}
+
+impl Archived {
+	fn digest(&self, algorithm: Algorithm) -> &[u8] {
+		match algorithm {
+			Algorithm::Sha256 => &self.sha256,
+			Algorithm::Md5 => &self.md5,
+		}
+	}
+}
+
+/// What the archive volumes contain, by path relative to the archive root.
+#[derive(Default)]
+struct Index {
+	files: BTreeMap<RawPathBuf, Archived>,
+	/// Directories, symlinks and everything an `IGNORED` marker stands for.
+	present: BTreeSet<RawPathBuf>,
+	/// Directories whose contents were dumped, so they are listed on the device.
+	listed: BTreeSet<RawPathBuf>,
+	/// Directories that were excluded as a whole.
+	ignored_dirs: BTreeSet<RawPathBuf>,
+}
+
+/// Verifies the archive volumes in `options.output_dir` against `backend`.
+///
+/// Problems with individual entries end up in the [`Report`], while errors reading the archives abort.
+pub fn run(backend: &dyn Backend, options: &Options) -> Result<Report, Error> {
+	let index = index(&options.output_dir)?;
+	let mut report = Report {
+		checked: 0,
+		findings: Vec::new(),
+	};
+	let algorithm = probe(backend)?;
+	let mut seen = BTreeSet::new();
+
+	for dir in &index.listed {
+		let device_dir = options.root.join(&**dir);
+		let entries = match backend.ls(&device_dir) {
+			Ok(entries) => entries,
+			Err(error) => {
+				report.findings.push(Finding {
+					path: device_dir,
+					problem: Problem::Error(error),
+				});
+				continue;
+			}
+		};
+		for entry in entries {
+			if entry.name == "." || entry.name == ".." {
+				continue;
+			}
+			let relative = if dir.is_empty() {
+				entry.name.to_vec().into()
+			} else {
+				dir.join(entry.name.as_str())
+			};
+			let path = options.root.join(&*relative);
+			if entry.mode.is_dir() {
+				if !index.present.contains(&relative) && !index.listed.contains(&relative) {
+					report.findings.push(Finding {
+						path,
+						problem: Problem::Missing,
+					});
+				}
+			} else if entry.mode.is_file() {
+				seen.insert(relative.clone());
+				if let Some(archived) = index.files.get(&relative) {
+					if options.verbosity >= 1 {
+						println!("file {:?}", &path);
+					}
+					report.checked += 1;
+					if let Some(problem) = compare(backend, algorithm, &path, &entry, archived) {
+						report.findings.push(Finding { path, problem });
+					}
+				} else if !index.present.contains(&relative) {
+					report.findings.push(Finding {
+						path,
+						problem: Problem::Missing,
+					});
+				}
+			}
+		}
+	}
+
+	for relative in index.files.keys() {
+		if !seen.contains(relative) {
+			report.findings.push(Finding {
+				path: options.root.join(&**relative),
+				problem: Problem::Extra,
+			});
+		}
+	}
+	Ok(report)
+}
+
+/// Reads all volumes in `dir`, ordered by set name and number.
+fn index(dir: &Path) -> Result<Index, Error> {
+	let mut index = Index::default();
+	let mut parents = BTreeSet::new();
+	for volume in archive::volumes(dir)? {
+		archive::read(&volume.path, |path, kind, data| {
+			if dump::is_manifest(path) {
+				return Ok(());
+			}
+			parents.insert(parent(path));
+			match kind {
+				EntryKind::File if dump::is_marker(path) => {
+					if let Some(excluded) = path.strip_suffix(b".IGNORED") {
+						index.present.insert(excluded.to_vec().into());
+					} else {
+						let dir = parent(path);
+						index.present.insert(dir.clone());
+						index.ignored_dirs.insert(dir);
+					}
+				}
+				EntryKind::File => {
+					let mut hasher = Hasher::default();
+					let size = io::copy(data, &mut hasher)?;
+					index.files.insert(
+						path.to_owned(),
+						Archived {
+							size,
+							sha256: hasher.sha256.finalize().to_vec(),
+							md5: hasher.md5.finalize().to_vec(),
+						},
+					);
+				}
+				EntryKind::Directory => {
+					index.present.insert(path.to_owned());
+					index.listed.insert(path.to_owned());
+				}
+				EntryKind::Symlink | EntryKind::Other => {
+					index.present.insert(path.to_owned());
+				}
+			}
+			Ok(())
+		})?;
+	}
+
+	// Directories that only appear as the parent of other entries, like the path a non-split dump started at.
+	// Their own parents aren't listed, since they usually hold much more than what was dumped.
+	for dir in parents {
+		if !index.ignored_dirs.contains(&dir) {
+			index.listed.insert(dir);
+		}
+	}
+	for dir in &index.ignored_dirs {
+		index.listed.remove(dir);
+	}
+	Ok(index)
+}
+
+/// The path of the directory containing `path`, without trailing `/`.
+fn parent(path: &RawPath) -> RawPathBuf {
+	match path.directory() {
+		Some(dir) => {
+			let dir: &[u8] = dir;
+			dir[..dir.len() - 1].to_vec().into()
+		}
+		None => "".into(),
+	}
+}
+
+fn compare(
+	backend: &dyn Backend,
+	algorithm: Option<Algorithm>,
+	path: &RawPath,
+	entry: &LsEntry,
+	archived: &Archived,
+) -> Option<Problem> {
+	if entry.size != archived.size {
+		return Some(Problem::Size {
+			archived: archived.size,
+			device: entry.size,
+		});
+	}
+
+	let result = if let Some(algorithm) = algorithm {
+		match backend.checksum(path, algorithm) {
+			Ok(Some(digest)) => Ok(digest == archived.digest(algorithm)),
+			Ok(None) => Err(Error::new(
+				ErrorKind::Other,
+				AnError(format!("`{}` failed", algorithm.command())),
+			)),
+			Err(error) => Err(error),
+		}
+	} else {
+		let mut hasher = Hasher::default();
+		backend
+			.pull_to(path, entry.size, &mut hasher)
+			.map(|_| hasher.sha256.finalize().as_slice() == archived.sha256)
+	};
+	match result {
+		Ok(true) => None,
+		Ok(false) => Some(Problem::Content),
+		Err(error) => Some(Problem::Error(error)),
+	}
+}
+
+/// Finds the first [`Algorithm`] the backend supports, by hashing `/dev/null`.
+fn probe(backend: &dyn Backend) -> Result<Option<Algorithm>, Error> {
+	for &algorithm in Algorithm::ALL {
+		if backend
+			.checksum(RawPath::new("/dev/null"), algorithm)?
+			.is_some()
+		{
+			return Ok(Some(algorithm));
+		}
+	}
+	Ok(None)
+}
+
+#[derive(Default)]
+struct Hasher {
+	sha256: Sha256,
+	md5: Md5,
+}
+
+impl Write for Hasher {
+	fn write(&mut self, buf: &[u8]) -> Result<usize, Error> {
+		self.sha256.update(buf);
+		self.md5.update(buf);
+		Ok(buf.len())
+	}
+
+	fn flush(&mut self) -> Result<(), Error> {
+		Ok(())
+	}
+}
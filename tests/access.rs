@@ -0,0 +1,203 @@
+#![cfg(not(miri))]
+
+#[path = "fake_adb_.rs"]
+mod fake_adb;
+use adb_dump::{
+	access::{Access, RootStrategy, Su},
+	archive::{Format, Metadata},
+	error::AdbError,
+	restore, RawPath,
+};
+use fake_adb::{FakeAdb, Node, Shell};
+use std::{fs, io::Read};
+
+fn adb(shell: Shell) -> FakeAdb {
+	let adb = FakeAdb::start("fake-serial");
+	adb.dir("/data", 1)
+		.dir("/data/data", 2)
+		.file("/data/data/it's.db", 3, b"secret")
+		.dir("/data/data/sub", 4)
+		.file("/sdcard/public.txt", 5, b"public");
+	*adb.shell.lock().unwrap() = shell;
+	adb
+}
+
+fn pull(adb: &FakeAdb, path: &str, size: u64) -> Result<Vec<u8>, std::io::Error> {
+	let mut data = Vec::new();
+	adb.device()
+		.pull_reader(path, size)?
+		.read_to_end(&mut data)?;
+	Ok(data)
+}
+
+#[test]
+fn already_root() {
+	let adb = adb(Shell::default());
+	assert_eq!(
+		adb.device().gain_root(RootStrategy::Never).unwrap(),
+		Access::Root
+	);
+
+	adb.shell.lock().unwrap().twrp = Some("3.7.0_12-0");
+	assert_eq!(
+		adb.device().gain_root(RootStrategy::Auto).unwrap(),
+		Access::Twrp("3.7.0_12-0".to_string())
+	);
+}
+
+#[test]
+fn adb_root() {
+	let adb = adb(Shell {
+		uid: 2000,
+		adb_root: true,
+		su: Some("su -c "),
+		..Shell::default()
+	});
+	assert!(pull(&adb, "/data/data/it's.db", 6).is_err());
+
+	let device = adb.device();
+	assert_eq!(
+		device.gain_root(RootStrategy::Auto).unwrap(),
+		Access::AdbRoot
+	);
+	assert_eq!(pull(&adb, "/data/data/it's.db", 6).unwrap(), b"secret");
+	assert_eq!(device.gain_root(RootStrategy::Auto).unwrap(), Access::Root);
+}
+
+#[test]
+fn su() {
+	for (su, expected) in [("su -c ", Su::DashC), ("su 0 sh -c ", Su::Uid)] {
+		let adb = adb(Shell {
+			uid: 2000,
+			adb_root: false,
+			su: Some(su),
+			..Shell::default()
+		});
+		let device = adb.device();
+		assert_eq!(device.ls("/data/data").unwrap().count(), 0);
+
+		let access = device.gain_root(RootStrategy::Auto).unwrap();
+		assert_eq!(access, Access::Su(expected));
+		assert!(access.is_root());
+
+		let entries: Vec<_> = device.ls("/data/data").unwrap().collect();
+		assert_eq!(entries.len(), 2);
+		assert_eq!(entries[0].name, "it's.db");
+		assert!(entries[0].mode.is_file());
+		assert_eq!(entries[0].size, 6);
+		assert_eq!(entries[0].mtime.timestamp(), 3);
+		assert_eq!((entries[0].uid, entries[0].gid), (Some(1000), Some(1000)));
+		assert_eq!(entries[1].name, "sub");
+		assert!(entries[1].mode.is_dir());
+
+		let mut data = Vec::new();
+		device
+			.pull_reader("/data/data/it's.db", 6)
+			.unwrap()
+			.read_to_end(&mut data)
+			.unwrap();
+		assert_eq!(data, b"secret");
+
+		let error = device.ls("/data/missing").map(|_| ()).unwrap_err();
+		assert!(
+			matches!(AdbError::of(&error), Some(AdbError::NotFound { path }) if path.to_string_panicky() == "/data/missing"),
+			"{:?}",
+			error
+		);
+	}
+}
+
+#[test]
+fn shell_only() {
+	let adb = adb(Shell {
+		uid: 2000,
+		adb_root: true,
+		su: Some("su -c "),
+		..Shell::default()
+	});
+	let access = adb.device().gain_root(RootStrategy::Never).unwrap();
+	assert_eq!(access, Access::Shell);
+	assert!(!access.is_root());
+
+	adb.shell.lock().unwrap().adb_root = false;
+	let access = adb.device().gain_root(RootStrategy::AdbRoot).unwrap();
+	assert_eq!(access, Access::Shell);
+
+	adb.shell.lock().unwrap().su = None;
+	let access = adb.device().gain_root(RootStrategy::Auto).unwrap();
+	assert_eq!(access, Access::Shell);
+	let error = pull(&adb, "/data/data/it's.db", 6).unwrap_err();
+	assert!(
+		matches!(
+			AdbError::of(&error),
+			Some(AdbError::PermissionDenied { .. })
+		),
+		"{:?}",
+		error
+	);
+	assert_eq!(pull(&adb, "/sdcard/public.txt", 6).unwrap(), b"public");
+}
+
+#[test]
+fn restore_with_su() {
+	let adb = adb(Shell {
+		uid: 2000,
+		adb_root: false,
+		su: Some("su -c "),
+		..Shell::default()
+	});
+	let device = adb.device();
+	assert_eq!(
+		device.gain_root(RootStrategy::Auto).unwrap(),
+		Access::Su(Su::DashC)
+	);
+
+	let dir = std::env::temp_dir().join(format!(
+		"adb-dump-test-{}-access-restore_with_su",
+		std::process::id()
+	));
+	let _ = fs::remove_dir_all(&dir);
+	fs::create_dir_all(&dir).unwrap();
+	let mut writer = Format::Tar.create(&dir.join("backup.1.tar")).unwrap();
+	for (path, data) in [
+		("data/data/it's.db", &b"overwritten"[..]),
+		("data/data/new/file.db", b"new"),
+	] {
+		writer
+			.start_file(
+				&Metadata::new(RawPath::new(path), 0o600, 1_600_000_000),
+				data.len() as u64,
+			)
+			.unwrap()
+			.write_all(data)
+			.unwrap();
+	}
+	writer.finish().unwrap();
+
+	let report = restore::run(
+		&device,
+		&restore::Options {
+			output_dirs: vec![dir],
+			root: "/".into(),
+			remap: Vec::new(),
+			only: Vec::new(),
+			overwrite: false,
+			security: false,
+			verbosity: -1,
+		},
+	)
+	.unwrap();
+	let findings: Vec<_> = report.findings.iter().map(ToString::to_string).collect();
+	assert_eq!(findings, ["exists\t\"/data/data/it's.db\""]);
+	assert_eq!(report.restored, 1);
+
+	let files = adb.files.lock().unwrap();
+	assert!(matches!(
+		files.get(&b"/data/data/it's.db"[..]),
+		Some(Node::File { data, .. }) if data == b"secret"
+	));
+	assert!(matches!(
+		files.get(&b"/data/data/new/file.db"[..]),
+		Some(Node::File { mode: 0o100_600, mtime: 1_600_000_000, data }) if data == b"new"
+	));
+}
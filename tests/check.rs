@@ -0,0 +1,171 @@
+#![cfg(not(miri))]
+
+use adb_dump::{
+	archive::Format,
+	check,
+	dump::{self, MANIFEST_FILE_NAME},
+	rules::Rules,
+};
+use std::{
+	fs::{self, File},
+	io::Read,
+	path::Path,
+};
+
+#[path = "fake_backend_.rs"]
+mod fake_backend;
+use fake_backend::{dump_options, output_dir, FakeBackend};
+
+fn dump(dir: &Path, format: Format) {
+	dump::run(
+		&FakeBackend::new()
+			.dir("/sdcard")
+			.dir("/sdcard/a")
+			.file("/sdcard/a/1", b"one")
+			.file("/sdcard/a/2", b"two")
+			.file("/sdcard/3", b"three"),
+		&Rules::new(),
+		&dump::Options {
+			split_size: 4,
+			format,
+			..dump_options(dir)
+		},
+	)
+	.unwrap();
+}
+
+fn check(dir: &Path) -> check::Report {
+	check::run(&check::Options {
+		output_dir: dir.to_owned(),
+		verbosity: -1,
+	})
+	.unwrap()
+}
+
+fn findings(report: &check::Report) -> Vec<String> {
+	report.findings.iter().map(ToString::to_string).collect()
+}
+
+/// Rewrites the uncompressed TAR volume `name` in `dir`, passing each file through `edit` and dropping directories.
+fn rewrite(
+	dir: &Path,
+	name: &str,
+	mut edit: impl FnMut(&str, Vec<u8>) -> Option<Vec<u8>>,
+	extra: &[(&str, &[u8])],
+) {
+	let path = dir.join(name);
+	let mut files = Vec::new();
+	for entry in tar::Archive::new(File::open(&path).unwrap())
+		.entries()
+		.unwrap()
+	{
+		let mut entry = entry.unwrap();
+		if !entry.header().entry_type().is_file() {
+			continue;
+		}
+		let entry_path = entry.path().unwrap().to_string_lossy().into_owned();
+		let mut data = Vec::new();
+		entry.read_to_end(&mut data).unwrap();
+		if let Some(data) = edit(&entry_path, data) {
+			files.push((entry_path, data));
+		}
+	}
+	files.extend(
+		extra
+			.iter()
+			.map(|(path, data)| ((*path).to_string(), data.to_vec())),
+	);
+
+	let mut builder = tar::Builder::new(File::create(&path).unwrap());
+	for (entry_path, data) in files {
+		let mut header = tar::Header::new_gnu();
+		header.set_size(data.len() as u64);
+		header.set_mode(0o644);
+		header.set_cksum();
+		builder
+			.append_data(&mut header, entry_path, &data[..])
+			.unwrap();
+	}
+	builder.finish().unwrap();
+}
+
+#[test]
+fn intact() {
+	for format in [Format::Zip, Format::Tar] {
+		let dir = output_dir(&format!("check-intact-{}", format.extension()));
+		dump(&dir, format);
+		let report = check(&dir);
+		assert_eq!(findings(&report), Vec::<String>::new());
+		assert_eq!(report.checked, 3);
+		assert_eq!(report.volumes, 3);
+	}
+}
+
+#[test]
+fn manifest() {
+	let dir = output_dir("check-manifest");
+	dump(&dir, Format::Tar);
+	let mut manifest = String::new();
+	tar::Archive::new(File::open(dir.join("backup.1.tar")).unwrap())
+		.entries()
+		.unwrap()
+		.map(Result::unwrap)
+		.find(|entry| &*entry.path().unwrap() == Path::new(MANIFEST_FILE_NAME))
+		.unwrap()
+		.read_to_string(&mut manifest)
+		.unwrap();
+	assert_eq!(
+		manifest,
+		format!(
+			"{}\t5\t644\t1600000000\t{}\t1\n",
+			hex::encode("sdcard/3"),
+			"8b5b9db0c13db24256c829aa364aa90c6d2eba318b9232a4ab9313b954d3555f",
+		)
+	);
+}
+
+#[test]
+fn tampered() {
+	let dir = output_dir("check-tampered");
+	dump(&dir, Format::Tar);
+	rewrite(
+		&dir,
+		"backup.1.tar",
+		|path, data| match path {
+			"sdcard/3" => Some(b"THREE".to_vec()),
+			_ => Some(data),
+		},
+		&[("sdcard/4", b"four")],
+	);
+	rewrite(
+		&dir,
+		"backup.2.tar",
+		|path, data| match path {
+			"sdcard/a/1" => None,
+			_ => Some(data),
+		},
+		&[],
+	);
+	rewrite(
+		&dir,
+		"backup.3.tar",
+		|path, data| match path {
+			MANIFEST_FILE_NAME => None,
+			_ => Some(data),
+		},
+		&[],
+	);
+	fs::write(dir.join("backup.4.tar"), b"not a tar").unwrap();
+
+	let report = check(&dir);
+	assert_eq!(
+		findings(&report),
+		[
+			"content\tbackup.1.tar\t\"sdcard/3\"",
+			"unlisted\tbackup.1.tar\t\"sdcard/4\"",
+			"missing\tbackup.2.tar\t\"sdcard/a/1\"",
+			"no-manifest\tbackup.3.tar",
+			"unreadable\tbackup.4.tar\tfailed to read entire block",
+		]
+	);
+}
@@ -0,0 +1,76 @@
+//! The binary's argument validation and exit status.
+
+#![cfg(not(miri))]
+
+#[path = "fake_adb_.rs"]
+mod fake_adb;
+use fake_adb::FakeAdb;
+use std::process::Command;
+
+fn run(args: &[&str]) -> (bool, String) {
+	let output = Command::new(env!("CARGO_BIN_EXE_adb-dump"))
+		.args(args)
+		.output()
+		.unwrap();
+	(
+		output.status.success(),
+		String::from_utf8(output.stderr).unwrap(),
+	)
+}
+
+#[test]
+fn retry_backoff() {
+	for backoff in ["-1", "NaN", "inf", "1e300"] {
+		let (success, stderr) = run(&[
+			&format!("--retry-backoff={}", backoff),
+			"--on-error=retry",
+			"/sdcard",
+		]);
+		assert!(!success);
+		assert!(
+			stderr.contains("Expected a non-negative number of seconds"),
+			"{}",
+			stderr
+		);
+	}
+}
+
+#[test]
+fn failing_device() {
+	let adb = FakeAdb::start("fake-serial");
+	adb.file("/sdcard/a.txt", 1, b"contents");
+	let output_dir = std::env::temp_dir().join(format!("adb-dump-test-{}-cli", std::process::id()));
+	let _ = std::fs::remove_dir_all(&output_dir);
+
+	// The unknown device fails first, but the other one is still dumped.
+	let output = Command::new(env!("CARGO_BIN_EXE_adb-dump"))
+		.env(
+			"ANDROID_ADB_SERVER_PORT",
+			adb.client.addr().port().to_string(),
+		)
+		.args([
+			"--serial",
+			"missing-serial",
+			"--serial",
+			"fake-serial",
+			"-o",
+		])
+		.arg(&output_dir)
+		.arg("/sdcard")
+		.output()
+		.unwrap();
+	let stderr = String::from_utf8(output.stderr).unwrap();
+	assert!(!output.status.success(), "{}", stderr);
+	assert!(
+		stderr.contains("1 of 2 devices could not be dumped"),
+		"{}",
+		stderr
+	);
+	assert!(output_dir
+		.join("fake-serial")
+		.read_dir()
+		.unwrap()
+		.next()
+		.is_some());
+	std::fs::remove_dir_all(&output_dir).unwrap();
+}
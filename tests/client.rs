@@ -0,0 +1,218 @@
+#![cfg(not(miri))]
+
+#[path = "fake_adb_.rs"]
+mod fake_adb;
+use adb_dump::{device::DeviceState, error::AdbError, Epoch, SerialNumber};
+use fake_adb::FakeAdb;
+use std::io::{ErrorKind, Read};
+
+#[test]
+fn get_serialno() {
+	let adb = FakeAdb::start("fake-serial");
+	let serial_number = adb.client.get_serialno().unwrap();
+	assert_eq!(format!("{:?}", serial_number), r#""fake-serial""#);
+}
+
+#[test]
+fn devices() {
+	let adb = FakeAdb::start("fake-serial");
+	adb.other_devices.lock().unwrap().extend(vec![
+		"192.168.0.2:5555       offline transport_id:7".to_string(),
+		"0123456789ABCDEF       no permissions (missing udev rules? user is in the plugdev group); see [http://developer.android.com/tools/device.html] usb:1-1 transport_id:3".to_string(),
+		"emulator-5554          recovery product:omni model:Pixel device:sailfish transport_id:4".to_string(),
+	]);
+
+	let devices = adb.client.devices().unwrap();
+	assert_eq!(devices.len(), 4);
+
+	assert_eq!(*devices[0].serial_number, "fake-serial");
+	assert_eq!(devices[0].state, DeviceState::Device);
+	assert_eq!(devices[0].product.as_deref(), Some("fake"));
+	assert_eq!(devices[0].model.as_deref(), Some("Fake_Model"));
+	assert_eq!(devices[0].device.as_deref(), Some("fake"));
+	assert_eq!(devices[0].transport_id, Some(1));
+
+	assert_eq!(*devices[1].serial_number, "192.168.0.2:5555");
+	assert_eq!(devices[1].state, DeviceState::Offline);
+	assert_eq!(devices[1].model, None);
+
+	assert!(
+		matches!(&devices[2].state, DeviceState::Other(state) if state.starts_with("no permissions (missing udev rules?"))
+	);
+	assert_eq!(devices[2].transport_id, Some(3));
+
+	assert_eq!(devices[3].state, DeviceState::Recovery);
+	assert!(devices[3].state.is_usable());
+
+	let entries = devices[0].handle().ls("/").unwrap();
+	assert_eq!(entries.count(), 0);
+}
+
+#[test]
+fn ls() {
+	let adb = FakeAdb::start("fake-serial");
+	adb.dir("/data", 1)
+		.file("/data/a.txt", 2, b"a")
+		.file("/data/new\nline", 3, b"bb")
+		.file("/data/sub/nested", 4, b"not listed");
+	let device = adb.device();
+
+	let entries = device.ls("/data").unwrap().collect::<Vec<_>>();
+	assert_eq!(entries.len(), 2);
+	assert_eq!(entries[0].name, "a.txt");
+	assert_eq!(entries[0].size, 1);
+	assert!(entries[0].mode.is_file());
+	assert_eq!(entries[1].name, "new\nline");
+	assert_eq!(entries[1].size, 2);
+}
+
+#[test]
+fn ls_v2() {
+	let adb = FakeAdb::start("fake-serial");
+	adb.dir("/data", 1)
+		.large_file("/data/video.mp4", 5_000_000_000, 5 << 30);
+	let entries = adb.device().ls("/data").unwrap().collect::<Vec<_>>();
+	assert_eq!(entries[0].size, (5 << 30) & 0xffff_ffff);
+	assert_eq!(entries[0].atime, None);
+
+	// Without truncation if the device supports it.
+	adb.features.lock().unwrap().push("ls_v2");
+	let entries = adb.device().ls("/data").unwrap().collect::<Vec<_>>();
+	assert_eq!(entries[0].size, 5 << 30);
+	assert_eq!(entries[0].mtime, Epoch::from_timestamp(5_000_000_000));
+	assert_eq!(entries[0].atime, Some(Epoch::from_timestamp(5_000_000_001)));
+	assert_eq!(entries[0].ctime, Some(Epoch::from_timestamp(5_000_000_002)));
+}
+
+#[test]
+fn ls_v2_errors() {
+	let adb = FakeAdb::start("fake-serial");
+	adb.file("/data/a.txt", 2, b"a").vanished("/data/gone", 2);
+	// Old devices leave it out.
+	let entries = adb.device().ls("/data").unwrap().collect::<Vec<_>>();
+	assert_eq!(entries.len(), 1);
+
+	adb.features.lock().unwrap().push("ls_v2");
+	let error = adb.device().ls("/data").err().unwrap();
+	assert_eq!(error.kind(), ErrorKind::NotFound);
+	assert!(
+		matches!(AdbError::of(&error), Some(AdbError::NotFound { path }) if path.to_string_panicky() == "/data/gone")
+	);
+
+	adb.vanished("/data/gone", 13);
+	let error = adb.device().ls("/data").err().unwrap();
+	assert_eq!(error.kind(), ErrorKind::PermissionDenied);
+}
+
+#[test]
+fn pull() {
+	let adb = FakeAdb::start("fake-serial");
+	adb.file("/data/a.txt", 2, b"contents");
+	let device = adb.device();
+
+	assert_eq!(device.pull("/data/a.txt", 8).unwrap(), b"contents");
+	assert!(device.pull("/data/a.txt", 9).is_err());
+}
+
+#[test]
+fn pull_streaming() {
+	let adb = FakeAdb::start("fake-serial");
+	adb.file("/data/a.txt", 2, b"streamed in chunks");
+	let device = adb.device();
+
+	let mut target = Vec::new();
+	assert_eq!(device.pull_to("/data/a.txt", 18, &mut target).unwrap(), 18);
+	assert_eq!(target, b"streamed in chunks");
+
+	// The mismatch is only reported once everything has been read.
+	let mut reader = device.pull_reader("/data/a.txt", 17).unwrap();
+	let mut prefix = [0; 17];
+	reader.read_exact(&mut prefix).unwrap();
+	assert_eq!(reader.read(&mut [0; 8]).unwrap(), 1);
+	let error = reader.read(&mut [0; 8]).unwrap_err();
+	assert_eq!(error.kind(), ErrorKind::InvalidData);
+	assert_eq!(reader.received(), 18);
+}
+
+#[test]
+fn readlink() {
+	let adb = FakeAdb::start("fake-serial");
+	adb.symlink("/sdcard/it's", b"/storage/emulated/0");
+	let device = adb.device();
+
+	assert_eq!(
+		device.readlink("/sdcard/it's").unwrap().to_string_panicky(),
+		"/storage/emulated/0"
+	);
+	assert!(device.readlink("/sdcard/missing").is_err());
+}
+
+#[test]
+fn errors() {
+	let adb = FakeAdb::start("fake-serial");
+	adb.file("/data/a.txt", 2, b"contents");
+	let device = adb.device();
+
+	let error = device.pull("/data/missing", 1).unwrap_err();
+	assert_eq!(error.kind(), ErrorKind::NotFound);
+	assert!(
+		matches!(AdbError::of(&error), Some(AdbError::NotFound { path }) if path.to_string_panicky() == "/data/missing")
+	);
+
+	let error = device.pull("/data/a.txt", 9).unwrap_err();
+	assert!(matches!(
+		AdbError::of(&error),
+		Some(AdbError::SizeMismatch {
+			expected: 9,
+			actual: 8,
+			..
+		})
+	));
+
+	let error = adb
+		.client
+		.device(SerialNumber::from("other-serial"))
+		.pull("/data/a.txt", 8)
+		.unwrap_err();
+	assert!(matches!(AdbError::of(&error), Some(AdbError::NoDevice)));
+}
+
+#[test]
+fn server_messages() {
+	for (message, expected) in [
+		(
+			"device unauthorized.\nThis adb server's $ADB_VENDOR_KEYS is not set",
+			"Unauthorized",
+		),
+		("device offline", "Offline"),
+		("more than one device/emulator", "MultipleDevices"),
+		("no devices/emulators found", "NoDevice"),
+		("device 'abc' not found", "NoDevice"),
+		("closed", "Failed"),
+	] {
+		let classified = format!("{:?}", AdbError::from_server_message(message));
+		assert!(
+			classified.starts_with(expected),
+			"{}: {}",
+			message,
+			classified
+		);
+	}
+
+	for (message, expected) in [
+		("open failed: Permission denied", "PermissionDenied"),
+		("opendir failed: No such file or directory", "NotFound"),
+		("remote Read-only file system", "Failed"),
+	] {
+		let classified = format!(
+			"{:?}",
+			AdbError::from_device_message("/data/x".into(), message)
+		);
+		assert!(
+			classified.starts_with(expected),
+			"{}: {}",
+			message,
+			classified
+		);
+	}
+}
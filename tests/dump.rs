@@ -0,0 +1,580 @@
+#![cfg(not(miri))]
+
+use adb_dump::{
+	archive::{self, Format, NAMES_FILE_NAME},
+	dump::{self, ErrorPolicy, Options, DELETED_FILE_NAME, MANIFEST_FILE_NAME},
+	progress::{Event, Progress, Stats},
+	rules::Rules,
+	verify::Algorithm,
+	RawPathBuf, RawStr, RawString,
+};
+use std::{
+	fs::File,
+	io::{ErrorKind, Read},
+	path::Path,
+	sync::{Arc, Mutex},
+	time::Duration,
+};
+
+#[path = "fake_backend_.rs"]
+mod fake_backend;
+use fake_backend::{archived_files, archives, dump_options, output_dir, FakeBackend, Node};
+use tar::EntryType;
+use zip::ZipArchive;
+
+fn options(output_dir: &Path, resume: bool) -> Options {
+	options_with(output_dir, resume, ErrorPolicy::Abort)
+}
+
+fn options_with(output_dir: &Path, resume: bool, error_policy: ErrorPolicy) -> Options {
+	Options {
+		resume,
+		error_policy,
+		..dump_options(output_dir)
+	}
+}
+
+fn backend() -> FakeBackend {
+	FakeBackend::new()
+		.dir("/sdcard")
+		.dir("/sdcard/a")
+		.file("/sdcard/a/1", b"one")
+		.file("/sdcard/a/2", b"two")
+		.dir("/sdcard/b")
+		.file("/sdcard/b/3", b"three")
+		.file("/sdcard/4", b"four")
+}
+
+#[test]
+fn resume_after_disconnect() {
+	let dir = output_dir("resume_after_disconnect");
+	let backend = backend();
+
+	backend.disconnect_after(2);
+	let error = dump::run(&backend, &Rules::new(), &options(&dir, false)).unwrap_err();
+	assert_eq!(error.kind(), ErrorKind::BrokenPipe);
+	let before = archives(&dir);
+
+	backend.reconnect();
+	dump::run(&backend, &Rules::new(), &options(&dir, true)).unwrap();
+
+	// Earlier volumes are kept as they are, new ones are numbered after them.
+	let after = archives(&dir);
+	assert!(after.len() > before.len());
+	assert_eq!(after[..before.len()], before[..]);
+
+	let files = archived_files(&dir);
+	assert_eq!(
+		files.keys().collect::<Vec<_>>(),
+		["sdcard/4", "sdcard/a/1", "sdcard/a/2", "sdcard/b/3"]
+	);
+	assert_eq!(files["sdcard/b/3"], b"three");
+}
+
+#[test]
+fn resume_complete() {
+	let dir = output_dir("resume_complete");
+	let backend = backend();
+	dump::run(&backend, &Rules::new(), &options(&dir, false)).unwrap();
+	let before = archives(&dir);
+
+	// Nothing needs to be listed or pulled again.
+	backend.disconnect_after(0);
+	dump::run(&backend, &Rules::new(), &options(&dir, true)).unwrap();
+	assert_eq!(archives(&dir), before);
+}
+
+#[test]
+fn refuses_existing_journal() {
+	let dir = output_dir("refuses_existing_journal");
+	dump::run(&backend(), &Rules::new(), &options(&dir, false)).unwrap();
+	let error = dump::run(&backend(), &Rules::new(), &options(&dir, false)).unwrap_err();
+	assert_eq!(error.kind(), ErrorKind::AlreadyExists);
+}
+
+#[test]
+fn spool_removed_on_error() {
+	let dir = output_dir("spool_removed_on_error");
+	let mut options = options_with(&dir, false, ErrorPolicy::Skip);
+	options.since = vec![dir.join("missing")];
+	assert!(dump::run(&backend(), &Rules::new(), &options).is_err());
+	assert!(!dir.join("adb-dump.partial").exists());
+}
+
+#[test]
+fn skip_failed_entries() {
+	let dir = output_dir("skip_failed_entries");
+	let backend = backend();
+	backend.fail("/sdcard/a/1", usize::MAX);
+	backend.fail("/sdcard/b", usize::MAX);
+
+	let summary = dump::run(
+		&backend,
+		&Rules::new(),
+		&options_with(&dir, false, ErrorPolicy::Skip),
+	)
+	.unwrap();
+	assert_eq!(
+		summary
+			.failures
+			.iter()
+			.map(|failure| failure.path.to_string_panicky())
+			.collect::<Vec<_>>(),
+		["/sdcard/a/1", "/sdcard/b"]
+	);
+
+	let files = archived_files(&dir);
+	assert_eq!(
+		files.keys().collect::<Vec<_>>(),
+		["adb-dump.errors.txt", "sdcard/4", "sdcard/a/2"]
+	);
+	assert!(!dir.join("adb-dump.partial").exists());
+
+	// Lost entries are picked up again when resuming.
+	backend.failing.lock().unwrap().clear();
+	let summary = dump::run(
+		&backend,
+		&Rules::new(),
+		&options_with(&dir, true, ErrorPolicy::Skip),
+	)
+	.unwrap();
+	assert!(summary.is_complete());
+	assert_eq!(
+		archived_files(&dir).keys().collect::<Vec<_>>(),
+		[
+			"adb-dump.errors.txt",
+			"sdcard/4",
+			"sdcard/a/1",
+			"sdcard/a/2",
+			"sdcard/b/3"
+		]
+	);
+}
+
+#[test]
+fn error_manifest() {
+	let dir = output_dir("error_manifest");
+	let backend = backend();
+	backend.fail("/sdcard/b/3", usize::MAX);
+	dump::run(
+		&backend,
+		&Rules::new(),
+		&options_with(&dir, false, ErrorPolicy::Skip),
+	)
+	.unwrap();
+	assert_eq!(
+		archived_files(&dir)["adb-dump.errors.txt"],
+		b"\"/sdcard/b/3\"\tflaky\n"
+	);
+}
+
+#[test]
+fn retry() {
+	let dir = output_dir("retry");
+	let backend = backend();
+	backend.fail("/sdcard/a", 1);
+	backend.fail("/sdcard/a/1", 2);
+	let summary = dump::run(
+		&backend,
+		&Rules::new(),
+		&options_with(
+			&dir,
+			false,
+			ErrorPolicy::Retry {
+				attempts: 2,
+				backoff: Duration::from_millis(1),
+			},
+		),
+	)
+	.unwrap();
+	assert!(summary.is_complete());
+
+	// Failed attempts don't leave partial entries behind.
+	let files = archived_files(&dir);
+	assert_eq!(files.len(), 4);
+	assert_eq!(files["sdcard/a/1"], b"one");
+}
+
+#[test]
+fn links_and_special_files() {
+	let dir = output_dir("links_and_special_files");
+	let backend = FakeBackend::new()
+		.dir("/dev")
+		.symlink("/dev/stdin", "/proc/self/fd/0")
+		.special("/dev/null", 0o020_666, (1, 3))
+		.special("/dev/pipe", 0o010_600, (0, 0));
+	let mut options = options(&dir, false);
+	options.paths = vec!["/dev".into()];
+	dump::run(&backend, &Rules::new(), &options).unwrap();
+
+	let mut archive = ZipArchive::new(File::open(dir.join("backup.1.zip")).unwrap()).unwrap();
+	let mut link = archive.by_name("dev/stdin").unwrap();
+	assert_eq!(link.unix_mode().unwrap() & 0o170_000, 0o120_000);
+	let mut target = String::new();
+	link.read_to_string(&mut target).unwrap();
+	assert_eq!(target, "/proc/self/fd/0");
+	drop(link);
+
+	let mut special = String::new();
+	archive
+		.by_name("adb-dump.special.txt")
+		.unwrap()
+		.read_to_string(&mut special)
+		.unwrap();
+	assert_eq!(
+		special,
+		"\"dev/null\"\tchar\t666\t1600000000\t1:3\n\"dev/pipe\"\tfifo\t600\t1600000000\n"
+	);
+}
+
+/// Path, type and contents of each entry but the checksum manifest.
+fn tar_entries(path: &Path) -> Vec<(String, EntryType, Vec<u8>)> {
+	let file = File::open(path).unwrap();
+	let reader: Box<dyn Read> = match path.to_str().unwrap() {
+		#[cfg(feature = "gzip")]
+		name if name.ends_with(".gz") => Box::new(flate2::read::GzDecoder::new(file)),
+		#[cfg(feature = "zstd")]
+		name if name.ends_with(".zst") => Box::new(zstd::Decoder::new(file).unwrap()),
+		_ => Box::new(file),
+	};
+	tar::Archive::new(reader)
+		.entries()
+		.unwrap()
+		.map(Result::unwrap)
+		.filter(|entry| &*entry.path().unwrap() != Path::new(MANIFEST_FILE_NAME))
+		.map(|mut entry| {
+			let path = String::from_utf8(entry.path_bytes().into_owned()).unwrap();
+			let entry_type = entry.header().entry_type();
+			let mut data = Vec::new();
+			match entry.link_name_bytes() {
+				Some(link) => data.extend_from_slice(&link),
+				None => {
+					entry.read_to_end(&mut data).unwrap();
+				}
+			}
+			if entry_type.is_character_special() {
+				let header = entry.header();
+				data = format!(
+					"{}:{}",
+					header.device_major().unwrap().unwrap(),
+					header.device_minor().unwrap().unwrap()
+				)
+				.into_bytes();
+			}
+			(path, entry_type, data)
+		})
+		.collect()
+}
+
+#[test]
+fn tar() {
+	let long_name = format!("/data/{}", "long name ".repeat(20));
+	let backend = FakeBackend::new()
+		.dir("/data")
+		.file(&long_name, b"long")
+		.symlink("/data/link", "/data/long")
+		.special("/data/null", 0o020_666, (1, 3))
+		.special("/data/pipe", 0o010_600, (0, 0))
+		.special("/data/socket", 0o140_600, (0, 0));
+
+	for format in Format::NAMES {
+		let dir = output_dir(&format!("tar-{}", format));
+		let mut options = options(&dir, false);
+		options.paths = vec!["/data".into()];
+		options.format = format.parse().unwrap();
+		if options.format == Format::Zip {
+			continue;
+		}
+		dump::run(&backend, &Rules::new(), &options).unwrap();
+
+		assert_eq!(
+			tar_entries(&dir.join(format!("backup.1.{}", format))),
+			[
+				(
+					"data/link".to_string(),
+					EntryType::Symlink,
+					b"/data/long".to_vec()
+				),
+				(
+					long_name[1..].to_string(),
+					EntryType::Regular,
+					b"long".to_vec()
+				),
+				("data/null".to_string(), EntryType::Char, b"1:3".to_vec()),
+				("data/pipe".to_string(), EntryType::Fifo, vec![]),
+				(
+					"adb-dump.special.txt".to_string(),
+					EntryType::Regular,
+					b"\"data/socket\"\tsocket\t600\t1600000000\n".to_vec()
+				),
+			]
+		);
+	}
+}
+
+#[test]
+fn non_utf8_names() {
+	let name = b"sdcard/caf\xe9 100%";
+	let backend = FakeBackend::new()
+		.dir("/sdcard")
+		.raw_file(&[b"/", &name[..]].concat(), b"latin-1")
+		.file("/sdcard/100%", b"utf-8");
+
+	// ZIP entry names are encoded where necessary and listed in a manifest.
+	let dir = output_dir("non_utf8_names");
+	dump::run(&backend, &Rules::new(), &options(&dir, false)).unwrap();
+	let files = archived_files(&dir);
+	assert_eq!(
+		files.keys().collect::<Vec<_>>(),
+		[NAMES_FILE_NAME, "sdcard/100%25", "sdcard/caf%E9 100%25"]
+	);
+	assert_eq!(files["sdcard/caf%E9 100%25"], b"latin-1");
+	assert_eq!(
+		files[NAMES_FILE_NAME],
+		b"7364636172642f636166e92031303025\n"
+	);
+	let decoded = RawString::decode_lossless("sdcard/caf%E9 100%25").unwrap();
+	assert_eq!(&**decoded, &name[..]);
+
+	// A UTF-8 name that looks like an encoded one is encoded too, so they don't collide.
+	let similar = FakeBackend::new()
+		.dir("/sdcard")
+		.raw_file(b"/sdcard/a\xff", b"latin-1")
+		.file("/sdcard/a%FF", b"utf-8");
+	let dir = output_dir("non_utf8_names-collision");
+	dump::run(&similar, &Rules::new(), &options(&dir, false)).unwrap();
+	let files = archived_files(&dir);
+	assert_eq!(files["sdcard/a%FF"], b"latin-1");
+	assert_eq!(files["sdcard/a%25FF"], b"utf-8");
+	let mut read = Vec::new();
+	for volume in archive::volumes(&dir).unwrap() {
+		archive::read(&volume.path, |path, _, data| {
+			if dump::is_manifest(path) {
+				return Ok(());
+			}
+			let mut contents = Vec::new();
+			data.read_to_end(&mut contents)?;
+			read.push((path.to_vec(), contents));
+			Ok(())
+		})
+		.unwrap();
+	}
+	read.sort();
+	assert_eq!(
+		read,
+		[
+			(b"sdcard/a%FF".to_vec(), b"utf-8".to_vec()),
+			(b"sdcard/a\xff".to_vec(), b"latin-1".to_vec()),
+		]
+	);
+	for name in [&b"a%FF"[..], b"a\xff"] {
+		let encoded = RawStr::new(name).encode_lossless();
+		assert_eq!(&**RawString::decode_lossless(&encoded).unwrap(), name);
+	}
+
+	// TAR keeps them as they are.
+	let dir = output_dir("non_utf8_names-tar");
+	let mut options = options(&dir, false);
+	options.format = Format::Tar;
+	options.split_size = u64::MAX;
+	dump::run(&backend, &Rules::new(), &options).unwrap();
+	let mut archive = tar::Archive::new(File::open(dir.join("backup.1.tar")).unwrap());
+	let names = archive
+		.entries()
+		.unwrap()
+		.map(|entry| entry.unwrap().path_bytes().into_owned())
+		.collect::<Vec<_>>();
+	assert_eq!(
+		names,
+		[
+			&b"sdcard/100%"[..],
+			&name[..],
+			MANIFEST_FILE_NAME.as_bytes()
+		]
+	);
+}
+
+#[cfg(unix)]
+#[test]
+fn os_str_round_trip() {
+	use std::{convert::TryFrom, ffi::OsString};
+	let path = RawPathBuf::from(b"/sdcard/caf\xe9".to_vec());
+	let os_string = OsString::try_from(&**path).unwrap();
+	let converted = RawPathBuf::try_from(os_string.as_os_str()).unwrap();
+	assert_eq!(&***converted, &***path);
+}
+
+#[test]
+fn incremental() {
+	let base = output_dir("incremental-base");
+	let mut backend = backend();
+	dump::run(&backend, &Rules::new(), &options(&base, false)).unwrap();
+
+	backend
+		.nodes
+		.insert(b"/sdcard/a/2".to_vec(), Node::File(b"twotwo".to_vec()));
+	backend.nodes.remove(&b"/sdcard/b"[..]);
+	backend.nodes.remove(&b"/sdcard/b/3"[..]);
+	let backend = backend.file("/sdcard/5", b"five");
+	let first = output_dir("incremental-first");
+	let mut options = options(&first, false);
+	options.since = vec![base.clone()];
+	dump::run(&backend, &Rules::new(), &options).unwrap();
+	let files = archived_files(&first);
+	assert_eq!(
+		files.keys().collect::<Vec<_>>(),
+		[DELETED_FILE_NAME, "sdcard/5", "sdcard/a/2"]
+	);
+	assert_eq!(
+		files[DELETED_FILE_NAME],
+		format!("{}\n", hex::encode("sdcard/b/3")).as_bytes()
+	);
+
+	// Layered on top of each other, the two are up to date. Directories are archived regardless.
+	let second = output_dir("incremental-second");
+	let mut options = options.clone();
+	options.output_dir = second.clone();
+	options.since = vec![base, first];
+	dump::run(&backend, &Rules::new(), &options).unwrap();
+	assert!(archived_files(&second).is_empty());
+}
+
+#[test]
+fn incremental_checksums() {
+	let base = output_dir("incremental_checksums-base");
+	let mut backend = backend();
+	dump::run(&backend, &Rules::new(), &options(&base, false)).unwrap();
+
+	// Same size and mtime.
+	backend
+		.nodes
+		.insert(b"/sdcard/a/1".to_vec(), Node::File(b"ONE".to_vec()));
+	*backend.tools.lock().unwrap() = vec![Algorithm::Sha256];
+	for compare_checksums in [false, true] {
+		let dir = output_dir(&format!("incremental_checksums-{}", compare_checksums));
+		let mut options = options(&dir, false);
+		options.since = vec![base.clone()];
+		options.compare_checksums = compare_checksums;
+		dump::run(&backend, &Rules::new(), &options).unwrap();
+		let files = archived_files(&dir);
+		assert_eq!(files.contains_key("sdcard/a/1"), compare_checksums);
+		assert_eq!(files.len(), usize::from(compare_checksums));
+	}
+
+	// If hashing fails altogether, mtimes are compared instead.
+	backend.fail("/dev/null", 1);
+	let dir = output_dir("incremental_checksums-failing");
+	let mut options = options(&dir, false);
+	options.since = vec![base];
+	options.compare_checksums = true;
+	dump::run(&backend, &Rules::new(), &options).unwrap();
+	assert!(archived_files(&dir).is_empty());
+}
+
+#[test]
+fn parallel() {
+	let mut backend = FakeBackend::new().dir("/sdcard");
+	for i in 0..10 {
+		backend = backend.dir(&format!("/sdcard/{}", i));
+		for j in 0..10 {
+			backend = backend.file(
+				&format!("/sdcard/{}/{}", i, j),
+				format!("{} {}", i, j).as_bytes(),
+			);
+		}
+	}
+	let backend = backend.file("/sdcard/large", &vec![b'x'; (1 << 20) + 1]);
+
+	let mut dumped = Vec::new();
+	for jobs in [1, 4] {
+		backend.fail("/sdcard/3", 1);
+		backend.fail("/sdcard/5/5", 1);
+		let dir = output_dir(&format!("parallel-{}", jobs));
+		let mut options = options_with(&dir, false, ErrorPolicy::Skip);
+		options.split_size = 64;
+		options.jobs = jobs;
+		let summary = dump::run(&backend, &Rules::new(), &options).unwrap();
+		assert_eq!(summary.failures.len(), 2);
+
+		let mut names = Vec::new();
+		for archive in archives(&dir) {
+			let mut archive = ZipArchive::new(File::open(dir.join(archive)).unwrap()).unwrap();
+			for i in 0..archive.len() {
+				names.push(archive.by_index(i).unwrap().name().to_string());
+			}
+		}
+		dumped.push((names, archived_files(&dir)));
+	}
+	assert_eq!(dumped[0], dumped[1]);
+}
+
+#[test]
+fn prefetch_bounded() {
+	let mut backend = FakeBackend::new().dir("/sdcard");
+	for i in 0..300 {
+		backend = backend
+			.dir(&format!("/sdcard/{:03}", i))
+			.file(&format!("/sdcard/{:03}/file", i), b"data");
+	}
+	let dir = output_dir("prefetch-bounded");
+	let mut options = options(&dir, false);
+	options.split_size = 1 << 20;
+	options.jobs = 4;
+	dump::run(&backend, &Rules::new(), &options).unwrap();
+
+	// Subdirectories are listed ahead of the walker, but not all at once.
+	let mut pulls = 0;
+	let mut listed = 0;
+	for (request, path) in backend.requests.lock().unwrap().iter() {
+		if *request == "pull" {
+			pulls += 1;
+		} else if path.starts_with(b"/sdcard/") {
+			assert!(
+				pulls + 100 >= listed,
+				"{} listed after {} pulls",
+				listed,
+				pulls
+			);
+			listed += 1;
+		}
+	}
+	assert_eq!((pulls, listed), (300, 300));
+}
+
+#[test]
+fn progress() {
+	for jobs in [1, 4] {
+		let backend = backend();
+		backend.fail("/sdcard/a/1", usize::MAX);
+		let dir = output_dir(&format!("progress-{}", jobs));
+		let stats = Arc::new(Mutex::new(Stats::new()));
+		let dirs = Arc::new(Mutex::new(Vec::new()));
+		let mut options = options_with(&dir, false, ErrorPolicy::Skip);
+		options.jobs = jobs;
+		options.scan = true;
+		options.progress = Some(Progress::new({
+			let stats = Arc::clone(&stats);
+			let dirs = Arc::clone(&dirs);
+			move |event| {
+				let mut stats = stats.lock().unwrap();
+				if let Event::Dir(path) = event {
+					assert_eq!(stats.total_files, Some(4), "scanned first");
+					dirs.lock().unwrap().push(path.to_string_panicky());
+				}
+				stats.update(event);
+			}
+		}));
+		dump::run(&backend, &Rules::new(), &options).unwrap();
+
+		let stats = stats.lock().unwrap();
+		assert_eq!(stats.total_bytes, Some(15));
+		// The lost file counts as skipped.
+		assert_eq!(stats.files, 4);
+		assert_eq!(stats.bytes, 15);
+		// Half of the lost file arrived before it failed, unless a worker pulled it.
+		assert_eq!(stats.pulled, if jobs == 1 { 13 } else { 12 });
+		assert_eq!(stats.fraction(), Some(1.0));
+		assert_eq!(stats.eta(Duration::from_secs(1)), Some(Duration::ZERO));
+		assert_eq!(*dirs.lock().unwrap(), ["/sdcard", "/sdcard/a", "/sdcard/b"]);
+	}
+}
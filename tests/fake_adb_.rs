@@ -0,0 +1,690 @@
+#![allow(dead_code)]
+
+//! A tiny stand-in for the ADB server, speaking just enough of the smart-socket and SYNC protocols.
+
+use adb_dump::{client::AdbClient, device::Device};
+use std::{
+	collections::BTreeMap,
+	convert::TryInto,
+	io::{Read, Write},
+	net::{TcpListener, TcpStream},
+	sync::{Arc, Mutex},
+	thread,
+};
+
+#[derive(Clone)]
+pub enum Node {
+	Dir {
+		mode: u32,
+		mtime: u32,
+	},
+	File {
+		mode: u32,
+		mtime: u32,
+		data: Vec<u8>,
+	},
+	Symlink {
+		target: Vec<u8>,
+	},
+	/// A file that can be listed, but not pulled.
+	Large {
+		size: u64,
+		mtime: i64,
+	},
+	/// An entry whose `lstat` fails with this `errno` while listing, as when it's deleted meanwhile.
+	Vanished {
+		errno: u32,
+	},
+}
+
+pub type Files = Arc<Mutex<BTreeMap<Vec<u8>, Node>>>;
+
+/// Who adbd and `exec:` commands run as.
+#[derive(Clone, Default)]
+pub struct Shell {
+	/// Unless 0, SYNC and commands can't read anything under `/data`.
+	pub uid: u32,
+	/// Whether `adb root` works, as on userdebug builds.
+	pub adb_root: bool,
+	/// How `su` has to be called, like `su -c `, if it's installed.
+	pub su: Option<&'static str>,
+	/// `ro.twrp.version`.
+	pub twrp: Option<&'static str>,
+	/// Further properties for `getprop`.
+	pub props: Vec<(&'static str, &'static str)>,
+	/// Mount points for `/proc/mounts`. `twrp mount` adds to them.
+	pub mounts: Vec<&'static str>,
+}
+
+impl Shell {
+	fn can_read(&self, path: &[u8]) -> bool {
+		self.uid == 0 || !path.starts_with(b"/data")
+	}
+}
+
+pub struct FakeAdb {
+	pub client: AdbClient,
+	pub serial: &'static str,
+	pub files: Files,
+	/// Further lines for `host:devices-l`.
+	pub other_devices: Arc<Mutex<Vec<String>>>,
+	/// The reply to `host-serial:…:features`.
+	pub features: Arc<Mutex<Vec<&'static str>>>,
+	pub shell: Arc<Mutex<Shell>>,
+	/// The state in `host:devices-l` and the reply to `host-serial:…:get-state`.
+	pub state: Arc<Mutex<&'static str>>,
+}
+
+impl FakeAdb {
+	pub fn start(serial: &'static str) -> Self {
+		let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+		let client = AdbClient::new(listener.local_addr().unwrap());
+		let files = Files::default();
+		files.lock().unwrap().insert(
+			b"/".to_vec(),
+			Node::Dir {
+				mode: 0o040_755,
+				mtime: 0,
+			},
+		);
+
+		let other_devices = Arc::<Mutex<Vec<String>>>::default();
+		let features = Arc::<Mutex<Vec<&'static str>>>::default();
+		let shell = Arc::<Mutex<Shell>>::default();
+		let state = Arc::new(Mutex::new("device"));
+
+		let (shared_files, shared_devices, shared_features, shared_shell, shared_state) = (
+			files.clone(),
+			other_devices.clone(),
+			features.clone(),
+			shell.clone(),
+			state.clone(),
+		);
+		thread::spawn(move || {
+			for stream in listener.incoming() {
+				let files = shared_files.clone();
+				let other_devices = shared_devices.clone();
+				let features = shared_features.clone();
+				let shell = shared_shell.clone();
+				let state = shared_state.clone();
+				thread::spawn(move || {
+					serve(
+						serial,
+						&files,
+						&other_devices,
+						&features,
+						&shell,
+						&state,
+						stream.unwrap(),
+					)
+				});
+			}
+		});
+
+		Self {
+			client,
+			serial,
+			files,
+			other_devices,
+			features,
+			shell,
+			state,
+		}
+	}
+
+	pub fn device(&self) -> Device {
+		self.client.device(self.serial.into())
+	}
+
+	pub fn dir(&self, path: &str, mtime: u32) -> &Self {
+		self.files.lock().unwrap().insert(
+			path.as_bytes().to_vec(),
+			Node::Dir {
+				mode: 0o040_755,
+				mtime,
+			},
+		);
+		self
+	}
+
+	pub fn symlink(&self, path: &str, target: &[u8]) -> &Self {
+		self.files.lock().unwrap().insert(
+			path.as_bytes().to_vec(),
+			Node::Symlink {
+				target: target.to_vec(),
+			},
+		);
+		self
+	}
+
+	pub fn large_file(&self, path: &str, mtime: i64, size: u64) -> &Self {
+		self.files
+			.lock()
+			.unwrap()
+			.insert(path.as_bytes().to_vec(), Node::Large { size, mtime });
+		self
+	}
+
+	pub fn vanished(&self, path: &str, errno: u32) -> &Self {
+		self.files
+			.lock()
+			.unwrap()
+			.insert(path.as_bytes().to_vec(), Node::Vanished { errno });
+		self
+	}
+
+	pub fn file(&self, path: &str, mtime: u32, data: &[u8]) -> &Self {
+		self.files.lock().unwrap().insert(
+			path.as_bytes().to_vec(),
+			Node::File {
+				mode: 0o100_644,
+				mtime,
+				data: data.to_vec(),
+			},
+		);
+		self
+	}
+}
+
+fn read_request(stream: &mut TcpStream) -> Option<Vec<u8>> {
+	let mut len = [0; 4];
+	stream.read_exact(&mut len).ok()?;
+	let len = usize::from_str_radix(std::str::from_utf8(&len).unwrap(), 16).unwrap();
+	let mut request = vec![0; len];
+	stream.read_exact(&mut request).unwrap();
+	Some(request)
+}
+
+fn strip_prefix<'a>(data: &'a [u8], prefix: &[u8]) -> Option<&'a [u8]> {
+	if data.starts_with(prefix) {
+		Some(&data[prefix.len()..])
+	} else {
+		None
+	}
+}
+
+fn length_prefixed(data: &[u8]) -> Vec<u8> {
+	let mut result = format!("{:04x}", data.len()).into_bytes();
+	result.extend_from_slice(data);
+	result
+}
+
+fn serve(
+	serial: &str,
+	files: &Files,
+	other_devices: &Mutex<Vec<String>>,
+	features: &Mutex<Vec<&str>>,
+	shell: &Mutex<Shell>,
+	state: &Mutex<&str>,
+	mut stream: TcpStream,
+) {
+	while let Some(request) = read_request(&mut stream) {
+		if request == format!("host-serial:{}:features", serial).as_bytes() {
+			stream.write_all(b"OKAY").unwrap();
+			stream
+				.write_all(&length_prefixed(
+					features.lock().unwrap().join(",").as_bytes(),
+				))
+				.unwrap();
+			return;
+		}
+		if request == format!("host-serial:{}:get-state", serial).as_bytes() {
+			stream.write_all(b"OKAY").unwrap();
+			stream
+				.write_all(&length_prefixed(state.lock().unwrap().as_bytes()))
+				.unwrap();
+			return;
+		}
+		if request == b"host:devices-l" {
+			let mut listing = format!(
+				"{:<22} {} product:fake model:Fake_Model device:fake transport_id:1\n",
+				serial,
+				state.lock().unwrap()
+			);
+			for line in other_devices.lock().unwrap().iter() {
+				listing.push_str(line);
+				listing.push('\n');
+			}
+			stream.write_all(b"OKAY").unwrap();
+			stream
+				.write_all(&length_prefixed(listing.as_bytes()))
+				.unwrap();
+			return;
+		} else if request == b"host:get-serialno" {
+			stream.write_all(b"OKAY").unwrap();
+			stream
+				.write_all(&length_prefixed(serial.as_bytes()))
+				.unwrap();
+			return;
+		} else if let Some(requested) = strip_prefix(&request, b"host:transport:") {
+			if requested == serial.as_bytes() {
+				stream.write_all(b"OKAY").unwrap();
+			} else {
+				stream.write_all(b"FAIL").unwrap();
+				stream
+					.write_all(&length_prefixed(b"device not found"))
+					.unwrap();
+				return;
+			}
+		} else if request == b"sync:" {
+			stream.write_all(b"OKAY").unwrap();
+			let shell = shell.lock().unwrap().clone();
+			return sync(files, &shell, stream);
+		} else if let Some(command) = strip_prefix(&request, b"exec:") {
+			stream.write_all(b"OKAY").unwrap();
+			let mut shell = shell.lock().unwrap();
+			let output = exec(files, &mut shell, command, &mut stream);
+			stream.write_all(&output).unwrap();
+			return;
+		} else if request == b"root:" {
+			stream.write_all(b"OKAY").unwrap();
+			let mut shell = shell.lock().unwrap();
+			if shell.adb_root {
+				shell.uid = 0;
+				stream.write_all(b"restarting adbd as root\n").unwrap();
+			} else {
+				stream
+					.write_all(b"adbd cannot run as root in production builds\n")
+					.unwrap();
+			}
+			return;
+		} else {
+			stream.write_all(b"FAIL").unwrap();
+			stream
+				.write_all(&length_prefixed(b"unknown service"))
+				.unwrap();
+			return;
+		}
+	}
+}
+
+/// Removes the quotes `adb_dump::device::quote` adds.
+fn unquote(quoted: &[u8]) -> Vec<u8> {
+	String::from_utf8(quoted[1..quoted.len() - 1].to_vec())
+		.unwrap()
+		.replace("'\\''", "'")
+		.into_bytes()
+}
+
+/// Runs one of the few commands `adb_dump` uses.
+fn exec(files: &Files, shell: &mut Shell, command: &[u8], stdin: &mut dyn Read) -> Vec<u8> {
+	let (command, uid) = if command.starts_with(b"su ") {
+		match shell.su.and_then(|su| strip_prefix(command, su.as_bytes())) {
+			Some(wrapped) => (unquote(wrapped), 0),
+			None => return b"/system/bin/sh: su: inaccessible or not found\n".to_vec(),
+		}
+	} else {
+		(command.to_vec(), shell.uid)
+	};
+	let mut files = files.lock().unwrap();
+	let can_read = |path: &[u8]| {
+		Shell {
+			uid,
+			..Shell::default()
+		}
+		.can_read(path)
+	};
+
+	if command == b"id -u" {
+		format!("{}\n", uid).into_bytes()
+	} else if command == b"getprop ro.twrp.version" {
+		format!("{}\n", shell.twrp.unwrap_or_default()).into_bytes()
+	} else if command == b"getprop" {
+		let twrp = shell.twrp.map(|version| ("ro.twrp.version", version));
+		let mut output = String::new();
+		for (key, value) in twrp.iter().chain(&shell.props) {
+			output.push_str(&format!("[{}]: [{}]\n", key, value));
+		}
+		output.into_bytes()
+	} else if command == b"cat /proc/mounts" {
+		let mut output = String::from("rootfs / rootfs ro,seclabel 0 0\n");
+		for mount_point in &shell.mounts {
+			output.push_str(&format!(
+				"/dev/block/by-name{} {} ext4 rw,seclabel,relatime 0 0\n",
+				mount_point, mount_point
+			));
+		}
+		output.into_bytes()
+	} else if let Some(partition) = strip_prefix(&command, b"twrp mount ") {
+		let partition = String::from_utf8(unquote(partition)).unwrap();
+		shell.mounts.push(Box::leak(partition.into_boxed_str()));
+		Vec::new()
+	} else if let Some(path) = strip_prefix(&command, b"ls ") {
+		let mut prefix = strip_suffix(path, b" 2>/dev/null").to_vec();
+		prefix.push(b'/');
+		let mut output = Vec::new();
+		for name in files.keys() {
+			match strip_prefix(name, &prefix) {
+				Some(name) if !name.is_empty() && !name.contains(&b'/') => {
+					output.extend_from_slice(name);
+					output.push(b'\n');
+				}
+				_ => (),
+			}
+		}
+		output
+	} else if let Some(path) = strip_prefix(&command, b"readlink ") {
+		match files.get(&unquote(path)) {
+			Some(Node::Symlink { target }) => [&target[..], b"\n"].concat(),
+			_ => Vec::new(),
+		}
+	} else if command == b"cat /proc/partitions" {
+		match files.get(&b"/proc/partitions"[..]) {
+			Some(Node::File { data, .. }) => data.clone(),
+			_ => Vec::new(),
+		}
+	} else if let Some(path) = strip_prefix(&command, b"stat -c %f ") {
+		let path = unquote(strip_suffix(path, b" 2>/dev/null"));
+		match files.get(&path) {
+			Some(Node::Dir { mode, .. } | Node::File { mode, .. }) if can_read(&path) => {
+				format!("{:x}\n", mode).into_bytes()
+			}
+			_ => Vec::new(),
+		}
+	} else if command.windows(6).any(|window| window == b"cat > ") {
+		let mut data = Vec::new();
+		stdin.read_to_end(&mut data).unwrap();
+		let mut path = Vec::new();
+		for part in command
+			.split(|b| *b == b'&')
+			.filter(|part| !part.is_empty())
+		{
+			let part = part.strip_prefix(b" ").unwrap_or(part);
+			let part = part.strip_suffix(b" ").unwrap_or(part);
+			if let Some(dir) = strip_prefix(part, b"mkdir -p ") {
+				let dir = unquote(dir);
+				for (i, _) in dir.iter().enumerate().filter(|(_, b)| **b == b'/').skip(1) {
+					insert_dir(&mut files, &dir[..i]);
+				}
+				insert_dir(&mut files, &dir);
+			} else {
+				path = unquote(strip_prefix(part, b"cat > ").unwrap());
+			}
+		}
+		if !can_read(&path) {
+			return format!(
+				"/system/bin/sh: can't create {}: Permission denied\n",
+				String::from_utf8_lossy(&path)
+			)
+			.into_bytes();
+		}
+		files.insert(
+			path,
+			Node::File {
+				mode: 0o100_644,
+				mtime: 0,
+				data,
+			},
+		);
+		Vec::new()
+	} else if let Some(rest) = strip_prefix(&command, b"chmod ") {
+		let parts: Vec<_> = strip_suffix(rest, b" 2>&1").split(|b| *b == b' ').collect();
+		let [permissions, path, b"&&", b"touch", b"-c", b"-m", b"-d", mtime, _, b"&&", b"stat", b"-c", b"%s", _] =
+			parts[..]
+		else {
+			panic!("Unexpected command {:?}", String::from_utf8_lossy(&command))
+		};
+		let path = unquote(path);
+		match files.get_mut(&path) {
+			Some(Node::File {
+				mode,
+				mtime: old,
+				data,
+			}) if can_read(&path) => {
+				let permissions = std::str::from_utf8(permissions).unwrap();
+				*mode = 0o100_000 | u32::from_str_radix(permissions, 8).unwrap();
+				*old = std::str::from_utf8(&mtime[1..]).unwrap().parse().unwrap();
+				format!("{}\n", data.len()).into_bytes()
+			}
+			Some(_) => b"chmod: Operation not permitted\n".to_vec(),
+			None => b"chmod: No such file or directory\n".to_vec(),
+		}
+	} else if let Some(path) =
+		strip_prefix(&command, b"cat ").or_else(|| strip_prefix(&command, b"dd if="))
+	{
+		let path = path.split(|b| *b == b' ').next().unwrap();
+		let path = unquote(path);
+		match files.get(&path) {
+			Some(Node::File { data, .. }) if can_read(&path) => data.clone(),
+			_ => Vec::new(),
+		}
+	} else if let Some(path) = strip_prefix(&command, b"blockdev --getsize64 ") {
+		match files.get(&unquote(path)) {
+			Some(Node::File { data, .. }) => format!("{}\n", data.len()).into_bytes(),
+			_ => b"blockdev: No such file or directory\n".to_vec(),
+		}
+	} else if let Some(rest) = strip_prefix(&command, b"cd ") {
+		let end = rest.windows(9).position(|w| w == b" 2>&1 && ").unwrap();
+		let path = unquote(&rest[..end]);
+		assert!(rest.ends_with(b"stat -c '%f %s %Y %u %g %n' {} + 2>/dev/null"));
+		let error = |message: &str| {
+			format!(
+				"/system/bin/sh: cd: {}: {}\n",
+				String::from_utf8_lossy(&path),
+				message
+			)
+			.into_bytes()
+		};
+		match files.get(&path) {
+			Some(Node::Dir { .. }) if can_read(&path) => {}
+			Some(Node::Dir { .. }) => return error("Permission denied"),
+			_ => return error("No such file or directory"),
+		}
+		let mut prefix = path.clone();
+		prefix.push(b'/');
+		let mut output = Vec::new();
+		for (name, node) in files.iter() {
+			let name = match strip_prefix(name, &prefix) {
+				Some(name) if !name.is_empty() && !name.contains(&b'/') => name,
+				_ => continue,
+			};
+			let (mode, size, mtime) = match node {
+				Node::Dir { mode, mtime } => (*mode, 4096, i64::from(*mtime)),
+				Node::File { mode, mtime, data } => (*mode, data.len() as u64, i64::from(*mtime)),
+				Node::Symlink { target } => (0o120_777, target.len() as u64, 0),
+				Node::Large { size, mtime } => (0o100_644, *size, *mtime),
+				// `stat`'s error goes to /dev/null.
+				Node::Vanished { .. } => continue,
+			};
+			output.extend_from_slice(
+				format!("{:x} {} {} 1000 1000 ./", mode, size, mtime).as_bytes(),
+			);
+			output.extend_from_slice(name);
+			output.push(b'\n');
+		}
+		output
+	} else {
+		panic!("Unexpected command {:?}", String::from_utf8_lossy(&command))
+	}
+}
+
+fn insert_dir(files: &mut BTreeMap<Vec<u8>, Node>, path: &[u8]) {
+	files.entry(path.to_vec()).or_insert(Node::Dir {
+		mode: 0o040_755,
+		mtime: 0,
+	});
+}
+
+fn strip_suffix<'a>(data: &'a [u8], suffix: &[u8]) -> &'a [u8] {
+	assert!(data.ends_with(suffix));
+	&data[..data.len() - suffix.len()]
+}
+
+fn sync(files: &Files, shell: &Shell, mut stream: TcpStream) {
+	loop {
+		let mut header = [0; 8];
+		if stream.read_exact(&mut header).is_err() {
+			return;
+		}
+		let len = u32::from_le_bytes(header[4..].try_into().unwrap()) as usize;
+		match &header[..4] {
+			b"LIST" => {
+				let mut path = vec![0; len];
+				stream.read_exact(&mut path).unwrap();
+				let mut prefix = path.clone();
+				if !prefix.ends_with(b"/") {
+					prefix.push(b'/');
+				}
+				let files = files.lock().unwrap();
+				if !files.contains_key(&path) && !files.keys().any(|name| name.starts_with(&prefix))
+				{
+					fail(&mut stream, b"No such file or directory");
+					continue;
+				}
+				for (name, node) in files.iter().filter(|_| shell.can_read(&path)) {
+					let name = match strip_prefix(name, &prefix) {
+						Some(name) if !name.is_empty() && !name.contains(&b'/') => name,
+						_ => continue,
+					};
+					let (mode, size, mtime) = match node {
+						Node::Dir { mode, mtime } => (*mode, 4096, *mtime),
+						Node::File { mode, mtime, data } => (*mode, data.len() as u32, *mtime),
+						Node::Symlink { target } => (0o120_777, target.len() as u32, 0),
+						Node::Large { size, mtime } => (0o100_644, *size as u32, *mtime as u32),
+						// adbd leaves out what it can't `lstat`.
+						Node::Vanished { .. } => continue,
+					};
+					stream.write_all(b"DENT").unwrap();
+					for field in &[mode, size, mtime, name.len() as u32] {
+						stream.write_all(&field.to_le_bytes()).unwrap();
+					}
+					stream.write_all(name).unwrap();
+				}
+				stream
+					.write_all(b"DONE\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0")
+					.unwrap();
+			}
+			b"LIS2" => {
+				let mut path = vec![0; len];
+				stream.read_exact(&mut path).unwrap();
+				let mut prefix = path.clone();
+				if !prefix.ends_with(b"/") {
+					prefix.push(b'/');
+				}
+				let files = files.lock().unwrap();
+				if !files.contains_key(&path) && !files.keys().any(|name| name.starts_with(&prefix))
+				{
+					fail(&mut stream, b"No such file or directory");
+					continue;
+				}
+				for (name, node) in files.iter().filter(|_| shell.can_read(&path)) {
+					let name = match strip_prefix(name, &prefix) {
+						Some(name) if !name.is_empty() && !name.contains(&b'/') => name,
+						_ => continue,
+					};
+					let (error, mode, size, mtime) = match node {
+						Node::Dir { mode, mtime } => (0, *mode, 4096, i64::from(*mtime)),
+						Node::File { mode, mtime, data } => {
+							(0, *mode, data.len() as u64, i64::from(*mtime))
+						}
+						Node::Symlink { target } => (0, 0o120_777, target.len() as u64, 0),
+						Node::Large { size, mtime } => (0, 0o100_644, *size, *mtime),
+						Node::Vanished { errno } => (*errno, 0, 0, 0),
+					};
+					let (nlink, times) = match error {
+						0 => (1, [mtime + 1, mtime, mtime + 2]),
+						_ => (0, [0; 3]),
+					};
+					stream.write_all(b"DNT2").unwrap();
+					stream.write_all(&error.to_le_bytes()).unwrap();
+					stream.write_all(&[0; 8 + 8]).unwrap(); // dev, ino
+					for field in &[mode, nlink, 0, 0] {
+						stream.write_all(&field.to_le_bytes()).unwrap(); // mode, nlink, uid, gid
+					}
+					stream.write_all(&size.to_le_bytes()).unwrap();
+					for time in &times {
+						stream.write_all(&time.to_le_bytes()).unwrap(); // atime, mtime, ctime
+					}
+					stream
+						.write_all(&(name.len() as u32).to_le_bytes())
+						.unwrap();
+					stream.write_all(name).unwrap();
+				}
+				stream.write_all(b"DONE").unwrap();
+				stream.write_all(&[0; 72]).unwrap();
+			}
+			b"STAT" => {
+				let mut path = vec![0; len];
+				stream.read_exact(&mut path).unwrap();
+				let (mode, size, mtime) = match files.lock().unwrap().get(&path) {
+					// adbd's `lstat` fails like for missing files.
+					_ if !shell.can_read(&path) => (0, 0, 0),
+					Some(Node::Dir { mode, mtime }) => (*mode, 4096, *mtime),
+					Some(Node::File { mode, mtime, data }) => (*mode, data.len() as u32, *mtime),
+					Some(Node::Symlink { .. }) => (0o120_777, 0, 0),
+					Some(Node::Large { size, mtime }) => (0o100_644, *size as u32, *mtime as u32),
+					Some(Node::Vanished { .. }) | None => (0, 0, 0),
+				};
+				stream.write_all(b"STAT").unwrap();
+				for field in &[mode, size, mtime] {
+					stream.write_all(&field.to_le_bytes()).unwrap();
+				}
+			}
+			b"RECV" => {
+				let mut path = vec![0; len];
+				stream.read_exact(&mut path).unwrap();
+				match files.lock().unwrap().get(&path) {
+					Some(Node::File { .. }) if !shell.can_read(&path) => {
+						fail(&mut stream, b"Permission denied");
+					}
+					Some(Node::File { data, .. }) => {
+						for chunk in data.chunks(3) {
+							stream.write_all(b"DATA").unwrap();
+							stream
+								.write_all(&(chunk.len() as u32).to_le_bytes())
+								.unwrap();
+							stream.write_all(chunk).unwrap();
+						}
+						stream.write_all(b"DONE\0\0\0\0").unwrap();
+					}
+					_ => fail(&mut stream, b"No such file or directory"),
+				}
+			}
+			b"SEND" => {
+				let mut target = vec![0; len];
+				stream.read_exact(&mut target).unwrap();
+				let comma = target.iter().rposition(|b| *b == b',').unwrap();
+				let mode = std::str::from_utf8(&target[comma + 1..])
+					.unwrap()
+					.parse()
+					.unwrap();
+				let path = target[..comma].to_vec();
+
+				let mut data = Vec::new();
+				let mtime = loop {
+					let mut header = [0; 8];
+					stream.read_exact(&mut header).unwrap();
+					let len = u32::from_le_bytes(header[4..].try_into().unwrap());
+					match &header[..4] {
+						b"DATA" => {
+							let start = data.len();
+							data.resize(start + len as usize, 0);
+							stream.read_exact(&mut data[start..]).unwrap();
+						}
+						b"DONE" => break len,
+						other => panic!("Unexpected send request {:?}", other),
+					}
+				};
+				if !shell.can_read(&path) {
+					fail(&mut stream, b"Permission denied");
+					continue;
+				}
+				files
+					.lock()
+					.unwrap()
+					.insert(path, Node::File { mode, mtime, data });
+				stream.write_all(b"OKAY\0\0\0\0").unwrap();
+			}
+			b"QUIT" => return,
+			other => panic!("Unexpected sync request {:?}", other),
+		}
+	}
+}
+
+fn fail(stream: &mut TcpStream, message: &[u8]) {
+	stream.write_all(b"FAIL").unwrap();
+	stream
+		.write_all(&(message.len() as u32).to_le_bytes())
+		.unwrap();
+	stream.write_all(message).unwrap();
+}
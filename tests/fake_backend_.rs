@@ -0,0 +1,387 @@
+#![allow(dead_code)]
+
+//! An in-memory [`Backend`] and [`Destination`] that can be told to fail, and helpers to inspect what a dump wrote.
+
+use adb_dump::{
+	archive::Format,
+	device::Security,
+	dump::{self, Backend, ErrorPolicy, MANIFEST_FILE_NAME},
+	restore::Destination,
+	verify::Algorithm,
+	Epoch, LsEntry, RawPath, RawPathBuf, RawStr, UnixMode,
+};
+use md5::Md5;
+use sha2::{Digest, Sha256};
+use std::{
+	collections::BTreeMap,
+	fs::{self, File},
+	io::{Error, ErrorKind, Read, Write},
+	path::{Path, PathBuf},
+	sync::Mutex,
+};
+use zip::ZipArchive;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Node {
+	Dir,
+	File(Vec<u8>),
+	Symlink(String),
+	/// A device node, FIFO or socket with this mode and device numbers.
+	Special(u32, (u32, u32)),
+}
+
+/// A node written by a restore, with the permissions and mtime set on it.
+pub type Restored = (Node, Option<(u32, i64)>);
+
+/// An owner and SELinux context set by a restore.
+pub type Relabeled = (Option<(u32, u32)>, Option<String>);
+
+#[derive(Default)]
+pub struct FakeBackend {
+	pub nodes: BTreeMap<Vec<u8>, Node>,
+	/// Owners and SELinux contexts reported for [`Backend::security`].
+	pub security: BTreeMap<Vec<u8>, (u32, u32, String)>,
+	/// Number of pulls left before every further request fails, if limited.
+	pub pulls_left: Mutex<Option<usize>>,
+	/// Paths that fail this many more times when listed or pulled. Pulls fail halfway through.
+	pub failing: Mutex<BTreeMap<Vec<u8>, usize>>,
+	/// Checksum tools the "device" has.
+	pub tools: Mutex<Vec<Algorithm>>,
+	/// What restores wrote.
+	pub restored: Mutex<BTreeMap<Vec<u8>, Restored>>,
+	/// What restores set owners and contexts on.
+	pub relabeled: Mutex<BTreeMap<Vec<u8>, Relabeled>>,
+	/// Every path listed (`ls`) or pulled (`pull`), in order.
+	pub requests: Mutex<Vec<(&'static str, Vec<u8>)>>,
+}
+
+impl FakeBackend {
+	pub fn new() -> Self {
+		let mut backend = Self::default();
+		backend.nodes.insert(b"/".to_vec(), Node::Dir);
+		backend
+	}
+
+	pub fn dir(mut self, path: &str) -> Self {
+		self.nodes.insert(path.as_bytes().to_vec(), Node::Dir);
+		self
+	}
+
+	pub fn file(self, path: &str, data: &[u8]) -> Self {
+		self.raw_file(path.as_bytes(), data)
+	}
+
+	/// Like [`file`](Self::file), for names that aren't UTF-8.
+	pub fn raw_file(mut self, path: &[u8], data: &[u8]) -> Self {
+		self.nodes.insert(path.to_vec(), Node::File(data.to_vec()));
+		self
+	}
+
+	pub fn symlink(mut self, path: &str, target: &str) -> Self {
+		self.nodes
+			.insert(path.as_bytes().to_vec(), Node::Symlink(target.to_string()));
+		self
+	}
+
+	pub fn special(mut self, path: &str, mode: u32, numbers: (u32, u32)) -> Self {
+		self.nodes
+			.insert(path.as_bytes().to_vec(), Node::Special(mode, numbers));
+		self
+	}
+
+	/// Gives the existing node at `path` an owner and SELinux context.
+	pub fn label(mut self, path: &str, uid: u32, gid: u32, context: &str) -> Self {
+		self.security
+			.insert(path.as_bytes().to_vec(), (uid, gid, context.to_string()));
+		self
+	}
+
+	/// Simulates the cable being pulled after `count` more files.
+	pub fn disconnect_after(&self, count: usize) {
+		*self.pulls_left.lock().unwrap() = Some(count);
+	}
+
+	pub fn reconnect(&self) {
+		*self.pulls_left.lock().unwrap() = None;
+	}
+
+	pub fn fail(&self, path: &str, times: usize) {
+		self.failing
+			.lock()
+			.unwrap()
+			.insert(path.as_bytes().to_vec(), times);
+	}
+
+	fn check_failing(&self, path: &RawPath) -> Result<(), Error> {
+		match self.failing.lock().unwrap().get_mut(&path.to_vec()) {
+			Some(times) if *times > 0 => {
+				*times -= 1;
+				Err(Error::new(ErrorKind::Other, "flaky"))
+			}
+			_ => Ok(()),
+		}
+	}
+
+	fn check_connected(&self) -> Result<(), Error> {
+		match *self.pulls_left.lock().unwrap() {
+			Some(0) => Err(Error::new(ErrorKind::BrokenPipe, "device disconnected")),
+			_ => Ok(()),
+		}
+	}
+}
+
+impl Backend for FakeBackend {
+	fn ls(&self, path: &RawPath) -> Result<Vec<LsEntry>, Error> {
+		self.requests.lock().unwrap().push(("ls", path.to_vec()));
+		self.check_connected()?;
+		self.check_failing(path)?;
+		let mut prefix = path.to_vec();
+		if !prefix.ends_with(b"/") {
+			prefix.push(b'/');
+		}
+		Ok(self
+			.nodes
+			.iter()
+			.filter_map(|(name, node)| {
+				let name = strip_prefix(name, &prefix)?;
+				if name.is_empty() || name.contains(&b'/') {
+					return None;
+				}
+				let (mode, size) = match node {
+					Node::Dir => (0o040_755, 4096),
+					Node::File(data) => (0o100_644, data.len() as u64),
+					Node::Symlink(target) => (0o120_777, target.len() as u64),
+					Node::Special(mode, _) => (*mode, 0),
+				};
+				Some(LsEntry {
+					mode: UnixMode::new(mode),
+					size,
+					mtime: Epoch::from_timestamp(1_600_000_000),
+					atime: None,
+					ctime: None,
+					uid: Some(1023),
+					gid: Some(1023),
+					context: None,
+					name: name.to_vec().into(),
+				})
+			})
+			.collect())
+	}
+
+	fn pull_to(
+		&self,
+		path: &RawPath,
+		_expected_size: u64,
+		target: &mut dyn Write,
+	) -> Result<u64, Error> {
+		self.requests.lock().unwrap().push(("pull", path.to_vec()));
+		self.check_connected()?;
+		if let Some(left) = &mut *self.pulls_left.lock().unwrap() {
+			*left -= 1;
+		}
+		match self.nodes.get(&path.to_vec()) {
+			Some(Node::File(data)) => {
+				if self.check_failing(path).is_err() {
+					target.write_all(&data[..data.len() / 2])?;
+					return Err(Error::new(ErrorKind::Other, "flaky"));
+				}
+				target.write_all(data)?;
+				Ok(data.len() as u64)
+			}
+			_ => Err(Error::new(ErrorKind::NotFound, "No such file")),
+		}
+	}
+
+	fn readlink(&self, path: &RawPath) -> Result<RawPathBuf, Error> {
+		self.check_connected()?;
+		self.check_failing(path)?;
+		match self.nodes.get(&path.to_vec()) {
+			Some(Node::Symlink(target)) => Ok(target.as_str().into()),
+			_ => Err(Error::new(ErrorKind::InvalidInput, "Not a symlink")),
+		}
+	}
+
+	fn device_numbers(&self, path: &RawPath) -> Result<(u32, u32), Error> {
+		self.check_connected()?;
+		match self.nodes.get(&path.to_vec()) {
+			Some(Node::Special(_, numbers)) => Ok(*numbers),
+			_ => Err(Error::new(ErrorKind::InvalidInput, "Not a device")),
+		}
+	}
+
+	fn checksum(&self, path: &RawPath, algorithm: Algorithm) -> Result<Option<Vec<u8>>, Error> {
+		self.check_connected()?;
+		self.check_failing(path)?;
+		if !self.tools.lock().unwrap().contains(&algorithm) {
+			return Ok(None);
+		}
+		let data = match self.nodes.get(&path.to_vec()) {
+			Some(Node::File(data)) => data.as_slice(),
+			_ if **path == "/dev/null" => &[],
+			_ => return Err(Error::new(ErrorKind::NotFound, "No such file")),
+		};
+		Ok(Some(match algorithm {
+			Algorithm::Sha256 => Sha256::digest(data).to_vec(),
+			Algorithm::Md5 => Md5::digest(data).to_vec(),
+		}))
+	}
+
+	fn security(&self, path: &RawPath) -> Result<Vec<Security>, Error> {
+		self.check_connected()?;
+		let mut prefix = path.to_vec();
+		if !prefix.ends_with(b"/") {
+			prefix.push(b'/');
+		}
+		Ok(self
+			.security
+			.iter()
+			.filter_map(|(name, (uid, gid, context))| {
+				let name = strip_prefix(name, &prefix)?;
+				if name.contains(&b'/') {
+					return None;
+				}
+				Some(Security {
+					name: name.to_vec().into(),
+					uid: *uid,
+					gid: *gid,
+					context: Some(context.as_str().into()),
+				})
+			})
+			.collect())
+	}
+}
+
+impl Destination for FakeBackend {
+	fn exists(&self, path: &RawPath) -> Result<bool, Error> {
+		self.check_failing(path)?;
+		Ok(self.nodes.contains_key(&path.to_vec())
+			|| self.restored.lock().unwrap().contains_key(&path.to_vec()))
+	}
+
+	fn create_dir(&self, path: &RawPath) -> Result<(), Error> {
+		self.restored
+			.lock()
+			.unwrap()
+			.entry(path.to_vec())
+			.or_insert((Node::Dir, None));
+		Ok(())
+	}
+
+	fn push(
+		&self,
+		path: &RawPath,
+		permissions: u32,
+		mtime: i64,
+		data: &mut dyn Read,
+	) -> Result<u64, Error> {
+		let mut buffer = Vec::new();
+		data.read_to_end(&mut buffer)?;
+		let written = buffer.len() as u64;
+		self.restored.lock().unwrap().insert(
+			path.to_vec(),
+			(Node::File(buffer), Some((permissions, mtime))),
+		);
+		Ok(written)
+	}
+
+	fn symlink(&self, path: &RawPath, target: &RawPath) -> Result<(), Error> {
+		self.restored.lock().unwrap().insert(
+			path.to_vec(),
+			(Node::Symlink(target.to_string_panicky()), None),
+		);
+		Ok(())
+	}
+
+	fn set_metadata(&self, path: &RawPath, permissions: u32, mtime: i64) -> Result<(), Error> {
+		let mut restored = self.restored.lock().unwrap();
+		let node = match (restored.get(&path.to_vec()), self.nodes.get(&path.to_vec())) {
+			(Some((node, _)), _) | (None, Some(node)) => node.clone(),
+			(None, None) => return Err(Error::new(ErrorKind::NotFound, "No such file")),
+		};
+		restored.insert(path.to_vec(), (node, Some((permissions, mtime))));
+		Ok(())
+	}
+
+	fn set_security(
+		&self,
+		path: &RawPath,
+		owner: Option<(u32, u32)>,
+		context: Option<&RawStr>,
+	) -> Result<(), Error> {
+		self.relabeled.lock().unwrap().insert(
+			path.to_vec(),
+			(owner, context.map(RawStr::to_string_panicky)),
+		);
+		Ok(())
+	}
+}
+
+fn strip_prefix<'a>(data: &'a [u8], prefix: &[u8]) -> Option<&'a [u8]> {
+	if data.starts_with(prefix) {
+		Some(&data[prefix.len()..])
+	} else {
+		None
+	}
+}
+
+/// A fresh, empty directory for a test's output.
+pub fn output_dir(name: &str) -> PathBuf {
+	let dir = std::env::temp_dir().join(format!("adb-dump-test-{}-{}", std::process::id(), name));
+	let _ = fs::remove_dir_all(&dir);
+	fs::create_dir_all(&dir).unwrap();
+	dir
+}
+
+/// Options to dump `/sdcard` into `output_dir` as ZIP, one file per volume, aborting on the first error.
+///
+/// Tests override the fields they care about.
+pub fn dump_options(output_dir: &Path) -> dump::Options {
+	dump::Options {
+		paths: vec!["/sdcard".into()],
+		output_dir: output_dir.to_owned(),
+		split_size: 1,
+		split: false,
+		format: Format::Zip,
+		verbosity: -1,
+		dry_run: false,
+		resume: false,
+		error_policy: ErrorPolicy::Abort,
+		since: Vec::new(),
+		compare_checksums: false,
+		jobs: 1,
+		scan: false,
+		progress: None,
+		security: false,
+	}
+}
+
+/// The names of all archives in `dir`, sorted.
+pub fn archives(dir: &Path) -> Vec<String> {
+	let mut names: Vec<_> = fs::read_dir(dir)
+		.unwrap()
+		.map(|entry| entry.unwrap().file_name().into_string().unwrap())
+		.filter(|name| name.ends_with(".zip"))
+		.collect();
+	names.sort();
+	names
+}
+
+/// All file entries (name and contents) in the readable archives in `dir`, except the checksum manifests.
+pub fn archived_files(dir: &Path) -> BTreeMap<String, Vec<u8>> {
+	let mut files = BTreeMap::new();
+	for name in archives(dir) {
+		let mut archive = match ZipArchive::new(File::open(dir.join(name)).unwrap()) {
+			Ok(archive) => archive,
+			Err(_) => continue, // Left behind by an interrupted run.
+		};
+		for i in 0..archive.len() {
+			let mut file = archive.by_index(i).unwrap();
+			if file.is_file() && file.name() != MANIFEST_FILE_NAME {
+				let mut data = Vec::new();
+				file.read_to_end(&mut data).unwrap();
+				files.insert(file.name().to_string(), data);
+			}
+		}
+	}
+	files
+}
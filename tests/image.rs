@@ -0,0 +1,201 @@
+#![cfg(not(miri))]
+
+#[path = "fake_adb_.rs"]
+mod fake_adb;
+use adb_dump::{
+	access::RootStrategy,
+	check,
+	error::AdbError,
+	image::{self, PARTITIONS_FILE_NAME},
+};
+use fake_adb::{FakeAdb, Shell};
+use sha2::{Digest, Sha256};
+use std::{fs, io::ErrorKind, path::PathBuf};
+
+fn output_dir(name: &str) -> PathBuf {
+	let dir = std::env::temp_dir().join(format!("adb-dump-test-{}-{}", std::process::id(), name));
+	let _ = fs::remove_dir_all(&dir);
+	dir
+}
+
+fn adb() -> FakeAdb {
+	let adb = FakeAdb::start("fake-serial");
+	adb.file(
+		"/proc/partitions",
+		0,
+		b"major minor  #blocks  name\n\
+		\n   8        0    4194304 sda\n   8        1          4 sda1\n   8        2          7 sda2\n\
+		\x20  8        3          8 sda3\n 253        0       1024 dm-0\n",
+	)
+	.symlink("/dev/block/by-name/persist", b"/dev/block/sda1")
+	.symlink("/dev/block/by-name/modemst1", b"/dev/block/sda2")
+	.symlink("/dev/block/by-name/broken", b"/dev/block/sda3")
+	.symlink("/dev/block/by-name/elsewhere", b"/dev/block/sdz9")
+	.file("/dev/block/sda1", 0, &[1; 4096])
+	// Not a whole number of KiB, which only `blockdev` gets right.
+	.file("/dev/block/sda2", 0, &[2; 7680]);
+	adb
+}
+
+#[test]
+fn partitions() {
+	let partitions = image::partitions(&adb().device()).unwrap();
+	let lines: Vec<_> = partitions.iter().map(ToString::to_string).collect();
+	assert_eq!(
+		lines,
+		[
+			"broken\t/dev/block/sda3\t8:3\t8192",
+			"modemst1\t/dev/block/sda2\t8:2\t7168",
+			"persist\t/dev/block/sda1\t8:1\t4096",
+			"sda\t/dev/block/sda\t8:0\t4294967296",
+			"dm-0\t/dev/block/dm-0\t253:0\t1048576",
+		]
+	);
+}
+
+#[test]
+fn older_by_name_dir() {
+	let adb = FakeAdb::start("fake-serial");
+	// Listing through `su` fails for the missing `/dev/block/by-name`.
+	*adb.shell.lock().unwrap() = Shell {
+		uid: 2000,
+		su: Some("su -c "),
+		..Shell::default()
+	};
+	adb.file(
+		"/proc/partitions",
+		0,
+		b"major minor  #blocks  name\n\n 179        5       2048 mmcblk0p5\n",
+	)
+	.dir("/dev/block/bootdevice/by-name", 0)
+	.symlink(
+		"/dev/block/bootdevice/by-name/persist",
+		b"/dev/block/mmcblk0p5",
+	);
+	let device = adb.device();
+	device.gain_root(RootStrategy::Su).unwrap();
+
+	let partitions = image::partitions(&device).unwrap();
+	let lines: Vec<_> = partitions.iter().map(ToString::to_string).collect();
+	assert_eq!(lines, ["persist\t/dev/block/mmcblk0p5\t179:5\t2097152"]);
+}
+
+#[test]
+fn images() {
+	let adb = adb();
+	let dir = output_dir("image-images");
+	let options = |partitions: &[&str]| image::Options {
+		output_dir: dir.clone(),
+		partitions: partitions.iter().map(ToString::to_string).collect(),
+		verbosity: -1,
+	};
+
+	let images = image::run(&adb.device(), &options(&["persist", "sda2"])).unwrap();
+	assert_eq!(images.len(), 2);
+	assert_eq!(images[1].partition.name, "modemst1");
+	assert_eq!(images[1].size, 7680);
+	assert_eq!(fs::read(dir.join("persist.img")).unwrap(), [1; 4096]);
+	assert_eq!(fs::read(dir.join("modemst1.img")).unwrap(), [2; 7680]);
+	let persist = hex::encode(Sha256::digest([1; 4096]));
+	let modemst1 = hex::encode(Sha256::digest([2; 7680]));
+	assert_eq!(images[0].sha256, hex::decode(&persist).unwrap());
+
+	let table = || fs::read_to_string(dir.join(PARTITIONS_FILE_NAME)).unwrap();
+	let expected = format!(
+		"broken\t/dev/block/sda3\t8:3\t8192\n\
+		modemst1\t/dev/block/sda2\t8:2\t7168\tmodemst1.img\t7680\t{}\n\
+		persist\t/dev/block/sda1\t8:1\t4096\tpersist.img\t4096\t{}\n\
+		sda\t/dev/block/sda\t8:0\t4294967296\n\
+		dm-0\t/dev/block/dm-0\t253:0\t1048576\n",
+		modemst1, persist
+	);
+	assert_eq!(table(), expected);
+
+	// Imaging another partition keeps the earlier ones listed.
+	adb.file("/dev/block/sda1", 0, &[3; 4096]);
+	image::run(&adb.device(), &options(&["persist"])).unwrap();
+	let persist = hex::encode(Sha256::digest([3; 4096]));
+	assert_eq!(
+		table(),
+		expected.replace(&hex::encode(Sha256::digest([1; 4096])), &persist)
+	);
+}
+
+#[test]
+fn checked() {
+	let adb = adb();
+	let dir = output_dir("image-checked");
+	image::run(
+		&adb.device(),
+		&image::Options {
+			output_dir: dir.clone(),
+			partitions: vec!["persist".to_string(), "modemst1".to_string()],
+			verbosity: -1,
+		},
+	)
+	.unwrap();
+	let check = || {
+		let report = check::run(&check::Options {
+			output_dir: dir.clone(),
+			verbosity: -1,
+		})
+		.unwrap();
+		assert_eq!(report.images, 2);
+		report
+			.findings
+			.iter()
+			.map(ToString::to_string)
+			.collect::<Vec<_>>()
+	};
+	assert!(check().is_empty());
+
+	let mut modemst1 = fs::read(dir.join("modemst1.img")).unwrap();
+	modemst1[0] = 0;
+	fs::write(dir.join("modemst1.img"), &modemst1).unwrap();
+	fs::remove_file(dir.join("persist.img")).unwrap();
+	assert_eq!(check(), ["content\tmodemst1.img", "missing\tpersist.img"]);
+	fs::write(dir.join("modemst1.img"), b"short").unwrap();
+	assert_eq!(
+		check(),
+		["size\tmodemst1.img\t7680\t5", "missing\tpersist.img"]
+	);
+}
+
+#[test]
+fn failures() {
+	let adb = adb();
+	let dir = output_dir("image-failures");
+	let options = |partition: &str| image::Options {
+		output_dir: dir.clone(),
+		partitions: vec![partition.to_string()],
+		verbosity: -1,
+	};
+
+	let error = image::run(&adb.device(), &options("efs")).unwrap_err();
+	assert_eq!(error.kind(), ErrorKind::NotFound);
+
+	// `dd` yields nothing for `broken`.
+	let error = image::run(&adb.device(), &options("broken")).unwrap_err();
+	assert!(
+		matches!(
+			AdbError::of(&error),
+			Some(AdbError::SizeMismatch {
+				expected: 8192,
+				actual: 0,
+				..
+			})
+		),
+		"{:?}",
+		error
+	);
+	assert_eq!(fs::read_dir(&dir).unwrap().count(), 0);
+
+	// The partial image is removed when other steps fail, too.
+	fs::create_dir(dir.join("persist.img")).unwrap();
+	image::run(&adb.device(), &options("persist")).unwrap_err();
+	let names: Vec<_> = fs::read_dir(&dir)
+		.unwrap()
+		.map(|entry| entry.unwrap().file_name())
+		.collect();
+	assert_eq!(names, ["persist.img"]);
+}
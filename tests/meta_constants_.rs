@@ -2,4 +2,4 @@
 
 pub const BRANCH: &str = "develop";
 pub const USER: &str = "Tamschi";
-pub const RUST_VERSION: &str = "1.49.0";
+pub const RUST_VERSION: &str = "1.67.0";
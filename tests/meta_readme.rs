@@ -12,3 +12,28 @@ fn versioning() {
 		r"^`{name}` strictly follows \[Semantic Versioning 2\.0\.0\]"
 	);
 }
+
+/// Keeps the CLI section in sync with `adb-dump --help`.
+///
+/// On mismatch, README.md is rewritten in place so the change can be reviewed and committed.
+#[test]
+fn cli() {
+	let output = std::process::Command::new(env!("CARGO_BIN_EXE_adb-dump"))
+		.arg("--help")
+		.output()
+		.unwrap();
+	assert!(output.status.success());
+	let help = String::from_utf8(output.stdout).unwrap();
+
+	let readme = std::fs::read_to_string("README.md").unwrap();
+	let start = readme.find("## CLI\n\n```text\n").unwrap() + "## CLI\n\n```text\n".len();
+	let end = start + readme[start..].find("```\n").unwrap();
+	if readme[start..end] != help {
+		std::fs::write(
+			"README.md",
+			format!("{}{}{}", &readme[..start], help, &readme[end..]),
+		)
+		.unwrap();
+		panic!("The CLI section in README.md was outdated and has been regenerated.");
+	}
+}
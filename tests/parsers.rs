@@ -0,0 +1,187 @@
+//! Parsers of server and device output, fed with captures from Linux and Windows hosts and old and new devices.
+//!
+//! None of these need an ADB server, so they run the same on every host.
+
+use adb_dump::{
+	client::AdbClient,
+	device::{DeviceInfo, DeviceState, Mount, Security},
+	image::BlockDevice,
+	RawStr,
+};
+use std::net::Ipv4Addr;
+
+fn lines(output: &[u8]) -> Vec<String> {
+	RawStr::new(output)
+		.lines()
+		.map(|line| line.to_string_panicky())
+		.collect()
+}
+
+#[test]
+fn line_endings() {
+	let expected = ["first", "second", "", "third"];
+	// LF, from Linux and macOS hosts and `exec:` services.
+	assert_eq!(lines(b"first\nsecond\n\nthird\n"), expected);
+	// CRLF, from Windows hosts and `shell:` PTYs.
+	assert_eq!(lines(b"first\r\nsecond\r\n\r\nthird\r\n"), expected);
+	// What the PTYs of devices before Android 7 make of CRLF.
+	assert_eq!(lines(b"first\r\r\nsecond\r\r\n\r\r\nthird\r\r\n"), expected);
+	// Mixed, as when a device's CRLF goes through a host that doesn't touch it.
+	assert_eq!(lines(b"first\nsecond\r\n\r\r\nthird"), expected);
+}
+
+#[test]
+fn line_edge_cases() {
+	assert!(lines(b"").is_empty());
+	assert_eq!(lines(b"\n"), [""]);
+	assert_eq!(lines(b"unterminated"), ["unterminated"]);
+	assert_eq!(lines(b"cut off\r"), ["cut off"]);
+	assert_eq!(lines(b"\r"), [""]);
+	assert_eq!(lines(b"a\n\r"), ["a", ""]);
+	// A `\r` inside a line is kept.
+	assert_eq!(lines(b"carriage\rreturn\n"), ["carriage\rreturn"]);
+}
+
+fn devices(listing: &[u8]) -> Vec<DeviceInfo> {
+	let client = AdbClient::new((Ipv4Addr::LOCALHOST, 5037));
+	RawStr::new(listing)
+		.lines()
+		.filter(|line| !line.iter().all(u8::is_ascii_whitespace))
+		.map(|line| DeviceInfo::parse(client, line).unwrap())
+		.collect()
+}
+
+#[test]
+fn device_listings() {
+	let linux: &[u8] = b"0123456789ABCDEF       device usb:1-1 product:sailfish model:Pixel device:sailfish transport_id:1\n\
+		emulator-5554          offline transport_id:2\n";
+	let windows: &[u8] = b"0123456789ABCDEF       device usb:1-1 product:sailfish model:Pixel device:sailfish transport_id:1\r\n\
+		emulator-5554          offline transport_id:2\r\n";
+	for (host, listing) in [("Linux", linux), ("Windows", windows)] {
+		let devices = devices(listing);
+		assert_eq!(devices.len(), 2, "{}", host);
+		assert_eq!(*devices[0].serial_number, "0123456789ABCDEF", "{}", host);
+		assert_eq!(devices[0].state, DeviceState::Device, "{}", host);
+		assert_eq!(devices[0].product.as_deref(), Some("sailfish"), "{}", host);
+		assert_eq!(devices[0].model.as_deref(), Some("Pixel"), "{}", host);
+		assert_eq!(devices[0].device.as_deref(), Some("sailfish"), "{}", host);
+		assert_eq!(devices[0].transport_id, Some(1), "{}", host);
+		assert_eq!(*devices[1].serial_number, "emulator-5554", "{}", host);
+		assert_eq!(devices[1].state, DeviceState::Offline, "{}", host);
+		assert_eq!(devices[1].transport_id, Some(2), "{}", host);
+	}
+
+	// Older servers leave out `-l`'s details and end with an empty line.
+	let old = devices(b"0123456789ABCDEF\tdevice\r\n\r\n");
+	assert_eq!(old.len(), 1);
+	assert_eq!(*old[0].serial_number, "0123456789ABCDEF");
+	assert_eq!(old[0].state, DeviceState::Device);
+	assert_eq!(old[0].transport_id, None);
+
+	assert!(devices(b"").is_empty());
+}
+
+fn security(output: &[u8]) -> Vec<(String, u32, u32, Option<String>)> {
+	RawStr::new(output)
+		.lines()
+		.filter_map(Security::parse)
+		.map(|security| {
+			(
+				security.name.to_string_panicky(),
+				security.uid,
+				security.gid,
+				security.context.map(|context| context.to_string_panicky()),
+			)
+		})
+		.collect()
+}
+
+#[test]
+fn stat_output() {
+	let expected = vec![
+		(
+			"DCIM".to_string(),
+			1023,
+			1023,
+			Some("u:object_r:media_rw_data_file:s0".to_string()),
+		),
+		(
+			"with space".to_string(),
+			10123,
+			1023,
+			Some("u:object_r:media_rw_data_file:s0:c123,c256,c512,c768".to_string()),
+		),
+	];
+	let linux: &[u8] = b"1023 1023 u:object_r:media_rw_data_file:s0 ./DCIM\n\
+		10123 1023 u:object_r:media_rw_data_file:s0:c123,c256,c512,c768 ./with space\n";
+	let windows: &[u8] = b"1023 1023 u:object_r:media_rw_data_file:s0 ./DCIM\r\n\
+		10123 1023 u:object_r:media_rw_data_file:s0:c123,c256,c512,c768 ./with space\r\n";
+	let pty: &[u8] = b"1023 1023 u:object_r:media_rw_data_file:s0 ./DCIM\r\r\n\
+		10123 1023 u:object_r:media_rw_data_file:s0:c123,c256,c512,c768 ./with space\r\r\n";
+	assert_eq!(security(linux), expected, "Linux");
+	assert_eq!(security(windows), expected, "Windows");
+	assert_eq!(security(pty), expected, "PTY");
+	// Cut off in the middle of a line, the name is still whole as far as it goes.
+	assert_eq!(
+		security(&linux[..linux.len() - 1]),
+		expected,
+		"unterminated"
+	);
+
+	// Without SELinux, `stat` prints `?`.
+	assert_eq!(
+		security(b"0 0 ? ./data\r\n"),
+		[("data".to_string(), 0, 0, None)]
+	);
+	// Anything but direct children is ignored.
+	assert!(security(b"0 0 ? ./a/b\n0 0 ? .\nstat: permission denied\n").is_empty());
+}
+
+#[test]
+fn proc_mounts() {
+	let mounts: Vec<Mount> = RawStr::new(
+		b"rootfs / rootfs ro,seclabel 0 0\r\n\
+		/dev/block/dm-0 /data ext4 rw,seclabel,nosuid,nodev,noatime 0 0\r\n\
+		/dev/fuse /mnt/with\\040space fuse ro,nosuid 0 0",
+	)
+	.lines()
+	.filter_map(Mount::parse)
+	.collect();
+	assert_eq!(mounts.len(), 3);
+	assert_eq!(mounts[1].source, "/dev/block/dm-0");
+	assert_eq!(**mounts[1].mount_point, "/data");
+	assert_eq!(mounts[1].fs_type, "ext4");
+	assert!(!mounts[1].is_read_only());
+	assert_eq!(**mounts[2].mount_point, "/mnt/with space");
+	assert!(mounts[2].is_read_only());
+}
+
+#[test]
+fn proc_partitions() {
+	let partitions: Vec<BlockDevice> = RawStr::new(
+		b"major minor  #blocks  name\r\n\
+		\r\n\
+		 179        0   61071360 mmcblk0\r\n\
+		 179        5       2048 mmcblk0p5\r\n",
+	)
+	.lines()
+	.filter_map(BlockDevice::parse)
+	.collect();
+	assert_eq!(
+		partitions,
+		[
+			BlockDevice {
+				name: "mmcblk0".to_string(),
+				major: 179,
+				minor: 0,
+				size: 61_071_360 * 1024,
+			},
+			BlockDevice {
+				name: "mmcblk0p5".to_string(),
+				major: 179,
+				minor: 5,
+				size: 2048 * 1024,
+			},
+		]
+	);
+}
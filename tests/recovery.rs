@@ -0,0 +1,104 @@
+#![cfg(not(miri))]
+
+#[path = "fake_adb_.rs"]
+mod fake_adb;
+use adb_dump::{
+	device::DeviceState,
+	recovery::{self, Encryption, Problem, Status},
+	RawPathBuf,
+};
+use fake_adb::{FakeAdb, Shell};
+
+fn adb(shell: Shell) -> FakeAdb {
+	let adb = FakeAdb::start("fake-serial");
+	*adb.state.lock().unwrap() = "recovery";
+	*adb.shell.lock().unwrap() = Shell {
+		twrp: Some("3.7.0_12-0"),
+		..shell
+	};
+	adb
+}
+
+fn paths(paths: &[&str]) -> Vec<RawPathBuf> {
+	paths.iter().map(|path| (*path).into()).collect()
+}
+
+#[test]
+fn booted() {
+	let adb = adb(Shell::default());
+	*adb.state.lock().unwrap() = "device";
+	let status = Status::probe(&adb.device()).unwrap();
+	assert_eq!(status.state, DeviceState::Device);
+	assert!(status.problems(&paths(&["/data"])).is_empty());
+}
+
+#[test]
+fn not_mounted() {
+	let adb = adb(Shell {
+		mounts: vec!["/cache"],
+		..Shell::default()
+	});
+	let device = adb.device();
+	let paths = paths(&["/sdcard", "/system/app", "/cache/recovery", "/proc"]);
+
+	let status = Status::probe(&device).unwrap();
+	assert_eq!(status.state, DeviceState::Recovery);
+	assert!(status.is_twrp());
+	assert_eq!(status.twrp["ro.twrp.version"], "3.7.0_12-0");
+	assert_eq!(status.encryption, None);
+	assert_eq!(
+		status.problems(&paths),
+		[Problem::NotMounted("/data"), Problem::NotMounted("/system")]
+	);
+
+	recovery::mount(&device, &status.unmounted(&paths)).unwrap();
+	let status = Status::probe(&device).unwrap();
+	assert!(status.is_mounted("/data"));
+	assert!(status.problems(&paths).is_empty());
+}
+
+#[test]
+fn full_disk_encryption() {
+	let adb = adb(Shell {
+		props: vec![("ro.crypto.state", "encrypted")],
+		..Shell::default()
+	});
+	let status = Status::probe(&adb.device()).unwrap();
+	assert_eq!(status.encryption, Some(Encryption::FullDisk));
+	let paths = paths(&["/data/media/0"]);
+	assert_eq!(
+		status.problems(&paths),
+		[Problem::Encrypted(Encryption::FullDisk)]
+	);
+	// Mounting won't help.
+	assert!(status.unmounted(&paths).is_empty());
+}
+
+#[test]
+fn file_based_encryption() {
+	let adb = adb(Shell {
+		props: vec![("ro.crypto.state", "encrypted"), ("ro.crypto.type", "file")],
+		mounts: vec!["/data"],
+		..Shell::default()
+	});
+	adb.dir("/data/data", 1)
+		.dir("/data/data/I6JeK0ZsqHw4uAYd,kg9eA", 1)
+		.dir("/data/data/Xz+u1hMxBCq3GRV5Tdeb0B", 1);
+	let paths = paths(&["/"]);
+
+	let status = Status::probe(&adb.device()).unwrap();
+	assert_eq!(status.encryption, Some(Encryption::FileBased));
+	assert_eq!(status.decrypted, Some(false));
+	assert_eq!(
+		status.problems(&paths),
+		[Problem::Encrypted(Encryption::FileBased)]
+	);
+
+	adb.files.lock().unwrap().clear();
+	adb.dir("/data/data", 1)
+		.dir("/data/data/android", 1)
+		.dir("/data/data/com.android.providers.settings", 1);
+	let status = Status::probe(&adb.device()).unwrap();
+	assert_eq!(status.decrypted, Some(true));
+	assert!(status.problems(&paths).is_empty());
+}
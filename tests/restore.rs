@@ -0,0 +1,338 @@
+#![cfg(not(miri))]
+
+use adb_dump::{
+	archive::{Format, Metadata},
+	dump,
+	restore::{self, Problem},
+	rules::{Rule, Rules},
+	RawPath,
+};
+use std::path::Path;
+
+#[path = "fake_backend_.rs"]
+mod fake_backend;
+use fake_backend::{dump_options, output_dir, FakeBackend, Node, Relabeled, Restored};
+
+fn backend() -> FakeBackend {
+	FakeBackend::new()
+		.dir("/sdcard")
+		.dir("/sdcard/DCIM")
+		.file("/sdcard/DCIM/photo.jpg", b"photo")
+		.dir("/sdcard/Music")
+		.file("/sdcard/Music/song.mp3", b"song")
+		.file("/sdcard/notes.txt", b"notes")
+		.symlink("/sdcard/link", "/sdcard/notes.txt")
+}
+
+fn dump(dir: &Path, format: Format, security: bool) {
+	dump::run(
+		&backend()
+			.label(
+				"/sdcard/DCIM",
+				1023,
+				1023,
+				"u:object_r:media_rw_data_file:s0",
+			)
+			.label(
+				"/sdcard/DCIM/photo.jpg",
+				10123,
+				1023,
+				"u:object_r:media_rw_data_file:s0:c512",
+			)
+			.label("/sdcard/link", 0, 0, "u:object_r:rootfs:s0"),
+		&Rules::new(),
+		&dump::Options {
+			split_size: 8,
+			format,
+			security,
+			..dump_options(dir)
+		},
+	)
+	.unwrap();
+}
+
+fn options(dir: &Path) -> restore::Options {
+	restore::Options {
+		output_dirs: vec![dir.to_owned()],
+		root: "/".into(),
+		remap: Vec::new(),
+		only: Vec::new(),
+		overwrite: false,
+		security: false,
+		verbosity: -1,
+	}
+}
+
+fn restored(destination: &FakeBackend) -> Vec<(String, Restored)> {
+	destination
+		.restored
+		.lock()
+		.unwrap()
+		.iter()
+		.map(|(path, restored)| (String::from_utf8(path.clone()).unwrap(), restored.clone()))
+		.collect()
+}
+
+#[test]
+fn round_trip() {
+	for format in [Format::Zip, Format::Tar] {
+		let dir = output_dir(&format!("restore-round_trip-{}", format.extension()));
+		dump(&dir, format, false);
+
+		let destination = FakeBackend::new();
+		let report = restore::run(&destination, &options(&dir)).unwrap();
+		assert!(report.is_ok(), "{:?}", report.findings);
+		assert_eq!(report.restored, 4);
+
+		let file = |data: &[u8]| (Node::File(data.to_vec()), Some((0o644, 1_600_000_000)));
+		let dir = || (Node::Dir, Some((0o755, 1_600_000_000)));
+		let expected = vec![
+			("/sdcard/DCIM", dir()),
+			("/sdcard/DCIM/photo.jpg", file(b"photo")),
+			(
+				"/sdcard/link",
+				(Node::Symlink("/sdcard/notes.txt".to_string()), None),
+			),
+			("/sdcard/Music", dir()),
+			("/sdcard/Music/song.mp3", file(b"song")),
+			("/sdcard/notes.txt", file(b"notes")),
+		];
+		let mut expected: Vec<_> = expected
+			.into_iter()
+			.map(|(path, restored)| (path.to_string(), restored))
+			.collect();
+		expected.sort_by(|a, b| a.0.cmp(&b.0));
+		assert_eq!(restored(&destination), expected, "{:?}", format);
+	}
+}
+
+#[test]
+fn refuses_to_overwrite() {
+	let dir = output_dir("restore-refuses_to_overwrite");
+	dump(&dir, Format::Zip, false);
+
+	let destination = backend();
+	let report = restore::run(&destination, &options(&dir)).unwrap();
+	assert_eq!(report.restored, 0);
+	let mut existing: Vec<_> = report
+		.findings
+		.iter()
+		.map(|finding| {
+			assert!(matches!(finding.problem, Problem::Exists));
+			finding.path.to_string_panicky()
+		})
+		.collect();
+	existing.sort();
+	assert_eq!(
+		existing,
+		[
+			"/sdcard/DCIM/photo.jpg",
+			"/sdcard/Music/song.mp3",
+			"/sdcard/link",
+			"/sdcard/notes.txt",
+		]
+	);
+	// Existing directories are left alone too.
+	assert!(restored(&destination).is_empty());
+
+	let report = restore::run(
+		&destination,
+		&restore::Options {
+			overwrite: true,
+			..options(&dir)
+		},
+	)
+	.unwrap();
+	assert!(report.is_ok(), "{:?}", report.findings);
+	assert_eq!(report.restored, 4);
+}
+
+#[test]
+fn selective_and_remapped() {
+	let dir = output_dir("restore-selective_and_remapped");
+	dump(&dir, Format::Tar, false);
+
+	let destination = FakeBackend::new();
+	let report = restore::run(
+		&destination,
+		&restore::Options {
+			remap: vec![
+				("/sdcard".into(), "/storage/emulated/0".into()),
+				("/sdcard/DCIM".into(), "/storage/photos".into()),
+			],
+			only: vec![
+				Rule::parse("DCIM", "test").unwrap(),
+				Rule::parse("*.txt", "test").unwrap(),
+			],
+			..options(&dir)
+		},
+	)
+	.unwrap();
+	assert!(report.is_ok(), "{:?}", report.findings);
+	assert_eq!(report.restored, 2);
+	assert_eq!(
+		restored(&destination)
+			.into_iter()
+			.map(|(path, _)| path)
+			.collect::<Vec<_>>(),
+		[
+			"/storage/emulated/0/notes.txt",
+			"/storage/photos",
+			"/storage/photos/photo.jpg",
+		]
+	);
+}
+
+#[test]
+fn incremental() {
+	let base = output_dir("restore-incremental-base");
+	dump(&base, Format::Zip, false);
+	let mut changed = backend()
+		.file("/sdcard/notes.txt", b"new notes")
+		.file("/sdcard/new.txt", b"new");
+	changed.nodes.remove(&b"/sdcard/Music/song.mp3"[..]);
+	let increment = output_dir("restore-incremental-increment");
+	dump::run(
+		&changed,
+		&Rules::new(),
+		&dump::Options {
+			split_size: 8,
+			since: vec![base.clone()],
+			..dump_options(&increment)
+		},
+	)
+	.unwrap();
+
+	// Newer files replace older ones, and deleted ones stay deleted.
+	let destination = FakeBackend::new();
+	let report = restore::run(
+		&destination,
+		&restore::Options {
+			output_dirs: vec![base.clone(), increment],
+			..options(&base)
+		},
+	)
+	.unwrap();
+	assert!(report.is_ok(), "{:?}", report.findings);
+	assert_eq!(report.restored, 4);
+	let files: Vec<_> = restored(&destination)
+		.into_iter()
+		.filter_map(|(path, (node, _))| match node {
+			Node::File(data) => Some((path, String::from_utf8(data).unwrap())),
+			_ => None,
+		})
+		.collect();
+	assert_eq!(
+		files,
+		[
+			("/sdcard/DCIM/photo.jpg".to_string(), "photo".to_string()),
+			("/sdcard/new.txt".to_string(), "new".to_string()),
+			("/sdcard/notes.txt".to_string(), "new notes".to_string()),
+		]
+	);
+}
+
+#[test]
+fn owners_and_contexts() {
+	for format in [Format::Zip, Format::Tar] {
+		let dir = output_dir(&format!(
+			"restore-owners_and_contexts-{}",
+			format.extension()
+		));
+		dump(&dir, format, true);
+
+		let destination = FakeBackend::new();
+		let report = restore::run(&destination, &options(&dir)).unwrap();
+		assert!(report.is_ok(), "{:?}", report.findings);
+		assert!(destination.relabeled.lock().unwrap().is_empty());
+
+		let destination = FakeBackend::new();
+		let report = restore::run(
+			&destination,
+			&restore::Options {
+				security: true,
+				..options(&dir)
+			},
+		)
+		.unwrap();
+		assert!(report.is_ok(), "{:?}", report.findings);
+		let relabeled: Vec<(String, Relabeled)> = destination
+			.relabeled
+			.lock()
+			.unwrap()
+			.iter()
+			.map(|(path, relabeled)| (String::from_utf8(path.clone()).unwrap(), relabeled.clone()))
+			.collect();
+		let label = |uid, gid, context: &str| (Some((uid, gid)), Some(context.to_string()));
+		let owned = || (Some((1023, 1023)), None);
+		assert_eq!(
+			relabeled,
+			[
+				(
+					"/sdcard/DCIM".to_string(),
+					label(1023, 1023, "u:object_r:media_rw_data_file:s0")
+				),
+				(
+					"/sdcard/DCIM/photo.jpg".to_string(),
+					label(10123, 1023, "u:object_r:media_rw_data_file:s0:c512")
+				),
+				("/sdcard/Music".to_string(), owned()),
+				("/sdcard/Music/song.mp3".to_string(), owned()),
+				(
+					"/sdcard/link".to_string(),
+					label(0, 0, "u:object_r:rootfs:s0")
+				),
+				("/sdcard/notes.txt".to_string(), owned()),
+			],
+			"{:?}",
+			format
+		);
+	}
+}
+
+#[test]
+fn unknown_owners() {
+	for format in [Format::Zip, Format::Tar] {
+		let dir = output_dir(&format!("restore-unknown_owners-{}", format.extension()));
+		std::fs::create_dir_all(&dir).unwrap();
+		let mut writer = format
+			.create(&dir.join(format!("backup.1.{}", format.extension())))
+			.unwrap();
+		fn metadata(path: &str) -> Metadata<'_> {
+			Metadata::new(RawPath::new(path), 0o755, 1_600_000_000)
+		}
+		writer.add_directory(&metadata("sdcard")).unwrap();
+		writer
+			.start_file(&metadata("sdcard/notes.txt"), 5)
+			.unwrap()
+			.write_all(b"notes")
+			.unwrap();
+		writer
+			.add_symlink(&metadata("sdcard/link"), RawPath::new("notes.txt"))
+			.unwrap();
+		writer.finish().unwrap();
+
+		// Unknown owners aren't mistaken for root.
+		let destination = FakeBackend::new();
+		let report = restore::run(
+			&destination,
+			&restore::Options {
+				security: true,
+				..options(&dir)
+			},
+		)
+		.unwrap();
+		assert!(report.is_ok(), "{:?}", report.findings);
+		assert_eq!(restored(&destination).len(), 3, "{:?}", format);
+		let relabeled = destination.relabeled.lock().unwrap();
+		assert_eq!(relabeled.len(), 3, "{:?}", format);
+		assert!(
+			relabeled
+				.values()
+				.all(|relabeled| *relabeled == (None, None)),
+			"{:?}: {:?}",
+			format,
+			relabeled
+		);
+	}
+}
@@ -0,0 +1,147 @@
+#![cfg(not(miri))]
+
+use adb_dump::{
+	rules::{Preset, Rule, Rules},
+	Epoch, LsEntry, RawPath, UnixMode,
+};
+
+fn file(size: u64, mtime: i64) -> LsEntry {
+	LsEntry {
+		mode: UnixMode::new(0o100_644),
+		size,
+		mtime: Epoch::from_timestamp(mtime),
+		atime: None,
+		ctime: None,
+		uid: None,
+		gid: None,
+		context: None,
+		name: "name".into(),
+	}
+}
+
+fn dir() -> LsEntry {
+	LsEntry {
+		mode: UnixMode::new(0o040_755),
+		size: 4096,
+		mtime: Epoch::from_timestamp(0),
+		atime: None,
+		ctime: None,
+		uid: None,
+		gid: None,
+		context: None,
+		name: "name".into(),
+	}
+}
+
+fn rules(text: &str) -> Rules {
+	Rules::parse(text, "test").unwrap()
+}
+
+fn excluded(rules: &Rules, path: &str, entry: Option<&LsEntry>) -> bool {
+	rules.excluded_by(RawPath::new(path), entry).is_some()
+}
+
+#[test]
+fn suffix_patterns() {
+	let rules = rules("HTTP Cache\norg.mozilla.firefox/cache");
+	assert!(excluded(&rules, "/data/data/x/HTTP Cache", None));
+	assert!(!excluded(&rules, "/data/data/x/NoHTTP Cache", None));
+	assert!(excluded(
+		&rules,
+		"/data/data/org.mozilla.firefox/cache",
+		None
+	));
+	assert!(!excluded(
+		&rules,
+		"/data/data/org.mozilla.firefox/cache/x",
+		None
+	));
+}
+
+#[test]
+fn anchored_patterns() {
+	let rules = rules("/data/crdroid_updates");
+	assert!(excluded(&rules, "/data/crdroid_updates", None));
+	assert!(!excluded(&rules, "/data/data/crdroid_updates", None));
+}
+
+#[test]
+fn globs() {
+	let rules = rules("*.tmp\n/sdcard/**/thumbs\nlog[0-9]\n/a/**");
+	assert!(excluded(&rules, "/sdcard/x.tmp", Some(&file(1, 0))));
+	assert!(excluded(&rules, "/sdcard/thumbs", Some(&dir())));
+	assert!(excluded(&rules, "/sdcard/DCIM/.x/thumbs", Some(&dir())));
+	assert!(excluded(&rules, "/var/log7", None));
+	assert!(!excluded(&rules, "/var/logs", None));
+	assert!(excluded(&rules, "/a/b/c", None));
+	assert!(!excluded(&rules, "/ab", None));
+}
+
+#[test]
+fn dir_only() {
+	let rules = rules("cache/");
+	assert!(excluded(&rules, "/x/cache", Some(&dir())));
+	assert!(!excluded(&rules, "/x/cache", Some(&file(1, 0))));
+}
+
+#[test]
+fn includes_win_when_later() {
+	let rules = rules("*.log\n!keep.log");
+	assert!(excluded(&rules, "/x/a.log", None));
+	assert!(!excluded(&rules, "/x/keep.log", None));
+}
+
+#[test]
+fn packages() {
+	let rules = rules("@org.telegram.messenger cache\n@com.google.android.googlequicksearchbox");
+	assert!(excluded(
+		&rules,
+		"/data/data/org.telegram.messenger/cache",
+		None
+	));
+	assert!(excluded(
+		&rules,
+		"/sdcard/Android/data/org.telegram.messenger/files/cache",
+		None
+	));
+	assert!(!excluded(&rules, "/data/data/org.other/cache", None));
+	assert!(excluded(
+		&rules,
+		"/data/user/0/com.google.android.googlequicksearchbox",
+		None
+	));
+}
+
+#[test]
+fn size_and_mtime() {
+	let rules = rules("size>1K *.mp4\nmtime<2000-01-01");
+	assert!(excluded(&rules, "/x.mp4", Some(&file(1001, 1_000_000_000))));
+	assert!(!excluded(
+		&rules,
+		"/x.mp4",
+		Some(&file(1000, 1_000_000_000))
+	));
+	assert!(!excluded(&rules, "/x.mp4", None));
+	assert!(excluded(&rules, "/old", Some(&file(0, 900_000_000))));
+	assert!(!excluded(&rules, "/new", Some(&file(0, 1_000_000_000))));
+}
+
+#[test]
+fn escapes_and_errors() {
+	let rules = rules("\\size>1");
+	assert!(excluded(&rules, "/size>1", None));
+	assert!(Rule::parse("size>1X", "test").is_err());
+	assert!(Rule::parse("", "test").is_err());
+}
+
+#[test]
+fn caches_preset() {
+	let rules = Preset::Caches.rules();
+	assert!(excluded(
+		&rules,
+		"/data/data/com.discord/cache",
+		Some(&dir())
+	));
+	assert!(excluded(&rules, "/data/crdroid_updates", Some(&dir())));
+	assert!(!excluded(&rules, "/data/data/com.discord", Some(&dir())));
+}
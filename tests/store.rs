@@ -0,0 +1,156 @@
+#![cfg(not(miri))]
+
+use adb_dump::{
+	archive::{self, Format},
+	check, dump,
+	rules::Rules,
+	store::{self, MaterializeOptions},
+};
+use std::{collections::BTreeMap, fs, path::Path};
+
+#[path = "fake_backend_.rs"]
+mod fake_backend;
+use fake_backend::{archived_files, dump_options, output_dir, FakeBackend};
+
+fn backend() -> FakeBackend {
+	FakeBackend::new()
+		.dir("/sdcard")
+		.dir("/sdcard/DCIM")
+		.file("/sdcard/DCIM/photo.jpg", b"photo")
+		.dir("/sdcard/Pictures")
+		.file("/sdcard/Pictures/photo.jpg", b"photo")
+		.file("/sdcard/notes.txt", b"notes")
+		.symlink("/sdcard/link", "/sdcard/notes.txt")
+}
+
+fn dump(output_dir: &Path, format: Format, security: bool) {
+	fs::create_dir_all(output_dir).unwrap();
+	dump::run(
+		&backend().label(
+			"/sdcard/notes.txt",
+			10123,
+			1023,
+			"u:object_r:media_rw_data_file:s0",
+		),
+		&Rules::new(),
+		&dump::Options {
+			split_size: 8,
+			format,
+			security,
+			..dump_options(output_dir)
+		},
+	)
+	.unwrap();
+}
+
+fn blobs(store: &Path) -> usize {
+	fs::read_dir(store.join(store::BLOBS_DIR_NAME))
+		.unwrap()
+		.map(|dir| fs::read_dir(dir.unwrap().path()).unwrap().count())
+		.sum()
+}
+
+#[test]
+fn deduplicated() {
+	let store = output_dir("store-deduplicated");
+	dump(&store::snapshot_dir(&store, "first"), Format::Tree, false);
+	let first = blobs(&store);
+	// The photo, the notes and three checksum manifests.
+	assert_eq!(first, 5);
+
+	dump(&store::snapshot_dir(&store, "second"), Format::Tree, false);
+	assert_eq!(blobs(&store), first);
+	assert_eq!(store::snapshots(&store).unwrap(), ["first", "second"]);
+
+	let report = check::run(&check::Options {
+		output_dir: store::snapshot_dir(&store, "second"),
+		verbosity: -1,
+	})
+	.unwrap();
+	assert!(report.is_ok());
+	assert_eq!(report.checked, 3);
+}
+
+#[test]
+fn materialize() {
+	let store = output_dir("store-materialize");
+	dump(
+		&store::snapshot_dir(&store, "snapshot"),
+		Format::Tree,
+		false,
+	);
+	let direct = output_dir("store-materialize-direct");
+	dump(&direct, Format::Zip, false);
+
+	let target = output_dir("store-materialize-zip");
+	store::materialize(&MaterializeOptions {
+		store: store.clone(),
+		snapshot: "snapshot".to_string(),
+		target: target.clone(),
+		format: Some(Format::Zip),
+		verbosity: -1,
+	})
+	.unwrap();
+	assert_eq!(archived_files(&target), archived_files(&direct));
+
+	let target = output_dir("store-materialize-files");
+	store::materialize(&MaterializeOptions {
+		store,
+		snapshot: "snapshot".to_string(),
+		target: target.clone(),
+		format: None,
+		verbosity: -1,
+	})
+	.unwrap();
+	assert_eq!(
+		fs::read(target.join("sdcard/Pictures/photo.jpg")).unwrap(),
+		b"photo"
+	);
+	assert_eq!(fs::read(target.join("sdcard/notes.txt")).unwrap(), b"notes");
+	#[cfg(unix)]
+	assert_eq!(
+		fs::read_link(target.join("sdcard/link")).unwrap(),
+		Path::new("/sdcard/notes.txt")
+	);
+	assert!(!target.join(dump::MANIFEST_FILE_NAME).exists());
+}
+
+#[test]
+fn owners_and_contexts() {
+	let store = output_dir("store-owners_and_contexts");
+	dump(&store::snapshot_dir(&store, "snapshot"), Format::Tree, true);
+	let target = output_dir("store-owners_and_contexts-tar");
+	store::materialize(&MaterializeOptions {
+		store,
+		snapshot: "snapshot".to_string(),
+		target: target.clone(),
+		format: Some(Format::Tar),
+		verbosity: -1,
+	})
+	.unwrap();
+
+	let mut security = BTreeMap::new();
+	for volume in archive::volumes(&target).unwrap() {
+		archive::read_entries(&volume.path, |metadata, _, _| {
+			security.insert(
+				metadata.path.to_string_panicky(),
+				(
+					metadata.uid,
+					metadata.gid,
+					metadata.context.map(|context| context.to_string_panicky()),
+				),
+			);
+			Ok(())
+		})
+		.unwrap();
+	}
+	assert_eq!(
+		security["sdcard/notes.txt"],
+		(
+			Some(10123),
+			Some(1023),
+			Some("u:object_r:media_rw_data_file:s0".to_string())
+		)
+	);
+	assert_eq!(security["sdcard/link"], (Some(1023), Some(1023), None));
+}
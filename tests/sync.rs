@@ -0,0 +1,97 @@
+#![cfg(not(miri))]
+
+#[path = "fake_adb_.rs"]
+mod fake_adb;
+use adb_dump::error::AdbError;
+use fake_adb::FakeAdb;
+use std::io::{ErrorKind, Read};
+
+#[test]
+fn stat() {
+	let adb = FakeAdb::start("fake-serial");
+	adb.file("/data/a.txt", 2, b"contents");
+	let device = adb.device();
+	let mut sync = device.sync().unwrap();
+
+	let stat = sync.stat("/data/a.txt").unwrap();
+	assert!(stat.exists());
+	assert!(stat.mode.is_file());
+	assert_eq!(stat.size, 8);
+
+	assert!(!sync.stat("/data/missing").unwrap().exists());
+	sync.quit().unwrap();
+}
+
+#[test]
+fn send_then_recv() {
+	let adb = FakeAdb::start("fake-serial");
+	let device = adb.device();
+	let mut sync = device.sync().unwrap();
+
+	let data = b"more than one chunk";
+	assert_eq!(
+		sync.send("/sdcard/b.bin", 0o100_600, 12345, &data[..])
+			.unwrap(),
+		data.len() as u64
+	);
+
+	let mut received = Vec::new();
+	sync.recv("/sdcard/b.bin")
+		.unwrap()
+		.read_to_end(&mut received)
+		.unwrap();
+	assert_eq!(received, data);
+
+	let stat = sync.stat("/sdcard/b.bin").unwrap();
+	assert_eq!(stat.mode.permissions(), 0o600);
+	assert_eq!(stat.epoch.to_date_time().and_utc().timestamp(), 12345);
+}
+
+#[test]
+fn recv_missing() {
+	let adb = FakeAdb::start("fake-serial");
+	let device = adb.device();
+	let mut sync = device.sync().unwrap();
+
+	let mut received = Vec::new();
+	let error = sync
+		.recv("/data/missing")
+		.unwrap()
+		.read_to_end(&mut received)
+		.unwrap_err();
+	assert!(error.to_string().contains("No such file or directory"));
+
+	// The connection stays usable.
+	assert!(!sync.stat("/data/missing").unwrap().exists());
+
+	// Empty reads don't touch the connection.
+	adb.file("/data/a.txt", 2, b"contents");
+	let mut recv = sync.recv("/data/a.txt").unwrap();
+	assert_eq!(recv.read(&mut []).unwrap(), 0);
+	recv.read_to_end(&mut received).unwrap();
+	assert_eq!(received, b"contents");
+}
+
+#[test]
+fn list_missing() {
+	let adb = FakeAdb::start("fake-serial");
+	adb.file("/data/a.txt", 2, b"contents");
+	let device = adb.device();
+	let mut sync = device.sync().unwrap();
+
+	for v2 in [false, true] {
+		let error = if v2 {
+			sync.list2("/data/missing").unwrap_err()
+		} else {
+			sync.list("/data/missing").unwrap_err()
+		};
+		assert_eq!(error.kind(), ErrorKind::NotFound, "{}", error);
+		assert!(
+			matches!(AdbError::of(&error), Some(AdbError::NotFound { path }) if path.to_string_panicky() == "/data/missing"),
+			"{}",
+			error
+		);
+		// The whole message was read, so the connection stays usable.
+		assert!(sync.stat("/data/a.txt").unwrap().exists());
+	}
+}
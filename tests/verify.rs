@@ -0,0 +1,163 @@
+#![cfg(not(miri))]
+
+use adb_dump::{
+	archive::{self, Format},
+	dump,
+	rules::Rules,
+	verify::{self, Algorithm, Problem},
+};
+use std::{collections::BTreeSet, path::Path};
+
+#[path = "fake_backend_.rs"]
+mod fake_backend;
+use fake_backend::{dump_options, output_dir, FakeBackend, Node};
+
+fn backend() -> FakeBackend {
+	FakeBackend::new()
+		.dir("/sdcard")
+		.dir("/sdcard/a")
+		.file("/sdcard/a/1", b"one")
+		.file("/sdcard/a/2", b"two")
+		.dir("/sdcard/b")
+		.file("/sdcard/b/3", b"three")
+		.file("/sdcard/4", b"four")
+		.dir("/system")
+		.file("/system/not-dumped", b"")
+}
+
+fn dump(backend: &FakeBackend, dir: &Path, format: Format, rules: &Rules, split: bool) {
+	dump::run(
+		backend,
+		rules,
+		&dump::Options {
+			split_size: 4,
+			split,
+			format,
+			..dump_options(dir)
+		},
+	)
+	.unwrap();
+}
+
+fn verify(backend: &FakeBackend, dir: &Path) -> verify::Report {
+	verify::run(
+		backend,
+		&verify::Options {
+			output_dir: dir.to_owned(),
+			root: "/".into(),
+			verbosity: -1,
+		},
+	)
+	.unwrap()
+}
+
+fn findings(report: &verify::Report) -> Vec<String> {
+	report.findings.iter().map(ToString::to_string).collect()
+}
+
+#[test]
+fn unchanged() {
+	let backend = backend();
+	for (format, tools) in [
+		(Format::Zip, &[][..]),
+		(Format::Tar, &[Algorithm::Md5][..]),
+		(Format::Zip, &[Algorithm::Sha256, Algorithm::Md5][..]),
+	] {
+		let dir = output_dir(&format!(
+			"verify-unchanged-{}-{}",
+			format.extension(),
+			tools.len()
+		));
+		dump(&backend, &dir, format, &Rules::new(), false);
+		*backend.tools.lock().unwrap() = tools.to_vec();
+		let report = verify(&backend, &dir);
+		assert_eq!(findings(&report), Vec::<String>::new());
+		assert_eq!(report.checked, 4);
+	}
+}
+
+#[test]
+fn changed() {
+	let mut backend = backend();
+	let dir = output_dir("verify-changed");
+	dump(&backend, &dir, Format::Zip, &Rules::new(), false);
+
+	backend
+		.nodes
+		.insert(b"/sdcard/a/1".to_vec(), Node::File(b"uno".to_vec()));
+	backend
+		.nodes
+		.insert(b"/sdcard/a/2".to_vec(), Node::File(b"dos!".to_vec()));
+	backend.nodes.remove(b"/sdcard/b/3".as_slice());
+	backend
+		.nodes
+		.insert(b"/sdcard/5".to_vec(), Node::File(b"five".to_vec()));
+	backend.nodes.insert(b"/sdcard/c".to_vec(), Node::Dir);
+
+	for tools in [&[][..], &[Algorithm::Sha256][..]] {
+		*backend.tools.lock().unwrap() = tools.to_vec();
+		let report = verify(&backend, &dir);
+		assert_eq!(
+			findings(&report),
+			[
+				"missing\t\"/sdcard/5\"",
+				"missing\t\"/sdcard/c\"",
+				"content\t\"/sdcard/a/1\"",
+				"size\t\"/sdcard/a/2\"\t3\t4",
+				"extra\t\"/sdcard/b/3\"",
+			]
+		);
+		assert!(matches!(report.findings[2].problem, Problem::Content));
+	}
+}
+
+#[test]
+fn excluded() {
+	let backend = backend()
+		.file("/sdcard/a/x.tmp", b"tmp")
+		.dir("/sdcard/cache")
+		.file("/sdcard/cache/file", b"cached");
+	let dir = output_dir("verify-excluded");
+	dump(
+		&backend,
+		&dir,
+		Format::Zip,
+		&Rules::parse("*.tmp\ncache/", "test").unwrap(),
+		false,
+	);
+	assert!(verify(&backend, &dir).is_ok());
+}
+
+#[test]
+fn dotted_set_names() {
+	let backend = backend()
+		.dir("/sdcard/com.foo")
+		.file("/sdcard/com.foo/5", b"five")
+		.dir("/sdcard/.hidden")
+		.file("/sdcard/.hidden/6", b"six");
+	for format in [Format::Zip, Format::Tar] {
+		let dir = output_dir(&format!("verify-dotted_set_names-{}", format.extension()));
+		dump(&backend, &dir, format, &Rules::new(), true);
+		let sets: BTreeSet<_> = archive::volumes(&dir)
+			.unwrap()
+			.into_iter()
+			.map(|volume| volume.set)
+			.collect();
+		assert_eq!(
+			sets.into_iter().collect::<Vec<_>>(),
+			[".hidden", "a", "adb-dump_root", "b", "com.foo"]
+		);
+		// Split volumes hold paths relative to the dumped path.
+		let report = verify::run(
+			&backend,
+			&verify::Options {
+				output_dir: dir.clone(),
+				root: "/sdcard".into(),
+				verbosity: -1,
+			},
+		)
+		.unwrap();
+		assert_eq!(findings(&report), Vec::<String>::new());
+		assert_eq!(report.checked, 6);
+	}
+}